@@ -0,0 +1,827 @@
+use crate::utils::core::block_source::{BlockSource, FileSource};
+use crate::utils::core::bundle::{export_bundle, BundleSource};
+use crate::utils::core::columnar::{ColumnarExporter, ExportFormat};
+use crate::utils::core::evm_exec::StateReconstructor;
+use crate::utils::core::genesis_load::save_genesis_to_file;
+use crate::utils::core::logging::{init_logging, LogFormat};
+use crate::utils::core::metrics::{spawn_metrics_server, ReplayMetrics};
+use crate::utils::core::networks::{Networks, NETWORK_NAMES};
+use crate::utils::core::progress::{NoProgress, ProgressObserver};
+use crate::utils::core::reconstruct::{
+    follow_source, import_snapshot, replay_blocks_with_progress, replay_receipts_with_traces,
+};
+use crate::utils::core::reconstructor::{ReconstructionMode, Reconstructor};
+use crate::utils::core::rpc_server::serve;
+use crate::utils::core::selection::Selection;
+use crate::utils::core::snapshot::{write_snapshot, SnapshotReader, StateSnapshot};
+use crate::utils::core::state as receipts;
+use crate::utils::core::state_iter::{KeyRange, StateStore};
+use crate::utils::core::tokens::check_token_balances;
+use crate::utils::core::verify::verify_archiver;
+use crate::utils::core::wvm_archiver::load_network_archiver_info;
+use anyhow::{anyhow, Error};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ethers::types::{Address, Bytes, H256};
+use serde::Serialize;
+use serde_json::json;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tracing::{info_span, Instrument};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "evm-state-reconstructing",
+    version,
+    about = "Reconstruct the state of EVM networks archived on WeaveVM"
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    /// Print JSON instead of human-readable output
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// Log verbosity as a level or filter (e.g. `debug`, `warn,evm_state_reconstructing=trace`);
+    /// `RUST_LOG` takes precedence
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: String,
+
+    /// Format of the log events written to stderr
+    #[arg(long, global = true, value_enum, default_value_t = LogFormatKind::Text)]
+    pub log_format: LogFormatKind,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Args)]
+pub struct NetworkArgs {
+    /// Network from the built-in registry, or `custom` together with --rpc-url and --genesis
+    #[arg(long, global = true, default_value = "metis")]
+    pub network: String,
+
+    /// Overrides the network's wvm-archiver URL
+    #[arg(long, global = true)]
+    pub archiver_url: Option<String>,
+
+    /// Network JSON-RPC URL, for `--network custom`
+    #[arg(long, global = true)]
+    pub rpc_url: Option<String>,
+
+    /// Genesis file, for `--network custom`
+    #[arg(long, global = true)]
+    pub genesis: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormatKind {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl From<LogFormatKind> for LogFormat {
+    fn from(kind: LogFormatKind) -> Self {
+        match kind {
+            LogFormatKind::Text => LogFormat::Text,
+            LogFormatKind::Json => LogFormat::Json,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ExportFormatKind {
+    Csv,
+    Parquet,
+}
+
+impl From<ExportFormatKind> for ExportFormat {
+    fn from(kind: ExportFormatKind) -> Self {
+        match kind {
+            ExportFormatKind::Csv => ExportFormat::Csv,
+            ExportFormatKind::Parquet => ExportFormat::Parquet,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SourceKind {
+    /// wvm-archiver HTTP API
+    Archiver,
+    /// Archive transactions read directly from WeaveVM
+    Calldata,
+    /// The network's own JSON-RPC node
+    Rpc,
+    /// A bundle file written by `export`
+    Bundle,
+    /// A directory of `<block>.json` files
+    Files,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ModeKind {
+    /// Re-execute every transaction in the EVM
+    Execution,
+    /// Approximate balances and nonces from receipts, without execution
+    Receipts,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    /// First block to replay, defaults to the first block the source has
+    #[arg(long)]
+    pub from: Option<u64>,
+
+    /// Last block to replay, defaults to the last block the source has
+    #[arg(long)]
+    pub to: Option<u64>,
+
+    /// Where blocks are read from
+    #[arg(long, value_enum, default_value_t = SourceKind::Archiver)]
+    pub source: SourceKind,
+
+    /// How blocks are turned into state; only `reconstruct`, `account`, `storage` and
+    /// `accounts` support `receipts`
+    #[arg(long, value_enum, default_value_t = ModeKind::Execution)]
+    pub mode: ModeKind,
+
+    /// With `--mode receipts`, fetch `trace_block` call traces from the network RPC to
+    /// account for value moved inside contracts
+    #[arg(long)]
+    pub traces: bool,
+
+    /// Bundle file or block directory, for `--source bundle` and `--source files`
+    #[arg(long)]
+    pub path: Option<PathBuf>,
+
+    /// Keep per-block history so that past blocks can be queried
+    #[arg(long)]
+    pub history: bool,
+
+    /// Number of past blocks to keep history for, implies --history
+    #[arg(long)]
+    pub history_retention: Option<u64>,
+
+    /// Start from a snapshot written by `snapshot`, or from a genesis / alloc JSON file,
+    /// instead of from genesis; replay then continues after the snapshot's block
+    #[arg(long)]
+    pub snapshot: Option<PathBuf>,
+
+    /// Block a genesis / alloc JSON --snapshot was taken at
+    #[arg(long, requires = "snapshot")]
+    pub snapshot_block: Option<u64>,
+
+    /// Only keep the state of these addresses and the contracts they depend on; every
+    /// transaction is still executed
+    #[arg(long, value_delimiter = ',')]
+    pub only: Vec<Address>,
+
+    /// With --only, skip blocks whose transactions and logs touch neither the selected
+    /// addresses nor any account already in the state; replay fails if a later block
+    /// reads an account left stale
+    #[arg(long, requires = "only")]
+    pub skip_untouched_blocks: bool,
+
+    /// Serve replay progress as Prometheus metrics on `http://<addr>/metrics`
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Replay a block range and print a summary of the resulting state
+    Reconstruct {
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Show the archiver's status
+    Info,
+    /// Replay a block range and print an account
+    Account {
+        address: Address,
+        /// Block to read the account at, defaults to the replayed head (needs --history)
+        #[arg(long)]
+        block: Option<u64>,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay a block range and print a storage slot
+    Storage {
+        address: Address,
+        slot: H256,
+        /// Block to read the slot at, defaults to the replayed head (needs --history)
+        #[arg(long)]
+        block: Option<u64>,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// List accounts by ascending address, one page at a time
+    Accounts {
+        /// Only addresses starting with these bytes
+        #[arg(long)]
+        prefix: Option<Bytes>,
+        /// Start after this address, e.g. the `next` cursor of the previous page
+        #[arg(long)]
+        after: Option<Address>,
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+        /// Read a binary snapshot in place instead of replaying
+        #[arg(long, conflicts_with = "snapshot")]
+        from_snapshot: Option<PathBuf>,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay a block range and print the holders of an ERC-20, ERC-721 or ERC-1155 token,
+    /// as indexed from transfer logs
+    Holders {
+        token: Address,
+        /// Block to list the holders at, defaults to the replayed head
+        #[arg(long)]
+        block: Option<u64>,
+        /// Cross-check the indexed balances against `balanceOf` on the reconstructed state;
+        /// a past --block needs --history
+        #[arg(long)]
+        check: bool,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay a block range and print an EIP-1186 proof of an account and storage slots
+    Proof {
+        address: Address,
+        slots: Vec<H256>,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay a block range and write the state as a genesis file for booting a new chain
+    Genesis {
+        /// Genesis file to write
+        #[arg(long)]
+        out: PathBuf,
+        /// Block to dump the state at, defaults to the replayed head (needs --history)
+        #[arg(long)]
+        block: Option<u64>,
+        /// Write only the `alloc` object instead of a full genesis file
+        #[arg(long)]
+        alloc_only: bool,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay a block range and write a binary snapshot of the resulting state
+    Snapshot {
+        /// Snapshot file to write
+        #[arg(long)]
+        out: PathBuf,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Export a block range into an offline bundle
+    Export {
+        /// Bundle file to write
+        #[arg(long)]
+        out: PathBuf,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay a block range and write its blocks, transactions, receipts, logs and state
+    /// diffs as Parquet or CSV tables
+    Tables {
+        /// Directory to write one subdirectory per table into
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, value_enum, default_value_t = ExportFormatKind::Parquet)]
+        format: ExportFormatKind,
+        /// Blocks per file
+        #[arg(long, default_value_t = 10_000)]
+        partition_size: u64,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Compare the archiver against the network RPC over a block range
+    Verify {
+        #[arg(long)]
+        from: u64,
+        #[arg(long)]
+        to: u64,
+    },
+    /// Replay, then keep following the source head
+    Follow {
+        /// Seconds between polls of the source head
+        #[arg(long, default_value_t = 12)]
+        poll_interval: u64,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+    /// Replay, then serve the resulting state over JSON-RPC
+    Serve {
+        /// Address the JSON-RPC server listens on
+        #[arg(long, default_value = "127.0.0.1:8545")]
+        addr: SocketAddr,
+        #[command(flatten)]
+        replay: ReplayArgs,
+    },
+}
+
+impl NetworkArgs {
+    pub fn resolve(&self) -> Result<Networks, Error> {
+        let mut network = if self.network == "custom" {
+            let rpc_url = self
+                .rpc_url
+                .as_deref()
+                .ok_or_else(|| anyhow!("--network custom requires --rpc-url"))?;
+            let genesis = self
+                .genesis
+                .as_deref()
+                .ok_or_else(|| anyhow!("--network custom requires --genesis"))?;
+            Networks::custom(rpc_url, self.archiver_url.clone(), genesis)?
+        } else {
+            Networks::by_name(&self.network)
+                .transpose()?
+                .ok_or_else(|| {
+                    anyhow!(
+                        "unknown network `{}`, expected one of {:?} or `custom`",
+                        self.network,
+                        NETWORK_NAMES
+                    )
+                })?
+        };
+
+        if let Some(url) = &self.archiver_url {
+            network.wvm_archiver_url = Some(url.clone());
+        }
+        Ok(network)
+    }
+}
+
+impl ReplayArgs {
+    pub fn block_source(&self, network: &Networks) -> Result<Box<dyn BlockSource>, Error> {
+        let path = || {
+            self.path
+                .clone()
+                .ok_or_else(|| anyhow!("--source {:?} requires --path", self.source))
+        };
+
+        Ok(match self.source {
+            SourceKind::Archiver => Box::new(network.archiver_source()),
+            SourceKind::Calldata => Box::new(network.calldata_source()),
+            SourceKind::Rpc => Box::new(network.rpc_source()),
+            SourceKind::Bundle => Box::new(BundleSource::open(path()?)?),
+            SourceKind::Files => Box::new(FileSource::new(path()?)),
+        })
+    }
+
+    pub async fn range(&self, source: &dyn BlockSource) -> Result<(u64, u64), Error> {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) => Ok((from, to)),
+            (from, to) => {
+                let (first, last) = source.block_range().await?;
+                Ok((from.unwrap_or(first), to.unwrap_or(last)))
+            }
+        }
+    }
+
+    /// Starts the metrics endpoint when `--metrics-addr` is set.
+    async fn observer(&self) -> Result<Arc<dyn ProgressObserver>, Error> {
+        let Some(addr) = self.metrics_addr else {
+            return Ok(Arc::new(NoProgress));
+        };
+        let metrics = Arc::new(ReplayMetrics::new()?);
+        spawn_metrics_server(metrics.clone(), addr).await?;
+        Ok(metrics)
+    }
+
+    pub async fn replay(&self, network: &Networks) -> Result<StateReconstructor, Error> {
+        self.replay_with(network, |state| state).await
+    }
+
+    /// Like `replay`, letting `configure` add hooks or indexes before the first block.
+    pub async fn replay_with(
+        &self,
+        network: &Networks,
+        configure: impl FnOnce(StateReconstructor) -> StateReconstructor,
+    ) -> Result<StateReconstructor, Error> {
+        let source = self.block_source(network)?;
+        self.replay_from(network, source.as_ref(), configure).await
+    }
+
+    /// Replays in the selected `--mode`.
+    pub async fn replay_any(&self, network: &Networks) -> Result<Box<dyn Reconstructor>, Error> {
+        if self.mode == ModeKind::Execution {
+            return Ok(Box::new(self.replay(network).await?));
+        }
+        if self.snapshot.is_some() || self.history || self.history_retention.is_some() {
+            return Err(anyhow!("--snapshot and --history need --mode execution"));
+        }
+        if !self.only.is_empty() {
+            return Err(anyhow!("--only needs --mode execution"));
+        }
+
+        let source = self.block_source(network)?;
+        let (from, to) = self.range(source.as_ref()).await?;
+        let observer = self.observer().await?;
+        if self.traces {
+            let mut state = receipts::StateReconstructor::from_genesis(&network.genesis_file);
+            replay_receipts_with_traces(
+                &mut state,
+                source.as_ref(),
+                &network.rpc_provider,
+                from,
+                to,
+                observer.as_ref(),
+            )
+            .await?;
+            return Ok(Box::new(state));
+        }
+        let mut state = ReconstructionMode::ReceiptDriven.from_genesis(&network.genesis_file);
+        replay_blocks_with_progress(state.as_mut(), source.as_ref(), from, to, observer.as_ref())
+            .await?;
+        Ok(state)
+    }
+
+    pub async fn replay_from(
+        &self,
+        network: &Networks,
+        source: &dyn BlockSource,
+        configure: impl FnOnce(StateReconstructor) -> StateReconstructor,
+    ) -> Result<StateReconstructor, Error> {
+        if self.mode != ModeKind::Execution {
+            return Err(anyhow!("this command needs --mode execution"));
+        }
+        if self.traces {
+            return Err(anyhow!("--traces only applies to --mode receipts"));
+        }
+        let (mut from, to) = self.range(source).await?;
+        let mut state = match &self.snapshot {
+            Some(path) => {
+                let snapshot = StateSnapshot::load(path, self.snapshot_block)?;
+                from = from.max(snapshot.block_number + 1);
+                import_snapshot(&network.genesis_file, &snapshot, source).await?
+            }
+            None => StateReconstructor::from_genesis(&network.genesis_file),
+        };
+        if self.history || self.history_retention.is_some() {
+            state = state.with_history(self.history_retention);
+        }
+        if !self.only.is_empty() {
+            let mut selection = Selection::new(self.only.iter().copied());
+            selection.skip_untouched_blocks = self.skip_untouched_blocks;
+            state = state.with_selection(selection);
+        }
+        let mut state = configure(state);
+        let observer = self.observer().await?;
+        replay_blocks_with_progress(&mut state, source, from, to, observer.as_ref()).await?;
+        Ok(state)
+    }
+}
+
+fn output<T: Serialize>(
+    json: bool,
+    value: &T,
+    text: impl FnOnce(&T) -> String,
+) -> Result<(), Error> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        println!("{}", text(value));
+    }
+    Ok(())
+}
+
+fn state_summary(state: &dyn Reconstructor) -> serde_json::Value {
+    let (block_number, head_hash) = state.head();
+    json!({
+        "mode": state.mode(),
+        "block_number": block_number,
+        "head_hash": head_hash,
+        "accounts": state.accounts(KeyRange::all()).count(),
+    })
+}
+
+pub async fn run(cli: Cli) -> Result<(), Error> {
+    init_logging(&cli.log_level, cli.log_format.into())?;
+    let network = cli.network.resolve()?;
+    let span = info_span!("run", network = %network.name);
+    run_command(cli.command, cli.json, network)
+        .instrument(span)
+        .await
+}
+
+async fn run_command(command: Command, json: bool, network: Networks) -> Result<(), Error> {
+    match command {
+        Command::Reconstruct { replay } => {
+            let state = replay.replay_any(&network).await?;
+            output(json, &state_summary(state.as_ref()), |summary| {
+                format!(
+                    "[*] Reconstructed state at block #{} ({} accounts, head {})",
+                    summary["block_number"], summary["accounts"], summary["head_hash"]
+                )
+            })
+        }
+        Command::Info => {
+            let info = load_network_archiver_info(network).await?;
+            output(json, &info, |info| format!("{:#?}", info))
+        }
+        Command::Account {
+            address,
+            block,
+            replay,
+        } => {
+            let (account, unknown) = match block {
+                Some(block) => (
+                    replay
+                        .replay(&network)
+                        .await?
+                        .account_state_at(address, block)?,
+                    Default::default(),
+                ),
+                None => {
+                    let state = replay.replay_any(&network).await?;
+                    (
+                        state.account(address).cloned(),
+                        state.unknown_fields(address),
+                    )
+                }
+            };
+            output(json, &account, |account| match account {
+                Some(account) if !unknown.is_empty() => format!(
+                    "{:#?}\n[!] Not derivable without execution: {}",
+                    account,
+                    unknown.names().join(", ")
+                ),
+                Some(account) => format!("{:#?}", account),
+                None => format!("[!] Account {:?} not found", address),
+            })
+        }
+        Command::Storage {
+            address,
+            slot,
+            block,
+            replay,
+        } => {
+            let value = match block {
+                Some(block) => replay
+                    .replay(&network)
+                    .await?
+                    .storage_at(address, slot, block)?,
+                None => replay
+                    .replay_any(&network)
+                    .await?
+                    .storage_slot(address, slot),
+            };
+            output(json, &value, |value| format!("{:?}", value))
+        }
+        Command::Accounts {
+            prefix,
+            after,
+            limit,
+            from_snapshot,
+            replay,
+        } => {
+            let store: Box<dyn StateStore> = match from_snapshot {
+                Some(path) => Box::new(SnapshotReader::open(path)?),
+                None => replay.replay_any(&network).await?,
+            };
+            let mut range = KeyRange::with_prefix(prefix.unwrap_or_default().to_vec());
+            if let Some(after) = after {
+                range = range.after(after);
+            }
+            let page = store.accounts_page(range, limit)?;
+            output(json, &page, |page| {
+                let mut lines: Vec<String> = page
+                    .items
+                    .iter()
+                    .map(|account| {
+                        format!(
+                            "{:?} nonce {} balance {} code {} bytes",
+                            account.address,
+                            account.nonce,
+                            account.balance,
+                            account.code.len()
+                        )
+                    })
+                    .collect();
+                if let Some(next) = page.next {
+                    lines.push(format!(
+                        "[*] More accounts follow, continue with --after {:?}",
+                        next
+                    ));
+                }
+                lines.join("\n")
+            })
+        }
+        Command::Holders {
+            token,
+            block,
+            check,
+            replay,
+        } => {
+            let state = replay
+                .replay_with(&network, StateReconstructor::with_token_index)
+                .await?;
+            let block = block.unwrap_or(state.block_number);
+            let tokens = state.tokens.as_ref().expect("replayed with a token index");
+            let holders = tokens.holders_at(token, block);
+            let mismatches = if check {
+                Some(check_token_balances(&state, token, block)?)
+            } else {
+                None
+            };
+            let snapshot = json!({
+                "token": token,
+                "standard": tokens.standard(token),
+                "block": block,
+                "holders": holders,
+                "mismatches": mismatches,
+            });
+            output(json, &snapshot, |_| {
+                let mut lines: Vec<String> = holders
+                    .iter()
+                    .map(|holder| match holder.id {
+                        Some(id) => format!("{:?}  #{}  {}", holder.holder, id, holder.balance),
+                        None => format!("{:?}  {}", holder.holder, holder.balance),
+                    })
+                    .collect();
+                lines.push(format!(
+                    "[*] {} holders of {:?} as of block #{}",
+                    holders.len(),
+                    token,
+                    block
+                ));
+                match &mismatches {
+                    Some(mismatches) if mismatches.is_empty() => {
+                        lines.push("[*] Every balance matches balanceOf".to_string())
+                    }
+                    Some(mismatches) => {
+                        for mismatch in mismatches {
+                            lines.push(format!(
+                                "[!] {:?}: indexed {}, balanceOf {}",
+                                mismatch.holder,
+                                mismatch.indexed,
+                                mismatch
+                                    .on_chain
+                                    .map(|balance| balance.to_string())
+                                    .unwrap_or_else(|| "failed".to_string())
+                            ));
+                        }
+                    }
+                    None => {}
+                }
+                lines.join("\n")
+            })
+        }
+        Command::Proof {
+            address,
+            slots,
+            replay,
+        } => {
+            let state = replay.replay(&network).await?;
+            let proof = json!({
+                "stateRoot": state.state_root(),
+                "proof": state.get_proof(address, &slots),
+            });
+            output(json, &proof, |proof| {
+                serde_json::to_string_pretty(proof).unwrap_or_default()
+            })
+        }
+        Command::Genesis {
+            out,
+            block,
+            alloc_only,
+            replay,
+        } => {
+            let state = replay.replay(&network).await?;
+            let block = block.unwrap_or(state.block_number);
+            let genesis = state.genesis_at(&network.genesis_file, block)?;
+            if alloc_only {
+                serde_json::to_writer_pretty(File::create(&out)?, &genesis.alloc)?;
+            } else {
+                save_genesis_to_file(&out, &genesis)?;
+            }
+            let summary = json!({
+                "path": out,
+                "block": block,
+                "accounts": genesis.alloc.len(),
+            });
+            output(json, &summary, |_| {
+                format!(
+                    "[*] Wrote {} accounts as of block #{} into {}",
+                    genesis.alloc.len(),
+                    block,
+                    out.display()
+                )
+            })
+        }
+        Command::Snapshot { out, replay } => {
+            let state = replay.replay(&network).await?;
+            let accounts = write_snapshot(
+                &state,
+                state.block_number,
+                state.head_hash,
+                state.snapshot_root(),
+                &out,
+            )?;
+            let summary = json!({
+                "path": out,
+                "block": state.block_number,
+                "accounts": accounts,
+                "stateRoot": state.snapshot_root(),
+            });
+            output(json, &summary, |_| {
+                format!(
+                    "[*] Wrote a snapshot of {} accounts at block #{} into {}",
+                    accounts,
+                    state.block_number,
+                    out.display()
+                )
+            })
+        }
+        Command::Export { out, replay } => {
+            let source = replay.block_source(&network)?;
+            let (from, to) = replay.range(source.as_ref()).await?;
+            let index = export_bundle(source.as_ref(), from, to, &out).await?;
+            let summary = json!({
+                "path": out,
+                "from": from,
+                "to": to,
+                "blocks": index.len(),
+            });
+            output(json, &summary, |_| {
+                format!(
+                    "[*] Exported {} blocks (#{} to #{}) into {}",
+                    index.len(),
+                    from,
+                    to,
+                    out.display()
+                )
+            })
+        }
+        Command::Tables {
+            out,
+            format,
+            partition_size,
+            replay,
+        } => {
+            let exporter = ColumnarExporter::new(&out, format.into(), partition_size);
+            let state = replay
+                .replay_with(&network, |state| state.with_hooks(exporter.clone()))
+                .await?;
+            let files = exporter.finish()?;
+            let summary = json!({
+                "path": out,
+                "block": state.block_number,
+                "files": files,
+            });
+            output(json, &summary, |_| {
+                format!(
+                    "[*] Wrote {} files up to block #{} into {}",
+                    files.len(),
+                    state.block_number,
+                    out.display()
+                )
+            })
+        }
+        Command::Verify { from, to } => {
+            let report = verify_archiver(&network, from, to).await?;
+            output(json, &report, |report| {
+                format!(
+                    "[*] Checked {} blocks: {} matching, {} mismatched\n{:#?}",
+                    report.blocks_checked,
+                    report.blocks_matching,
+                    report.mismatches.len(),
+                    report.mismatches
+                )
+            })?;
+            if report.is_consistent() {
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "archiver and RPC disagree on {} blocks",
+                    report.mismatches.len()
+                ))
+            }
+        }
+        Command::Follow {
+            poll_interval,
+            replay,
+        } => {
+            let source = replay.block_source(&network)?;
+            let mut state = replay
+                .replay_from(&network, source.as_ref(), |state| state)
+                .await?;
+            follow_source(
+                source.as_ref(),
+                &mut state,
+                Duration::from_secs(poll_interval),
+            )
+            .await
+        }
+        Command::Serve { addr, replay } => {
+            let state = replay.replay(&network).await?;
+            serve(Arc::new(RwLock::new(state)), addr).await
+        }
+    }
+}
@@ -1,9 +1,10 @@
 use anyhow::Error;
+use clap::Parser;
 
+pub mod cli;
 pub mod utils;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    println!("UwU DECENTRALIZE THE DATA NOW UwU");
-    Ok(())
+    cli::run(cli::Cli::parse()).await
 }
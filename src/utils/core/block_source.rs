@@ -0,0 +1,115 @@
+use crate::utils::core::rpc::get_block_txs_receipts;
+use crate::utils::core::wvm_archiver::{
+    get_block_from_wvm, load_archiver_info, WvmArchiverDataBlock,
+};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use ethers::middleware::Middleware;
+use ethers::providers::{Http, Provider};
+use ethers::types::{Block, Transaction, TransactionReceipt};
+use std::path::PathBuf;
+
+pub type BlockWithReceipts = (Block<Transaction>, Vec<TransactionReceipt>);
+
+/// Anything that can serve blocks together with their receipts for reconstruction.
+#[async_trait]
+pub trait BlockSource: Send + Sync {
+    /// Inclusive range of block numbers this source can currently serve.
+    async fn block_range(&self) -> Result<(u64, u64), Error>;
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error>;
+}
+
+/// Blocks archived on WeaveVM, served by a wvm-archiver instance.
+#[derive(Debug, Clone)]
+pub struct WvmArchiverSource {
+    pub wvm_archiver_url: Option<String>,
+}
+
+impl WvmArchiverSource {
+    pub fn new(wvm_archiver_url: Option<String>) -> Self {
+        Self { wvm_archiver_url }
+    }
+}
+
+#[async_trait]
+impl BlockSource for WvmArchiverSource {
+    async fn block_range(&self) -> Result<(u64, u64), Error> {
+        let info = load_archiver_info(self.wvm_archiver_url.clone()).await?;
+        Ok(info.archived_range())
+    }
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error> {
+        get_block_from_wvm(self.wvm_archiver_url.clone(), block_nr).await
+    }
+}
+
+/// Blocks fetched straight from the network's JSON-RPC node.
+#[derive(Debug, Clone)]
+pub struct RpcSource {
+    pub provider: Provider<Http>,
+}
+
+impl RpcSource {
+    pub fn new(provider: Provider<Http>) -> Self {
+        Self { provider }
+    }
+}
+
+#[async_trait]
+impl BlockSource for RpcSource {
+    async fn block_range(&self) -> Result<(u64, u64), Error> {
+        let head = self.provider.get_block_number().await?;
+        Ok((0, head.as_u64()))
+    }
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error> {
+        get_block_txs_receipts(self.provider.clone(), block_nr).await
+    }
+}
+
+/// Blocks stored as `<dir>/<block_nr>.json` files, in the same shape as the
+/// archiver's `/v1/block/raw` response.
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    pub dir: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl BlockSource for FileSource {
+    async fn block_range(&self) -> Result<(u64, u64), Error> {
+        let mut numbers = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(nr) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            {
+                numbers.push(nr);
+            }
+        }
+
+        match (numbers.iter().min(), numbers.iter().max()) {
+            (Some(first), Some(last)) => Ok((*first, *last)),
+            _ => Err(anyhow!("no block files found in {}", self.dir.display())),
+        }
+    }
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error> {
+        let path = self.dir.join(format!("{}.json", block_nr));
+        let contents = tokio::fs::read(&path).await?;
+        let data: WvmArchiverDataBlock = serde_json::from_slice(&contents)?;
+        data.into_block_receipts()
+    }
+}
@@ -0,0 +1,188 @@
+use crate::utils::core::block_source::{BlockSource, BlockWithReceipts};
+use crate::utils::core::wvm_archiver::WvmArchiverDataBlock;
+use crate::utils::core::wvm_calldata::{decode_archived_calldata, encode_archived_calldata};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use borsh_derive::{BorshDeserialize, BorshSerialize};
+use ethers::utils::keccak256;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Bundle layout:
+///
+/// ```text
+/// MAGIC
+/// frame*   := len: u32 LE | payload: brotli(borsh(WvmArchiverDataBlock)) | keccak256(payload)
+/// index    := borsh(Vec<BundleIndexEntry>)
+/// footer   := index offset: u64 LE | index len: u32 LE | MAGIC
+/// ```
+pub const BUNDLE_MAGIC: &[u8; 8] = b"WVMBNDL1";
+const FOOTER_LEN: u64 = 8 + 4 + BUNDLE_MAGIC.len() as u64;
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct BundleIndexEntry {
+    pub block_number: u64,
+    /// Offset of the frame payload, right after its length prefix.
+    pub offset: u64,
+    pub len: u32,
+    pub checksum: [u8; 32],
+}
+
+pub struct BundleWriter {
+    writer: BufWriter<File>,
+    offset: u64,
+    index: Vec<BundleIndexEntry>,
+}
+
+impl BundleWriter {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(BUNDLE_MAGIC)?;
+        Ok(Self {
+            writer,
+            offset: BUNDLE_MAGIC.len() as u64,
+            index: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, data: &WvmArchiverDataBlock) -> Result<&BundleIndexEntry, Error> {
+        let block_number = data
+            .block
+            .as_ref()
+            .and_then(|block| block.number)
+            .ok_or_else(|| anyhow!("cannot bundle a block without a number"))?;
+        let payload = encode_archived_calldata(data)?;
+        let checksum = keccak256(&payload);
+
+        self.writer
+            .write_all(&(payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&payload)?;
+        self.writer.write_all(&checksum)?;
+
+        self.index.push(BundleIndexEntry {
+            block_number,
+            offset: self.offset + 4,
+            len: payload.len() as u32,
+            checksum,
+        });
+        self.offset += 4 + payload.len() as u64 + 32;
+        Ok(self.index.last().unwrap())
+    }
+
+    /// Writes the index and footer, returning the index.
+    pub fn finish(mut self) -> Result<Vec<BundleIndexEntry>, Error> {
+        let index = borsh::to_vec(&self.index)?;
+        self.writer.write_all(&index)?;
+        self.writer.write_all(&self.offset.to_le_bytes())?;
+        self.writer.write_all(&(index.len() as u32).to_le_bytes())?;
+        self.writer.write_all(BUNDLE_MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.index)
+    }
+}
+
+/// Exports blocks `from..=to` of `source` into a bundle file at `path`.
+pub async fn export_bundle<S: BlockSource + ?Sized>(
+    source: &S,
+    from: u64,
+    to: u64,
+    path: impl AsRef<Path>,
+) -> Result<Vec<BundleIndexEntry>, Error> {
+    let mut writer = BundleWriter::create(path)?;
+    for block_nr in from..=to {
+        let data: WvmArchiverDataBlock = source.get_block(block_nr).await?.into();
+        writer.append(&data)?;
+    }
+    writer.finish()
+}
+
+/// Replays blocks from a bundle file, verifying each frame's checksum on read.
+#[derive(Debug, Clone)]
+pub struct BundleSource {
+    pub path: PathBuf,
+    pub index: BTreeMap<u64, BundleIndexEntry>,
+}
+
+impl BundleSource {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let mut file = File::open(&path)?;
+
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic != BUNDLE_MAGIC {
+            return Err(anyhow!("{} is not a block bundle", path.display()));
+        }
+
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+        file.read_exact(&mut footer)?;
+        if &footer[12..] != BUNDLE_MAGIC {
+            return Err(anyhow!(
+                "{} has a truncated or corrupted footer",
+                path.display()
+            ));
+        }
+        let index_offset = u64::from_le_bytes(footer[..8].try_into()?);
+        let index_len = u32::from_le_bytes(footer[8..12].try_into()?);
+
+        let mut index = vec![0u8; index_len as usize];
+        file.seek(SeekFrom::Start(index_offset))?;
+        file.read_exact(&mut index)?;
+        let entries: Vec<BundleIndexEntry> = borsh::from_slice(&index)?;
+
+        Ok(Self {
+            path,
+            index: entries
+                .into_iter()
+                .map(|entry| (entry.block_number, entry))
+                .collect(),
+        })
+    }
+
+    pub async fn get_archived_block(&self, block_nr: u64) -> Result<WvmArchiverDataBlock, Error> {
+        let entry = self.index.get(&block_nr).ok_or_else(|| {
+            anyhow!(
+                "block #{} is not in bundle {}",
+                block_nr,
+                self.path.display()
+            )
+        })?;
+
+        let mut file = tokio::fs::File::open(&self.path).await?;
+        file.seek(SeekFrom::Start(entry.offset)).await?;
+        let mut payload = vec![0u8; entry.len as usize];
+        file.read_exact(&mut payload).await?;
+
+        let mut checksum = [0u8; 32];
+        file.read_exact(&mut checksum).await?;
+        if keccak256(&payload) != entry.checksum || checksum != entry.checksum {
+            return Err(anyhow!(
+                "checksum mismatch for block #{} in bundle {}",
+                block_nr,
+                self.path.display()
+            ));
+        }
+
+        decode_archived_calldata(&payload)
+    }
+}
+
+#[async_trait]
+impl BlockSource for BundleSource {
+    async fn block_range(&self) -> Result<(u64, u64), Error> {
+        match (self.index.keys().next(), self.index.keys().next_back()) {
+            (Some(first), Some(last)) => Ok((*first, *last)),
+            _ => Err(anyhow!("bundle {} is empty", self.path.display())),
+        }
+    }
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error> {
+        self.get_archived_block(block_nr)
+            .await?
+            .into_block_receipts()
+    }
+}
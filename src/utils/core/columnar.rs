@@ -0,0 +1,633 @@
+use crate::utils::core::evm_wvm_types::{WvmBlock, WvmLog, WvmTransaction, WvmTransactionReceipt};
+use crate::utils::core::hooks::{ReplayHooks, StateChange};
+use anyhow::{anyhow, Context as _, Error};
+use arrow::array::RecordBatch;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::json::ReaderBuilder;
+use ethers::types::{Block, Transaction, TransactionReceipt, U256};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+/// Writes blocks, transactions, receipts, logs and state diffs of a re-execution replay as
+/// columnar files, one directory per table and one file per range of `partition_size` blocks:
+/// `<dir>/<table>/<first block>-<last block>.<ext>`.
+///
+/// Register a clone with `StateReconstructor::with_hooks` and call `finish` once the replay is
+/// done. Rows of discarded or reverted blocks are dropped while still buffered; reverting
+/// blocks whose partition was already written fails the export. Existing files are never
+/// overwritten. Hashes, addresses and byte strings are `0x`-prefixed hex; 256-bit quantities
+/// (values, prices, balances) are decimal strings and everything that fits is a `UInt64`;
+/// a `UInt64` field that does not fit, such as a bogus gas limit, fails the export.
+#[derive(Clone)]
+pub struct ColumnarExporter {
+    inner: Arc<Mutex<Exporter>>,
+}
+
+struct Exporter {
+    dir: PathBuf,
+    format: ExportFormat,
+    partition_size: u64,
+    /// Partition the buffered rows belong to.
+    partition: Option<u64>,
+    block_number: u64,
+    /// Last block of the partitions written so far.
+    flushed_to: Option<u64>,
+    tables: Tables,
+    written: Vec<PathBuf>,
+    /// First failure; hooks cannot fail, so it is reported by `finish`.
+    error: Option<Error>,
+}
+
+#[derive(Default)]
+struct Tables {
+    blocks: Vec<BlockRow>,
+    transactions: Vec<TransactionRow>,
+    receipts: Vec<ReceiptRow>,
+    logs: Vec<LogRow>,
+    state_diffs: Vec<StateDiffRow>,
+    storage_diffs: Vec<StorageDiffRow>,
+}
+
+impl ColumnarExporter {
+    pub fn new(dir: impl Into<PathBuf>, format: ExportFormat, partition_size: u64) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Exporter {
+                dir: dir.into(),
+                format,
+                partition_size: partition_size.max(1),
+                partition: None,
+                block_number: 0,
+                flushed_to: None,
+                tables: Tables::default(),
+                written: Vec::new(),
+                error: None,
+            })),
+        }
+    }
+
+    /// Writes the rows still buffered and returns every file written so far.
+    pub fn finish(&self) -> Result<Vec<PathBuf>, Error> {
+        let mut exporter = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        exporter.flush();
+        match exporter.error.take() {
+            Some(error) => Err(error),
+            None => Ok(exporter.written.clone()),
+        }
+    }
+
+    fn with(&self, f: impl FnOnce(&mut Exporter)) {
+        f(&mut self.inner.lock().unwrap_or_else(|e| e.into_inner()));
+    }
+}
+
+impl ReplayHooks for ColumnarExporter {
+    fn on_block_start(&mut self, block: &Block<Transaction>) {
+        let number = block.number.unwrap_or_default().as_u64();
+        self.with(|exporter| {
+            let partition = number / exporter.partition_size;
+            if exporter.partition != Some(partition) {
+                exporter.flush();
+                exporter.partition = Some(partition);
+            }
+            exporter.block_number = number;
+
+            let block = WvmBlock::from(block.clone());
+            let rows = BlockRow::new(&block).and_then(|row| {
+                let transactions = block
+                    .transactions
+                    .iter()
+                    .map(|tx| TransactionRow::new(number, tx))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((row, transactions))
+            });
+            match rows {
+                Ok((row, transactions)) => {
+                    exporter.tables.blocks.push(row);
+                    exporter.tables.transactions.extend(transactions);
+                }
+                Err(error) => exporter.fail(error),
+            }
+        });
+    }
+
+    fn on_state_change(&mut self, change: &StateChange) {
+        self.with(|exporter| {
+            let number = exporter.block_number;
+            let tables = &mut exporter.tables;
+            tables.state_diffs.push(StateDiffRow::new(number, change));
+            tables
+                .storage_diffs
+                .extend(
+                    change
+                        .storage
+                        .iter()
+                        .map(|(slot, before, after)| StorageDiffRow {
+                            block_number: number,
+                            transaction_hash: hex(change.tx_hash.as_bytes()),
+                            address: hex(change.address.as_bytes()),
+                            slot: hex(slot.as_bytes()),
+                            value_before: hex(before.as_bytes()),
+                            value_after: hex(after.as_bytes()),
+                        }),
+                );
+        });
+    }
+
+    fn on_receipts(&mut self, block: &Block<Transaction>, receipts: &[TransactionReceipt]) {
+        let number = block.number.unwrap_or_default().as_u64();
+        self.with(|exporter| {
+            for receipt in receipts {
+                let receipt = WvmTransactionReceipt::from(receipt.clone());
+                let row = match ReceiptRow::new(number, &receipt) {
+                    Ok(row) => row,
+                    Err(error) => {
+                        exporter.fail(error);
+                        continue;
+                    }
+                };
+                let tables = &mut exporter.tables;
+                tables.logs.extend(
+                    receipt
+                        .logs
+                        .iter()
+                        .enumerate()
+                        .map(|(index, log)| LogRow::new(number, &receipt, index, log)),
+                );
+                tables.receipts.push(row);
+            }
+        });
+    }
+
+    fn on_block_discarded(&mut self, block: &Block<Transaction>) {
+        let number = block.number.unwrap_or_default().as_u64();
+        self.with(|exporter| exporter.tables.truncate_after(number.saturating_sub(1)));
+    }
+
+    fn on_revert(&mut self, number: u64) {
+        self.with(|exporter| {
+            exporter.tables.truncate_after(number);
+            exporter.block_number = number;
+            if exporter.flushed_to.is_some_and(|last| last > number) {
+                exporter.fail(anyhow!(
+                    "blocks after #{} were reverted but are already written to {}",
+                    number,
+                    exporter.dir.display()
+                ));
+            }
+        });
+    }
+}
+
+impl Tables {
+    /// Drops the rows of the blocks after `number`.
+    fn truncate_after(&mut self, number: u64) {
+        self.blocks.retain(|row| row.number <= number);
+        self.transactions.retain(|row| row.block_number <= number);
+        self.receipts.retain(|row| row.block_number <= number);
+        self.logs.retain(|row| row.block_number <= number);
+        self.state_diffs.retain(|row| row.block_number <= number);
+        self.storage_diffs.retain(|row| row.block_number <= number);
+    }
+}
+
+impl Exporter {
+    fn fail(&mut self, error: Error) {
+        self.error.get_or_insert(error);
+    }
+
+    fn flush(&mut self) {
+        let Some(partition) = self.partition else {
+            return;
+        };
+        let first = partition * self.partition_size;
+        let name = format!(
+            "{:010}-{:010}.{}",
+            first,
+            first + self.partition_size - 1,
+            self.format.extension()
+        );
+        let tables = std::mem::take(&mut self.tables);
+        if let Err(error) = self.write_tables(&name, &tables) {
+            self.fail(error);
+        }
+        if let Some(last) = tables.blocks.last() {
+            self.flushed_to = Some(last.number);
+        }
+        self.partition = None;
+    }
+
+    /// Writes every table, even when an earlier one fails.
+    fn write_tables(&mut self, name: &str, tables: &Tables) -> Result<(), Error> {
+        let results = [
+            self.write_table("blocks", name, block_schema(), &tables.blocks),
+            self.write_table(
+                "transactions",
+                name,
+                transaction_schema(),
+                &tables.transactions,
+            ),
+            self.write_table("receipts", name, receipt_schema(), &tables.receipts),
+            self.write_table("logs", name, log_schema(), &tables.logs),
+            self.write_table(
+                "state_diffs",
+                name,
+                state_diff_schema(),
+                &tables.state_diffs,
+            ),
+            self.write_table(
+                "storage_diffs",
+                name,
+                storage_diff_schema(),
+                &tables.storage_diffs,
+            ),
+        ];
+        let errors: Vec<String> = results
+            .into_iter()
+            .filter_map(Result::err)
+            .map(|error| format!("{:#}", error))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("{}", errors.join("; ")))
+        }
+    }
+
+    /// Writes `rows` to `<dir>/<table>/<name>`, unless there are none.
+    fn write_table<R: Serialize>(
+        &mut self,
+        table: &str,
+        name: &str,
+        schema: Schema,
+        rows: &[R],
+    ) -> Result<(), Error> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let dir = self.dir.join(table);
+        let path = dir.join(name);
+        fs::create_dir_all(&dir)
+            .map_err(Error::from)
+            .and_then(|()| write_batch(&path, self.format, to_batch(Arc::new(schema), rows)?))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        self.written.push(path);
+        Ok(())
+    }
+}
+
+fn to_batch<R: Serialize>(schema: SchemaRef, rows: &[R]) -> Result<RecordBatch, Error> {
+    let mut decoder = ReaderBuilder::new(schema.clone()).build_decoder()?;
+    decoder.serialize(rows)?;
+    Ok(decoder
+        .flush()?
+        .unwrap_or_else(|| RecordBatch::new_empty(schema)))
+}
+
+/// Writes `batch` to `path` as a single CSV (with a header) or Parquet (Snappy) file, failing
+/// if `path` already exists.
+pub fn write_batch(path: &Path, format: ExportFormat, batch: RecordBatch) -> Result<(), Error> {
+    let file = File::create_new(path)?;
+    match format {
+        ExportFormat::Csv => arrow::csv::Writer::new(file).write(&batch)?,
+        ExportFormat::Parquet => {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+            writer.write(&batch)?;
+            writer.close()?;
+        }
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// A 256-bit big-endian quantity in decimal.
+fn decimal(bytes: &[u8; 32]) -> String {
+    U256::from_big_endian(bytes).to_string()
+}
+
+/// A 256-bit big-endian quantity expected to fit in 64 bits, such as gas or a timestamp.
+fn small(name: &str, bytes: &[u8; 32]) -> Result<u64, Error> {
+    let value = U256::from_big_endian(bytes);
+    if value.bits() > 64 {
+        return Err(anyhow!(
+            "{} {} does not fit in a UInt64 column",
+            name,
+            value
+        ));
+    }
+    Ok(value.as_u64())
+}
+
+fn field(name: &str, data_type: DataType, nullable: bool) -> Field {
+    Field::new(name, data_type, nullable)
+}
+
+#[derive(Serialize)]
+struct BlockRow {
+    number: u64,
+    hash: Option<String>,
+    parent_hash: String,
+    author: Option<String>,
+    state_root: String,
+    transactions_root: String,
+    receipts_root: String,
+    gas_used: u64,
+    gas_limit: u64,
+    timestamp: u64,
+    base_fee_per_gas: Option<String>,
+    extra_data: String,
+    transaction_count: u64,
+}
+
+fn block_schema() -> Schema {
+    use DataType::{UInt64, Utf8};
+    Schema::new(vec![
+        field("number", UInt64, false),
+        field("hash", Utf8, true),
+        field("parent_hash", Utf8, false),
+        field("author", Utf8, true),
+        field("state_root", Utf8, false),
+        field("transactions_root", Utf8, false),
+        field("receipts_root", Utf8, false),
+        field("gas_used", UInt64, false),
+        field("gas_limit", UInt64, false),
+        field("timestamp", UInt64, false),
+        field("base_fee_per_gas", Utf8, true),
+        field("extra_data", Utf8, false),
+        field("transaction_count", UInt64, false),
+    ])
+}
+
+impl BlockRow {
+    fn new(block: &WvmBlock<WvmTransaction>) -> Result<Self, Error> {
+        Ok(Self {
+            number: block.number.unwrap_or_default(),
+            hash: block.hash.as_ref().map(|hash| hex(hash)),
+            parent_hash: hex(&block.parent_hash),
+            author: block.author.as_ref().map(|author| hex(author)),
+            state_root: hex(&block.state_root),
+            transactions_root: hex(&block.transactions_root),
+            receipts_root: hex(&block.receipts_root),
+            gas_used: small("gas_used", &block.gas_used)?,
+            gas_limit: small("gas_limit", &block.gas_limit)?,
+            timestamp: small("timestamp", &block.timestamp)?,
+            base_fee_per_gas: block.base_fee_per_gas.as_ref().map(decimal),
+            extra_data: hex(&block.extra_data),
+            transaction_count: block.transactions.len() as u64,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct TransactionRow {
+    block_number: u64,
+    transaction_index: Option<u64>,
+    hash: String,
+    from: String,
+    to: Option<String>,
+    nonce: u64,
+    value: String,
+    gas: u64,
+    gas_price: Option<String>,
+    max_fee_per_gas: Option<String>,
+    max_priority_fee_per_gas: Option<String>,
+    input: String,
+    transaction_type: Option<u64>,
+}
+
+fn transaction_schema() -> Schema {
+    use DataType::{UInt64, Utf8};
+    Schema::new(vec![
+        field("block_number", UInt64, false),
+        field("transaction_index", UInt64, true),
+        field("hash", Utf8, false),
+        field("from", Utf8, false),
+        field("to", Utf8, true),
+        field("nonce", UInt64, false),
+        field("value", Utf8, false),
+        field("gas", UInt64, false),
+        field("gas_price", Utf8, true),
+        field("max_fee_per_gas", Utf8, true),
+        field("max_priority_fee_per_gas", Utf8, true),
+        field("input", Utf8, false),
+        field("transaction_type", UInt64, true),
+    ])
+}
+
+impl TransactionRow {
+    fn new(block_number: u64, tx: &WvmTransaction) -> Result<Self, Error> {
+        Ok(Self {
+            block_number,
+            transaction_index: tx.transaction_index,
+            hash: hex(&tx.hash),
+            from: hex(&tx.from),
+            to: tx.to.as_ref().map(|to| hex(to)),
+            nonce: small("nonce", &tx.nonce)?,
+            value: decimal(&tx.value),
+            gas: small("gas", &tx.gas)?,
+            gas_price: tx.gas_price.as_ref().map(decimal),
+            max_fee_per_gas: tx.max_fee_per_gas.as_ref().map(decimal),
+            max_priority_fee_per_gas: tx.max_priority_fee_per_gas.as_ref().map(decimal),
+            input: hex(&tx.input),
+            transaction_type: tx.transaction_type,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct ReceiptRow {
+    block_number: u64,
+    transaction_index: u64,
+    transaction_hash: String,
+    from: String,
+    to: Option<String>,
+    contract_address: Option<String>,
+    status: Option<u64>,
+    gas_used: Option<u64>,
+    cumulative_gas_used: u64,
+    effective_gas_price: Option<String>,
+    log_count: u64,
+}
+
+fn receipt_schema() -> Schema {
+    use DataType::{UInt64, Utf8};
+    Schema::new(vec![
+        field("block_number", UInt64, false),
+        field("transaction_index", UInt64, false),
+        field("transaction_hash", Utf8, false),
+        field("from", Utf8, false),
+        field("to", Utf8, true),
+        field("contract_address", Utf8, true),
+        field("status", UInt64, true),
+        field("gas_used", UInt64, true),
+        field("cumulative_gas_used", UInt64, false),
+        field("effective_gas_price", Utf8, true),
+        field("log_count", UInt64, false),
+    ])
+}
+
+impl ReceiptRow {
+    fn new(block_number: u64, receipt: &WvmTransactionReceipt) -> Result<Self, Error> {
+        Ok(Self {
+            block_number,
+            transaction_index: receipt.transaction_index,
+            transaction_hash: hex(&receipt.transaction_hash),
+            from: hex(&receipt.from),
+            to: receipt.to.as_ref().map(|to| hex(to)),
+            contract_address: receipt.contract_address.as_ref().map(|a| hex(a)),
+            status: receipt.status,
+            gas_used: receipt
+                .gas_used
+                .as_ref()
+                .map(|gas| small("gas_used", gas))
+                .transpose()?,
+            cumulative_gas_used: small("cumulative_gas_used", &receipt.cumulative_gas_used)?,
+            effective_gas_price: receipt.effective_gas_price.as_ref().map(decimal),
+            log_count: receipt.logs.len() as u64,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct LogRow {
+    block_number: u64,
+    transaction_index: u64,
+    transaction_hash: String,
+    log_index: u64,
+    address: String,
+    topic0: Option<String>,
+    topic1: Option<String>,
+    topic2: Option<String>,
+    topic3: Option<String>,
+    data: String,
+}
+
+fn log_schema() -> Schema {
+    use DataType::{UInt64, Utf8};
+    Schema::new(vec![
+        field("block_number", UInt64, false),
+        field("transaction_index", UInt64, false),
+        field("transaction_hash", Utf8, false),
+        field("log_index", UInt64, false),
+        field("address", Utf8, false),
+        field("topic0", Utf8, true),
+        field("topic1", Utf8, true),
+        field("topic2", Utf8, true),
+        field("topic3", Utf8, true),
+        field("data", Utf8, false),
+    ])
+}
+
+impl LogRow {
+    /// The `index`-th log of `receipt`; the block-wide `log_index` is used when archived.
+    fn new(block_number: u64, receipt: &WvmTransactionReceipt, index: usize, log: &WvmLog) -> Self {
+        let topic = |i: usize| log.topics.get(i).map(|topic| hex(topic));
+        Self {
+            block_number,
+            transaction_index: receipt.transaction_index,
+            transaction_hash: hex(&receipt.transaction_hash),
+            log_index: log.log_index.unwrap_or(index as u64),
+            address: hex(&log.address),
+            topic0: topic(0),
+            topic1: topic(1),
+            topic2: topic(2),
+            topic3: topic(3),
+            data: hex(&log.data),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StateDiffRow {
+    block_number: u64,
+    transaction_hash: String,
+    address: String,
+    nonce_before: u64,
+    nonce_after: u64,
+    balance_before: String,
+    balance_after: String,
+    created: bool,
+    destroyed: bool,
+    storage_changes: u64,
+}
+
+fn state_diff_schema() -> Schema {
+    use DataType::{Boolean, UInt64, Utf8};
+    Schema::new(vec![
+        field("block_number", UInt64, false),
+        field("transaction_hash", Utf8, false),
+        field("address", Utf8, false),
+        field("nonce_before", UInt64, false),
+        field("nonce_after", UInt64, false),
+        field("balance_before", Utf8, false),
+        field("balance_after", Utf8, false),
+        field("created", Boolean, false),
+        field("destroyed", Boolean, false),
+        field("storage_changes", UInt64, false),
+    ])
+}
+
+impl StateDiffRow {
+    fn new(block_number: u64, change: &StateChange) -> Self {
+        Self {
+            block_number,
+            transaction_hash: hex(change.tx_hash.as_bytes()),
+            address: hex(change.address.as_bytes()),
+            nonce_before: change.nonce.0.low_u64(),
+            nonce_after: change.nonce.1.low_u64(),
+            balance_before: change.balance.0.to_string(),
+            balance_after: change.balance.1.to_string(),
+            created: change.created,
+            destroyed: change.destroyed,
+            storage_changes: change.storage.len() as u64,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StorageDiffRow {
+    block_number: u64,
+    transaction_hash: String,
+    address: String,
+    slot: String,
+    value_before: String,
+    value_after: String,
+}
+
+fn storage_diff_schema() -> Schema {
+    use DataType::{UInt64, Utf8};
+    Schema::new(vec![
+        field("block_number", UInt64, false),
+        field("transaction_hash", Utf8, false),
+        field("address", Utf8, false),
+        field("slot", Utf8, false),
+        field("value_before", Utf8, false),
+        field("value_after", Utf8, false),
+    ])
+}
@@ -1,15 +1,30 @@
-use crate::utils::core::genesis_load::Genesis;
+use crate::utils::core::genesis_load::{AccountAlloc, Genesis};
+use crate::utils::core::history::{HistoryUnavailable, StateHistory};
+use crate::utils::core::hooks::{state_changes, to_ethers_log, ReplayHooks, ReplayInspector};
+use crate::utils::core::journal::{BlockDiff, ReorgDetected, StateJournal};
+use crate::utils::core::proof::{account_proof, hashed_address, state_root};
+use crate::utils::core::selection::{DependencyTracker, Selection};
+use crate::utils::core::snapshot::StateSnapshot;
+use crate::utils::core::tokens::TokenIndexer;
+use crate::utils::core::tracers::Tracer;
 use ethereum_types::{H160, H256, U256};
-use ethers::types::{Block, Transaction, TransactionReceipt};
-use ethers::utils::rlp;
+use ethers::types::{
+    Block, Bytes, EIP1186ProofResponse, GethTraceFrame, Log, Transaction, TransactionReceipt,
+};
 use revm::{
-    db::InMemoryDB,
-    primitives::{Address, ExecutionResult, TransactTo, U256 as rU256},
-    Database, Evm,
+    db::{AccountState as DbAccountState, CacheDB, InMemoryDB},
+    inspector_handle_register,
+    primitives::{
+        Address, BlockEnv, Env, ExecutionResult, ResultAndState, TransactTo, B256, U256 as rU256,
+    },
+    DatabaseCommit, DatabaseRef, Evm,
 };
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use tracing::debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AccountState {
     pub nonce: U256,
     pub balance: U256,
@@ -17,235 +32,1031 @@ pub struct AccountState {
     pub code: Vec<u8>,
 }
 
+impl AccountState {
+    /// No nonce, balance, code or storage, as in EIP-161.
+    pub fn is_empty(&self) -> bool {
+        self.nonce.is_zero()
+            && self.balance.is_zero()
+            && self.code.is_empty()
+            && self.storage.values().all(|value| value.is_zero())
+    }
+}
+
+/// Parameters of a read-only call, shaped like the `eth_call` transaction object.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRequest {
+    pub from: Option<H160>,
+    pub to: Option<H160>,
+    pub gas: Option<U256>,
+    pub gas_price: Option<U256>,
+    pub value: Option<U256>,
+    #[serde(alias = "input")]
+    pub data: Option<Bytes>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CallOutcome {
+    pub success: bool,
+    /// Return data, or the revert data if the call reverted.
+    pub output: Bytes,
+    pub gas_used: u64,
+    /// Set when execution halted without reverting, e.g. out of gas.
+    pub halt_reason: Option<String>,
+}
+
+impl From<&ExecutionResult> for CallOutcome {
+    fn from(result: &ExecutionResult) -> Self {
+        match result {
+            ExecutionResult::Success {
+                gas_used, output, ..
+            } => Self {
+                success: true,
+                output: output.data().to_vec().into(),
+                gas_used: *gas_used,
+                halt_reason: None,
+            },
+            ExecutionResult::Revert { gas_used, output } => Self {
+                success: false,
+                output: output.to_vec().into(),
+                gas_used: *gas_used,
+                halt_reason: None,
+            },
+            ExecutionResult::Halt { reason, gas_used } => Self {
+                success: false,
+                output: Bytes::default(),
+                gas_used: *gas_used,
+                halt_reason: Some(format!("{:?}", reason)),
+            },
+        }
+    }
+}
+
+impl From<&AccountState> for AccountAlloc {
+    fn from(account: &AccountState) -> Self {
+        Self {
+            balance: account.balance.to_string(),
+            code: if account.code.is_empty() {
+                String::new()
+            } else {
+                format!("0x{}", hex::encode(&account.code))
+            },
+            storage: account
+                .storage
+                .iter()
+                .filter(|(_, value)| !value.is_zero())
+                .map(|(slot, value)| (*slot, *value))
+                .collect(),
+            nonce: if account.nonce.is_zero() {
+                String::new()
+            } else {
+                format!("{:#x}", account.nonce)
+            },
+        }
+    }
+}
+
 pub struct StateReconstructor {
-    pub evm: Evm<'static, (), InMemoryDB>,
-    pub accounts: HashMap<H256, AccountState>,
+    pub db: InMemoryDB,
+    /// Execution environment; the tx part is overwritten for every replayed transaction.
+    pub env: Env,
+    pub accounts: HashMap<H160, AccountState>,
     pub block_number: u64,
+    pub head_hash: Option<H256>,
+    pub journal: StateJournal,
+    /// Per-block state archive, `None` unless enabled with `with_history`.
+    pub history: Option<StateHistory>,
+    /// Secure-trie keys (`keccak256(address)`) of the accounts, in trie order; `None`
+    /// unless enabled with `with_hashed_keys`.
+    pub hashed_keys: Option<BTreeMap<H256, H160>>,
+    /// Token balances indexed from receipt logs, `None` unless enabled with
+    /// `with_token_index`.
+    pub tokens: Option<TokenIndexer>,
+    /// Addresses a selective reconstruction keeps, `None` unless set with `with_selection`.
+    pub selection: Option<Selection>,
+    /// Callbacks run during replay, see `with_hooks`.
+    pub hooks: Vec<Box<dyn ReplayHooks>>,
+    /// Inspector run on every replayed transaction, see `with_inspector`.
+    pub inspector: Option<Box<dyn ReplayInspector>>,
+}
+
+impl Default for StateReconstructor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StateReconstructor {
     pub fn new() -> Self {
-        let db = InMemoryDB::default();
-        let evm = Evm::builder()
-            .with_db(db)
-            .modify_tx_env(|tx| {
-                tx.gas_price = rU256::ZERO;
-            })
-            .modify_block_env(|block| {
-                block.gas_limit = rU256::from(30_000_000);
-            })
-            .build();
+        let mut env = Env::default();
+        env.tx.gas_price = rU256::ZERO;
+        env.block.gas_limit = rU256::from(30_000_000);
 
         Self {
-            evm,
+            db: InMemoryDB::default(),
+            env,
             accounts: HashMap::new(),
             block_number: 0,
+            head_hash: None,
+            journal: StateJournal::default(),
+            history: None,
+            hashed_keys: None,
+            tokens: None,
+            selection: None,
+            hooks: Vec::new(),
+            inspector: None,
         }
     }
 
     pub fn apply_transaction(&mut self, tx: Transaction) -> Result<(), Box<dyn std::error::Error>> {
-        let from_addr = tx.from;
-        let to_addr = tx.to;
-
-        self.evm.tx_mut().caller = from_addr.0.into();
-        self.evm.tx_mut().gas_price = rU256::from(tx.gas_price.unwrap_or_default().as_u64());
-        self.evm.tx_mut().gas_limit = tx.gas.as_u64();
-        self.evm.tx_mut().transact_to = to_addr
-            .map(|t| TransactTo::Call(t.0.into()))
-            .unwrap_or(TransactTo::Create);
-        self.evm.tx_mut().value = rU256::from(tx.value.as_u64());
-        self.evm.tx_mut().data = tx.input.to_vec().into();
+        self.execute_transaction(tx, None)
+    }
 
-        let result = self.evm.transact()?.result;
+    fn execute_transaction(
+        &mut self,
+        tx: Transaction,
+        diff: Option<&mut BlockDiff>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tx_hash = tx.hash;
+        fill_tx_env(&mut self.env, &tx);
 
-        match result {
-            ExecutionResult::Success { .. } => {
-                // Update sender account
-                let from_converted: Address = Address::from_slice(&from_addr.0);
-                let from_info = self.evm.db_mut().basic(from_converted)?;
-                if let Some(account_info) = from_info {
-                    self.accounts.insert(
-                        from_addr.into(),
-                        AccountState {
-                            nonce: account_info.nonce.into(),
-                            balance: U256::from_big_endian(
-                                &account_info.balance.to_be_bytes::<32>(),
-                            ),
-                            storage: HashMap::new(),
-                            code: account_info
-                                .code
-                                .map(|code| code.bytecode().to_vec())
-                                .unwrap_or_default(),
-                        },
-                    );
-                }
+        let evm = Evm::builder().with_db(&mut self.db);
+        let ResultAndState { result, state } =
+            match (&mut self.selection, self.inspector.as_deref_mut()) {
+                (Some(selection), inspector) => evm
+                    .with_external_context(DependencyTracker::new(selection, inspector))
+                    .with_env(Box::new(self.env.clone()))
+                    .append_handler_register(inspector_handle_register)
+                    .build()
+                    .transact()?,
+                (None, Some(inspector)) => evm
+                    .with_external_context(inspector)
+                    .with_env(Box::new(self.env.clone()))
+                    .append_handler_register(inspector_handle_register)
+                    .build()
+                    .transact()?,
+                (None, None) => evm
+                    .with_env(Box::new(self.env.clone()))
+                    .build()
+                    .transact()?,
+            };
 
-                // Update recipient account (if any)
-                if let Some(to_addr) = to_addr {
-                    let to_converted: Address = Address::from_slice(&to_addr.0);
-                    let to_info = self.evm.db_mut().basic(to_converted)?;
-                    if let Some(account_info) = to_info {
-                        self.accounts.insert(
-                            to_addr.into(),
-                            AccountState {
-                                nonce: account_info.nonce.into(),
-                                balance: U256::from_big_endian(
-                                    &account_info.balance.to_be_bytes::<32>(),
-                                ),
-                                storage: HashMap::new(),
-                                code: account_info
-                                    .code
-                                    .map(|code| code.bytecode().to_vec())
-                                    .unwrap_or_default(),
-                            },
-                        );
-                    }
-                }
+        if let Some(selection) = &self.selection {
+            let stale = state
+                .keys()
+                .map(|address| H160::from(address.0 .0))
+                .find(|address| selection.is_stale(*address));
+            if let Some(address) = stale {
+                return Err(format!(
+                    "transaction {:?} loads {:?}, whose state is stale after a skipped block",
+                    tx_hash, address
+                )
+                .into());
+            }
+        }
 
-                // Handle contract creation
-                if to_addr.is_none() {
-                    // Derive the contract address
-                    let sender_nonce = {
-                        let db = self.evm.db_mut(); // Immutable borrow
-                        db.basic(from_addr.0.into())?.unwrap().nonce
-                    };
-                    let contract_address = H160::from_slice(
-                        &revm::primitives::keccak256(&rlp::encode_list::<&[u8], &[u8]>(&[
-                            &from_addr.0.as_ref(),                // Convert [u8; 20] to &[u8]
-                            &sender_nonce.to_be_bytes().as_ref(), // Convert u64 to &[u8]
-                        ]))[12..],
-                    );
-                    // Insert the new contract's account state
-                    let contract_info = self
-                        .evm
-                        .db_mut()
-                        .basic(Address::from_slice(&contract_address.0))?;
-                    if let Some(account_info) = contract_info {
-                        self.accounts.insert(
-                            H256::from_slice(&contract_address.0),
-                            AccountState {
-                                nonce: account_info.nonce.into(),
-                                balance: U256::from_big_endian(
-                                    &account_info.balance.to_be_bytes::<32>(),
-                                ),
-                                storage: HashMap::new(),
-                                code: account_info
-                                    .code
-                                    .map(|code| code.bytecode().to_vec())
-                                    .unwrap_or_default(),
-                            },
-                        );
-                    }
-                }
+        let number = diff
+            .as_ref()
+            .map(|diff| diff.number)
+            .unwrap_or(self.block_number);
+        // capture the pre-state before committing so the block can be reverted on reorg
+        if let Some(diff) = diff {
+            diff.record(&self.db, &state);
+        }
+        if let Some(history) = &mut self.history {
+            history.record(number, &state);
+        }
+        let changes = if self.hooks.is_empty() {
+            Vec::new()
+        } else {
+            state_changes(&self.db, &state, tx_hash)
+        };
+
+        let touched: Vec<Address> = state
+            .iter()
+            .filter(|(_, account)| account.is_touched())
+            .map(|(address, _)| *address)
+            .collect();
+        self.db.commit(state);
+
+        for address in touched {
+            self.sync_account(address);
+        }
+        // dependencies found by this transaction may have been read without being touched
+        let dependencies = match &mut self.selection {
+            Some(selection) => selection.take_pending(),
+            None => Vec::new(),
+        };
+        for address in dependencies {
+            self.sync_account(address.0.into());
+        }
+
+        match &result {
+            ExecutionResult::Success { .. } => {}
+            ExecutionResult::Revert { gas_used, output } => {
+                debug!(?tx_hash, gas_used, %output, "transaction reverted");
             }
-            ExecutionResult::Revert { .. } => {
-                println!("Transaction reverted");
+            ExecutionResult::Halt { reason, gas_used } => {
+                debug!(?tx_hash, gas_used, ?reason, "transaction halted");
             }
-            ExecutionResult::Halt { .. } => {
-                println!("Transaction halted");
+        }
+
+        if !self.hooks.is_empty() {
+            let outcome = CallOutcome::from(&result);
+            let logs: Vec<Log> = result
+                .logs()
+                .iter()
+                .enumerate()
+                .map(|(index, log)| to_ethers_log(&tx, log, index))
+                .collect();
+            for hooks in &mut self.hooks {
+                hooks.on_tx(&tx, &outcome);
+                for log in &logs {
+                    hooks.on_log(&tx, log);
+                }
+                for change in &changes {
+                    hooks.on_state_change(change);
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Refreshes the `accounts` view of `address` from the underlying db.
+    fn sync_account(&mut self, address: Address) {
+        let key = H160::from(address.0 .0);
+        let selected = self.is_selected(key);
+        let db = &self.db;
+        let Some(account) = db
+            .accounts
+            .get(&address)
+            .filter(|account| selected && account.account_state != DbAccountState::NotExisting)
+        else {
+            self.accounts.remove(&key);
+            if let Some(hashed_keys) = &mut self.hashed_keys {
+                hashed_keys.remove(&hashed_address(key));
+            }
+            return;
+        };
+
+        let code = account
+            .info
+            .code
+            .clone()
+            .or_else(|| db.contracts.get(&account.info.code_hash).cloned())
+            .map(|code| code.original_bytes().to_vec())
+            .unwrap_or_default();
+
+        if let Some(hashed_keys) = &mut self.hashed_keys {
+            hashed_keys.insert(hashed_address(key), key);
+        }
+        self.accounts.insert(
+            key,
+            AccountState {
+                nonce: account.info.nonce.into(),
+                balance: U256::from_big_endian(&account.info.balance.to_be_bytes::<32>()),
+                storage: account
+                    .storage
+                    .iter()
+                    .map(|(slot, value)| {
+                        (
+                            H256::from(slot.to_be_bytes::<32>()),
+                            H256::from(value.to_be_bytes::<32>()),
+                        )
+                    })
+                    .collect(),
+                code,
+            },
+        );
+    }
+
     pub fn apply_block(
         &mut self,
         block: &Block<Transaction>,
         receipts: &[TransactionReceipt],
     ) -> Result<(), Box<dyn std::error::Error>> {
-        assert_eq!(
-            block.transactions.len(),
-            receipts.len(),
-            "mismatched transaction and receipt counts"
-        );
+        let number = block.number.unwrap_or_default().as_u64();
+        if block.transactions.len() != receipts.len() {
+            return Err(format!(
+                "block #{}: {} transactions but {} receipts",
+                number,
+                block.transactions.len(),
+                receipts.len()
+            )
+            .into());
+        }
+        if self.is_reorg(block) {
+            return Err(Box::new(ReorgDetected {
+                block_number: number,
+                expected_parent: self.head_hash.unwrap_or_default(),
+                parent_hash: block.parent_hash,
+            }));
+        }
 
-        for (tx, receipt) in block.transactions.iter().zip(receipts.iter()) {
-            self.apply_transaction(tx.clone())?;
+        for hooks in &mut self.hooks {
+            hooks.on_block_start(block);
+        }
+        let parent_env = std::mem::take(&mut self.env.block);
+        self.env.block = block_env(block, &parent_env);
+        let skip = match &mut self.selection {
+            Some(selection)
+                if selection.skip_untouched_blocks
+                    && selection.can_skip(block, receipts, &self.db) =>
+            {
+                selection.skip(block, receipts);
+                true
+            }
+            _ => false,
+        };
+        let mut diff = BlockDiff::new(number, block.hash.unwrap_or_default(), block.parent_hash);
+        if skip {
+            debug!(number, "skipped block not touching the selection");
+        } else {
+            for tx in &block.transactions {
+                if let Err(err) = self.execute_transaction(tx.clone(), Some(&mut diff)) {
+                    self.discard_block(&diff);
+                    self.env.block = parent_env;
+                    for hooks in &mut self.hooks {
+                        hooks.on_block_discarded(block);
+                    }
+                    return Err(err);
+                }
+            }
         }
 
-        self.block_number = block.number.unwrap_or_default().as_u64();
+        self.journal.push(diff);
+        if let Some(hash) = block.hash {
+            self.record_block_hash(number, hash);
+        }
+        if let Some(history) = &mut self.history {
+            if !skip {
+                history.record_block(number, block);
+            }
+            history.finish_block(number);
+        }
+        if let Some(tokens) = &mut self.tokens {
+            tokens.index_block(block, receipts);
+        }
+        self.block_number = number;
+        self.head_hash = block.hash;
+        for hooks in &mut self.hooks {
+            hooks.on_receipts(block, receipts);
+            hooks.on_block_end(block);
+        }
         Ok(())
     }
 
-    pub fn get_account_state(&self, address: H256) -> Option<&AccountState> {
-        self.accounts.get(&address)
+    /// Undoes the transactions of a block that failed part-way, so that a block is either
+    /// applied whole or not at all.
+    fn discard_block(&mut self, diff: &BlockDiff) {
+        diff.revert(&mut self.db);
+        for address in diff.accounts.keys() {
+            self.sync_account(*address);
+        }
+        if let Some(history) = &mut self.history {
+            history.truncate_after(self.block_number);
+        }
+        if let Some(selection) = &mut self.selection {
+            selection.take_pending();
+        }
     }
 
-    pub fn from_genesis(genesis: &Genesis) -> Self {
-        let db = InMemoryDB::default();
-        let evm = Evm::builder()
-            .with_db(db)
-            .modify_tx_env(|tx| {
-                tx.gas_price = rU256::ZERO;
-            })
-            .modify_block_env(|block: &mut revm::primitives::BlockEnv| {
-                block.gas_limit =
-                    rU256::from_str_radix(&genesis.gas_limit.trim_start_matches("0x"), 16)
-                        .unwrap_or(rU256::from(30_000_000));
-                block.number = rU256::from_str_radix(
-                    &genesis
-                        .number
-                        .as_ref()
-                        .unwrap_or(&"0".to_string())
-                        .trim_start_matches("0x"),
-                    16,
-                )
-                .unwrap_or_default();
-                block.timestamp = rU256::from_str_radix(
-                    &genesis.timestamp.as_ref().unwrap().trim_start_matches("0x"),
-                    16,
+    /// Keeps the hash of block `number` for `BLOCKHASH`, in the db for the last 256 blocks
+    /// and in the history for as long as it retains them.
+    fn record_block_hash(&mut self, number: u64, hash: H256) {
+        self.db
+            .block_hashes
+            .insert(rU256::from(number), B256::from(hash.0));
+        if let Some(expired) = number.checked_sub(256) {
+            self.db.block_hashes.remove(&rU256::from(expired));
+        }
+        if let Some(history) = &mut self.history {
+            history.record_block_hash(number, hash);
+        }
+    }
+
+    /// Whether `block` is the next block by number but does not build on our head.
+    pub fn is_reorg(&self, block: &Block<Transaction>) -> bool {
+        let number = block.number.unwrap_or_default().as_u64();
+        match self.head_hash {
+            Some(head) => number == self.block_number + 1 && block.parent_hash != head,
+            None => false,
+        }
+    }
+
+    /// Rolls the state back to the end of block `number` using the journal.
+    pub fn revert_to(&mut self, number: u64) -> Result<(), Box<dyn std::error::Error>> {
+        if number >= self.block_number {
+            return Ok(());
+        }
+        match self.journal.oldest_revertible() {
+            Some(oldest) if number >= oldest => {}
+            _ => {
+                return Err(format!(
+                    "cannot revert to block #{}: journal only covers the last {} blocks",
+                    number,
+                    self.journal.blocks.len()
                 )
-                .unwrap_or_default();
-                block.coinbase = genesis.coinbase.unwrap_or_default().0.into();
-                block.difficulty =
-                    rU256::from_str_radix(&genesis.difficulty.trim_start_matches("0x"), 16)
-                        .unwrap_or_default();
-                block.basefee = rU256::from_str_radix(
-                    &genesis
-                        .base_fee_per_gas
-                        .as_ref()
-                        .unwrap_or(&"0x00".to_string())
-                        .trim_start_matches("0x"),
-                    16,
+                .into())
+            }
+        }
+
+        while let Some(diff) = self.journal.pop() {
+            diff.revert(&mut self.db);
+            for address in diff.accounts.keys() {
+                self.sync_account(*address);
+            }
+            self.block_number = diff.number - 1;
+            self.head_hash = Some(diff.parent_hash);
+            if self.block_number == number {
+                break;
+            }
+        }
+        if let Some(history) = &mut self.history {
+            history.truncate_after(self.block_number);
+        }
+        if let Some(tokens) = &mut self.tokens {
+            tokens.truncate_after(self.block_number);
+        }
+        for hooks in &mut self.hooks {
+            hooks.on_revert(self.block_number);
+        }
+
+        Ok(())
+    }
+
+    /// Rolls back the last `count` applied blocks.
+    pub fn revert_blocks(&mut self, count: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.revert_to(self.block_number.saturating_sub(count))
+    }
+
+    /// Sets how many recent blocks are kept in the reorg journal.
+    pub fn with_journal_depth(mut self, depth: usize) -> Self {
+        self.journal = StateJournal::new(depth);
+        self
+    }
+
+    /// Keeps an archive of per-block state, queryable for the last `retention` blocks
+    /// (every block if `None`) from the current block on.
+    pub fn with_history(mut self, retention: Option<u64>) -> Self {
+        let mut history = StateHistory::new(retention, self.block_number, &self.db);
+        for (number, hash) in &self.db.block_hashes {
+            history.record_block_hash(number.saturating_to(), H256::from(hash.0));
+        }
+        self.history = Some(history);
+        self
+    }
+
+    /// Indexes ERC-20, ERC-721 and ERC-1155 balances from the transfer logs of the receipts
+    /// of every block applied from now on, see `tokens::TokenIndexer`.
+    pub fn with_token_index(mut self) -> Self {
+        self.tokens = Some(TokenIndexer::new(self.block_number));
+        self
+    }
+
+    /// Keeps only the accounts in `selection` and their dependencies in `accounts` from now
+    /// on, while still executing every transaction; see `selection::Selection`.
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.accounts
+            .retain(|address, _| selection.contains(*address));
+        if let Some(hashed_keys) = &mut self.hashed_keys {
+            hashed_keys.retain(|_, address| selection.contains(*address));
+        }
+        self.selection = Some(selection);
+        self
+    }
+
+    /// Registers callbacks run for every replayed block, transaction, log and state change;
+    /// several sets run in the order they were added.
+    pub fn with_hooks(mut self, hooks: impl ReplayHooks + 'static) -> Self {
+        self.hooks.push(Box::new(hooks));
+        self
+    }
+
+    /// Runs `inspector` on every replayed transaction, replacing any previous one.
+    pub fn with_inspector(mut self, inspector: impl ReplayInspector + 'static) -> Self {
+        self.inspector = Some(Box::new(inspector));
+        self
+    }
+
+    /// Maintains the secure-trie key of every account, for walking the state in trie order
+    /// and looking accounts up by hashed key.
+    pub fn with_hashed_keys(mut self) -> Self {
+        self.hashed_keys = Some(
+            self.accounts
+                .keys()
+                .map(|address| (hashed_address(*address), *address))
+                .collect(),
+        );
+        self
+    }
+
+    /// The account whose secure-trie key is `hashed`; needs `with_hashed_keys`.
+    pub fn account_by_hashed_key(&self, hashed: H256) -> Option<(H160, &AccountState)> {
+        let address = *self.hashed_keys.as_ref()?.get(&hashed)?;
+        Some((address, self.accounts.get(&address)?))
+    }
+
+    /// Fails unless `block` is the head or within the retained history.
+    pub fn check_block(&self, block: u64) -> Result<(), HistoryUnavailable> {
+        if block == self.block_number {
+            return Ok(());
+        }
+        match &self.history {
+            Some(history) => history.check(block),
+            None => Err(HistoryUnavailable {
+                block_number: block,
+                range: None,
+            }),
+        }
+    }
+
+    /// Whether `address` is kept in `accounts`: always, unless a selection leaves it out.
+    pub fn is_selected(&self, address: H160) -> bool {
+        self.selection
+            .as_ref()
+            .is_none_or(|selection| selection.contains(address))
+    }
+
+    /// Account state as of the end of `block`; `None` for accounts a selection leaves out.
+    pub fn account_state_at(
+        &self,
+        address: H160,
+        block: u64,
+    ) -> Result<Option<AccountState>, HistoryUnavailable> {
+        self.check_block(block)?;
+        let history = match &self.history {
+            Some(history) if block != self.block_number && self.is_selected(address) => history,
+            _ => return Ok(self.get_account_state(address).cloned()),
+        };
+
+        Ok(history
+            .account_at(address, block)
+            .map(|account| AccountState {
+                nonce: account.nonce.into(),
+                balance: account.balance,
+                storage: history.all_storage_at(address, block),
+                code: history
+                    .code(account.code_hash)
+                    .map(|code| code.to_vec())
+                    .unwrap_or_default(),
+            }))
+    }
+
+    /// A single storage slot as of the end of `block`.
+    pub fn storage_at(
+        &self,
+        address: H160,
+        slot: H256,
+        block: u64,
+    ) -> Result<H256, HistoryUnavailable> {
+        self.check_block(block)?;
+        Ok(match &self.history {
+            Some(history) if block != self.block_number && self.is_selected(address) => {
+                history.storage_at(address, slot, block)
+            }
+            _ => self
+                .get_account_state(address)
+                .and_then(|account| account.storage.get(&slot).copied())
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Executes `request` at the head without committing anything.
+    pub fn call(&self, request: &CallRequest) -> Result<CallOutcome, Box<dyn std::error::Error>> {
+        self.call_at(request, self.block_number)
+    }
+
+    /// Executes `request` on top of the state as of `block`, without committing anything.
+    pub fn call_at(
+        &self,
+        request: &CallRequest,
+        block: u64,
+    ) -> Result<CallOutcome, Box<dyn std::error::Error>> {
+        self.simulate_at(request, self.gas_cap(request), block)
+    }
+
+    /// Smallest gas limit at which `request` succeeds at the head.
+    pub fn estimate_gas(&self, request: &CallRequest) -> Result<u64, Box<dyn std::error::Error>> {
+        self.estimate_gas_at(request, self.block_number)
+    }
+
+    /// Smallest gas limit at which `request` succeeds as of `block`, found by binary search
+    /// below the request's gas (or the block gas limit).
+    pub fn estimate_gas_at(
+        &self,
+        request: &CallRequest,
+        block: u64,
+    ) -> Result<u64, Box<dyn std::error::Error>> {
+        let cap = self.gas_cap(request);
+        let outcome = self.simulate_at(request, cap, block)?;
+        if !outcome.success {
+            return Err(format!(
+                "execution fails even with {} gas: {}",
+                cap,
+                outcome.halt_reason.as_deref().unwrap_or("reverted")
+            )
+            .into());
+        }
+
+        // refunds make gas_used a lower bound that may itself be too little
+        let (mut low, mut high) = (outcome.gas_used.saturating_sub(1), cap);
+        while low + 1 < high {
+            let mid = low + (high - low) / 2;
+            match self.simulate_at(request, mid, block) {
+                Ok(outcome) if outcome.success => high = mid,
+                _ => low = mid,
+            }
+        }
+        Ok(high)
+    }
+
+    /// The request's gas, saturated to `u64`, or the block gas limit if it has none.
+    fn gas_cap(&self, request: &CallRequest) -> u64 {
+        request
+            .gas
+            .map(|gas| gas.min(U256::from(u64::MAX)).as_u64())
+            .unwrap_or_else(|| self.env.block.gas_limit.saturating_to())
+    }
+
+    fn simulate_at(
+        &self,
+        request: &CallRequest,
+        gas_limit: u64,
+        block: u64,
+    ) -> Result<CallOutcome, Box<dyn std::error::Error>> {
+        self.check_block(block)?;
+        match &self.history {
+            Some(history) if block != self.block_number => {
+                self.simulate(history.database_at(block), request, gas_limit, block)
+            }
+            _ => self.simulate(&self.db, request, gas_limit, block),
+        }
+    }
+
+    fn simulate<DB>(
+        &self,
+        db: DB,
+        request: &CallRequest,
+        gas_limit: u64,
+        block: u64,
+    ) -> Result<CallOutcome, Box<dyn std::error::Error>>
+    where
+        DB: DatabaseRef,
+        DB::Error: std::error::Error + 'static,
+    {
+        let mut env = self.env.clone();
+        match self
+            .history
+            .as_ref()
+            .and_then(|history| history.block(block))
+        {
+            Some(header) => env.block = block_env(header, &env.block),
+            None => env.block.number = rU256::from(block),
+        }
+        // like eth_call, don't charge a base fee to zero-priced calls
+        env.block.basefee = rU256::ZERO;
+        env.tx.caller = request.from.unwrap_or_default().0.into();
+        env.tx.gas_limit = gas_limit;
+        env.tx.gas_price = to_revm_u256(request.gas_price.unwrap_or_default());
+        env.tx.gas_priority_fee = None;
+        env.tx.transact_to = request
+            .to
+            .map(|to| TransactTo::Call(to.0.into()))
+            .unwrap_or(TransactTo::Create);
+        env.tx.value = to_revm_u256(request.value.unwrap_or_default());
+        env.tx.data = request.data.clone().unwrap_or_default().to_vec().into();
+        env.tx.nonce = None;
+        env.tx.chain_id = None;
+
+        let ResultAndState { result, .. } = Evm::builder()
+            .with_ref_db(db)
+            .with_env(Box::new(env))
+            .build()
+            .transact()?;
+
+        Ok(CallOutcome::from(&result))
+    }
+
+    /// Re-executes the transaction `hash` of a retained block and traces it with `tracer`;
+    /// needs `with_history`.
+    pub fn trace_transaction(
+        &self,
+        hash: H256,
+        tracer: &Tracer,
+    ) -> Result<GethTraceFrame, Box<dyn std::error::Error>> {
+        let history = self
+            .history
+            .as_ref()
+            .ok_or("tracing a transaction by hash needs history")?;
+        let (number, index) = history
+            .transaction_location(hash)
+            .ok_or_else(|| format!("transaction {:?} is not in the retained history", hash))?;
+        let block = history
+            .block(number)
+            .ok_or_else(|| format!("block #{} is not in the retained history", number))?;
+        let mut traces = self.trace_transactions(block, index..index + 1, tracer)?;
+        Ok(traces.remove(0))
+    }
+
+    /// Traces of every transaction of the retained block `number`, in order.
+    pub fn trace_block_by_number(
+        &self,
+        number: u64,
+        tracer: &Tracer,
+    ) -> Result<Vec<(H256, GethTraceFrame)>, Box<dyn std::error::Error>> {
+        let empty = Block {
+            number: Some(number.into()),
+            ..Default::default()
+        };
+        let block = self
+            .history
+            .as_ref()
+            .and_then(|history| history.block(number))
+            .unwrap_or(&empty);
+        let transactions = &block.transactions;
+        if transactions.is_empty() {
+            // a block without transactions still has to be within reach
+            self.check_block(number.saturating_sub(1))?;
+            self.check_block(number)?;
+        }
+        let traces = self.trace_transactions(block, 0..transactions.len(), tracer)?;
+        Ok(transactions.iter().map(|tx| tx.hash).zip(traces).collect())
+    }
+
+    /// Re-executes `block` on the state before it and traces the transaction at `index`.
+    /// The block must be the next one, or one whose parent state is retained.
+    pub fn trace_block_transaction(
+        &self,
+        block: &Block<Transaction>,
+        index: usize,
+        tracer: &Tracer,
+    ) -> Result<GethTraceFrame, Box<dyn std::error::Error>> {
+        if index >= block.transactions.len() {
+            return Err(format!("block has no transaction {}", index).into());
+        }
+        let mut traces = self.trace_transactions(block, index..index + 1, tracer)?;
+        Ok(traces.remove(0))
+    }
+
+    /// Replays the transactions of `block` up to the end of `targets` on the state before
+    /// the block, tracing those in `targets`.
+    fn trace_transactions(
+        &self,
+        block: &Block<Transaction>,
+        targets: Range<usize>,
+        tracer: &Tracer,
+    ) -> Result<Vec<GethTraceFrame>, Box<dyn std::error::Error>> {
+        let parent = block
+            .number
+            .unwrap_or_default()
+            .as_u64()
+            .checked_sub(1)
+            .ok_or("the genesis block has no transactions")?;
+        self.check_block(parent)?;
+        match &self.history {
+            Some(history) if parent != self.block_number => {
+                self.trace_on(history.database_at(parent), block, targets, tracer)
+            }
+            _ => self.trace_on(&self.db, block, targets, tracer),
+        }
+    }
+
+    fn trace_on<DB>(
+        &self,
+        db: DB,
+        block: &Block<Transaction>,
+        targets: Range<usize>,
+        tracer: &Tracer,
+    ) -> Result<Vec<GethTraceFrame>, Box<dyn std::error::Error>>
+    where
+        DB: DatabaseRef,
+        DB::Error: std::error::Error + 'static,
+    {
+        let mut db = CacheDB::new(db);
+        let mut env = self.env.clone();
+        env.block = block_env(block, &self.env.block);
+
+        let mut traces = Vec::with_capacity(targets.len());
+        for (index, tx) in block.transactions.iter().enumerate().take(targets.end) {
+            fill_tx_env(&mut env, tx);
+            let state = if targets.contains(&index) {
+                let (trace, state) = tracer.trace(&mut db, env.clone())?;
+                traces.push(trace);
+                state
+            } else {
+                Evm::builder()
+                    .with_db(&mut db)
+                    .with_env(Box::new(env.clone()))
+                    .build()
+                    .transact()?
+                    .state
+            };
+            db.commit(state);
+        }
+        Ok(traces)
+    }
+
+    /// The state as of `block` in genesis `alloc` form, leaving out empty accounts.
+    pub fn alloc_at(&self, block: u64) -> Result<BTreeMap<H160, AccountAlloc>, HistoryUnavailable> {
+        self.check_block(block)?;
+        let addresses: Vec<H160> = match &self.history {
+            Some(history) if block != self.block_number => history
+                .addresses()
+                .copied()
+                .filter(|address| self.is_selected(*address))
+                .collect(),
+            _ => self.accounts.keys().copied().collect(),
+        };
+
+        let mut alloc = BTreeMap::new();
+        for address in addresses {
+            if let Some(account) = self
+                .account_state_at(address, block)?
+                .filter(|account| !account.is_empty())
+            {
+                alloc.insert(address, AccountAlloc::from(&account));
+            }
+        }
+        Ok(alloc)
+    }
+
+    /// A genesis file that boots a new chain from the state as of `block`; everything but
+    /// the `alloc` is taken from `template`.
+    pub fn genesis_at(
+        &self,
+        template: &Genesis,
+        block: u64,
+    ) -> Result<Genesis, HistoryUnavailable> {
+        Ok(Genesis {
+            alloc: self.alloc_at(block)?,
+            ..template.clone()
+        })
+    }
+
+    /// Merkle-Patricia root of the reconstructed head state.
+    pub fn state_root(&self) -> H256 {
+        state_root(&self.db)
+    }
+
+    /// The root a snapshot of `accounts` can be checked against on restore: the state root,
+    /// unless a selection keeps only part of the state it covers.
+    pub fn snapshot_root(&self) -> Option<H256> {
+        self.selection.is_none().then(|| self.state_root())
+    }
+
+    /// EIP-1186 proof of an account and some of its storage slots at the head.
+    pub fn get_proof(&self, address: H160, slots: &[H256]) -> EIP1186ProofResponse {
+        let slots: Vec<U256> = slots
+            .iter()
+            .map(|slot| U256::from_big_endian(slot.as_bytes()))
+            .collect();
+        account_proof(&self.db, address, &slots)
+    }
+
+    pub fn get_account_state(&self, address: H160) -> Option<&AccountState> {
+        self.accounts.get(&address)
+    }
+
+    /// Restores the state of a snapshot, using `genesis` for the chain configuration.
+    /// Fails if the snapshot records a state root that the restored state does not have.
+    pub fn from_snapshot(
+        genesis: &Genesis,
+        snapshot: &StateSnapshot,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut state = Self::from_genesis(&snapshot.genesis(genesis));
+        state.block_number = snapshot.block_number;
+        state.head_hash = snapshot.block_hash();
+        if let Some(hash) = state.head_hash {
+            state.record_block_hash(state.block_number, hash);
+        }
+
+        if let Some(expected) = snapshot.state_root() {
+            let root = state.state_root();
+            if root != expected {
+                return Err(format!(
+                    "snapshot of block #{} has state root {:?} but restores to {:?}",
+                    snapshot.block_number, expected, root
                 )
-                .unwrap_or_default();
-            })
-            .build();
+                .into());
+            }
+        }
+        Ok(state)
+    }
+
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        let mut env = Env::default();
+        env.cfg.chain_id = genesis.config.chain_id;
+        env.tx.gas_price = rU256::ZERO;
+
+        let block = &mut env.block;
+        block.gas_limit = rU256::from_str_radix(genesis.gas_limit.trim_start_matches("0x"), 16)
+            .unwrap_or(rU256::from(30_000_000));
+        block.number = rU256::from_str_radix(
+            genesis
+                .number
+                .as_ref()
+                .unwrap_or(&"0".to_string())
+                .trim_start_matches("0x"),
+            16,
+        )
+        .unwrap_or_default();
+        block.timestamp = rU256::from_str_radix(
+            genesis.timestamp.as_ref().unwrap().trim_start_matches("0x"),
+            16,
+        )
+        .unwrap_or_default();
+        block.coinbase = genesis.coinbase.unwrap_or_default().0.into();
+        block.difficulty = rU256::from_str_radix(genesis.difficulty.trim_start_matches("0x"), 16)
+            .unwrap_or_default();
+        block.basefee = rU256::from_str_radix(
+            genesis
+                .base_fee_per_gas
+                .as_ref()
+                .unwrap_or(&"0x00".to_string())
+                .trim_start_matches("0x"),
+            16,
+        )
+        .unwrap_or_default();
 
         let mut state = Self {
-            evm,
+            db: InMemoryDB::default(),
+            env,
             accounts: HashMap::new(),
             block_number: 0,
+            head_hash: None,
+            journal: StateJournal::default(),
+            history: None,
+            hashed_keys: None,
+            tokens: None,
+            selection: None,
+            hooks: Vec::new(),
+            inspector: None,
         };
 
         // Initialize accounts
         for (address, alloc) in &genesis.alloc {
-            let balance = rU256::from_str_radix(&alloc.balance, 10).unwrap_or(rU256::ZERO);
+            let balance = to_revm_u256(alloc.balance_value());
             let code = hex::decode(alloc.code.strip_prefix("0x").unwrap_or("")).unwrap_or_default();
-            let nonce = rU256::from_str_radix(&alloc.nonce.trim_start_matches("0x"), 16)
-                .unwrap_or(rU256::ZERO)
-                .to::<u64>();
-
-            for (key, value) in &alloc.storage {
-                let _ = state.evm.db_mut().insert_account_storage(
-                    address.0.into(),
-                    rU256::from_be_bytes(key.0),
-                    rU256::from_be_bytes(value.0),
-                );
-            }
+            let nonce = alloc.nonce_value();
 
-            state.evm.db_mut().insert_account_info(
+            // info first: storage inserted into an unknown account marks it as non-existent
+            state.db.insert_account_info(
                 address.0.into(),
                 revm::primitives::AccountInfo {
                     balance,
-                    nonce: nonce.into(),
+                    nonce,
                     code: Some(revm::primitives::Bytecode::new_raw(code.into())),
                     ..Default::default()
                 },
             );
+
+            for (key, value) in &alloc.storage {
+                let _ = state.db.insert_account_storage(
+                    address.0.into(),
+                    rU256::from_be_bytes(key.0),
+                    rU256::from_be_bytes(value.0),
+                );
+            }
+            state.sync_account(address.0.into());
         }
 
         state
     }
 }
+
+/// The context `block`'s transactions run in, taken from its header. Headers without an
+/// author keep the coinbase of `parent`, and blob pricing is carried over from it.
+fn block_env(block: &Block<Transaction>, parent: &BlockEnv) -> BlockEnv {
+    BlockEnv {
+        number: rU256::from(block.number.unwrap_or_default().as_u64()),
+        coinbase: block
+            .author
+            .map_or(parent.coinbase, |author| author.0.into()),
+        timestamp: to_revm_u256(block.timestamp),
+        gas_limit: to_revm_u256(block.gas_limit),
+        basefee: to_revm_u256(block.base_fee_per_gas.unwrap_or_default()),
+        difficulty: to_revm_u256(block.difficulty),
+        prevrandao: Some(B256::from(block.mix_hash.unwrap_or_default().0)),
+        blob_excess_gas_and_price: parent.blob_excess_gas_and_price.clone(),
+    }
+}
+
+fn fill_tx_env(env: &mut Env, tx: &Transaction) {
+    env.tx.caller = tx.from.0.into();
+    env.tx.gas_price = to_revm_u256(tx.gas_price.unwrap_or_default());
+    env.tx.gas_limit = tx.gas.as_u64();
+    env.tx.transact_to = tx
+        .to
+        .map(|t| TransactTo::Call(t.0.into()))
+        .unwrap_or(TransactTo::Create);
+    env.tx.value = to_revm_u256(tx.value);
+    env.tx.data = tx.input.to_vec().into();
+}
+
+fn to_revm_u256(value: U256) -> rU256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    rU256::from_be_bytes(bytes)
+}
@@ -9,6 +9,8 @@ use hex;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+pub type WvmAccessList = Vec<(Vec<u8>, Vec<[u8; 32]>)>;
+
 #[derive(
     BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default,
 )]
@@ -39,7 +41,7 @@ pub struct WvmTransaction {
     pub r: [u8; 32],
     pub s: [u8; 32],
     pub transaction_type: Option<u64>,
-    pub access_list: Option<Vec<(Vec<u8>, Vec<[u8; 32]>)>>,
+    pub access_list: Option<WvmAccessList>,
     pub max_priority_fee_per_gas: Option<[u8; 32]>,
     pub max_fee_per_gas: Option<[u8; 32]>,
     pub chain_id: Option<[u8; 32]>,
@@ -137,7 +139,6 @@ pub struct WvmLog {
 
 impl From<Transaction> for WvmTransaction {
     fn from(tx: Transaction) -> Self {
-        let mut other = ethers::types::OtherFields::default();
         Self {
             hash: tx.hash.0,
             nonce: {
@@ -422,7 +423,7 @@ impl From<WvmBlock<WvmTransaction>> for Block<Transaction> {
             gas_used: U256::from_big_endian(&wvm_block.gas_used),
             gas_limit: U256::from_big_endian(&wvm_block.gas_limit),
             extra_data: Bytes::from(wvm_block.extra_data),
-            logs_bloom: wvm_block.logs_bloom.map(|b| ethers::types::Bloom::from(b)),
+            logs_bloom: wvm_block.logs_bloom.map(ethers::types::Bloom::from),
             timestamp: U256::from_big_endian(&wvm_block.timestamp),
             difficulty: U256::from_big_endian(&wvm_block.difficulty),
             total_difficulty: wvm_block
@@ -433,7 +434,7 @@ impl From<WvmBlock<WvmTransaction>> for Block<Transaction> {
             transactions: wvm_block.transactions.into_iter().map(Into::into).collect(),
             size: wvm_block.size.map(|s| U256::from_big_endian(&s)),
             mix_hash: wvm_block.mix_hash.map(H256::from),
-            nonce: wvm_block.nonce.map(|n| ethers::types::H64::from(n)),
+            nonce: wvm_block.nonce.map(ethers::types::H64::from),
             base_fee_per_gas: wvm_block
                 .base_fee_per_gas
                 .map(|f| U256::from_big_endian(&f)),
@@ -1,33 +1,47 @@
-use ethers::types::{H160, H256};
-use serde::Deserialize;
-use serde_json;
-use std::collections::HashMap;
+use anyhow::{anyhow, Error};
+use ethers::types::{H160, H256, U256};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::BufWriter;
+use std::path::Path;
 
-#[derive(Debug, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Genesis {
     pub config: GenesisConfig,
-    pub alloc: HashMap<H160, AccountAlloc>,
+    pub alloc: BTreeMap<H160, AccountAlloc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub coinbase: Option<H160>,
     pub difficulty: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub extra_data: Option<String>,
     pub gas_limit: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub nonce: Option<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub mixhash: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub parent_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gas_used: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub base_fee_per_gas: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub excess_blob_gas: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub blob_gas_used: Option<String>,
+    /// Fields this crate does not read, kept so that re-exported genesis files stay complete.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct GenesisConfig {
     pub chain_id: u64,
@@ -40,33 +54,62 @@ pub struct GenesisConfig {
     pub petersburg_block: u64,
     pub istanbul_block: u64,
     pub berlin_block: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub london_block: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub merge_netsplit_block: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub terminal_total_difficulty: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub terminal_total_difficulty_passed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub shanghai_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub cancun_time: Option<u64>,
     #[serde(default)]
     pub deposit_contract_address: H160,
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
 }
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
 pub struct AccountAlloc {
     #[serde(default)]
     pub balance: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub code: String,
-    #[serde(default)]
-    pub storage: HashMap<H256, H256>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub storage: BTreeMap<H256, H256>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub nonce: String,
 }
 
-pub fn load_genesis_from_file(path: &str) -> Genesis {
-    let mut file = File::open(path).expect("Failed to open genesis file");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Failed to read genesis.json");
-    let genesis: Genesis = serde_json::from_str(&contents).expect("Failed to parse genesis.json");
-    genesis
+impl AccountAlloc {
+    /// Balance given either in decimal or as `0x`-prefixed hex; malformed values read as zero.
+    pub fn balance_value(&self) -> U256 {
+        match self.balance.strip_prefix("0x") {
+            Some(hex) => U256::from_str_radix(hex, 16).ok(),
+            None => U256::from_dec_str(&self.balance).ok(),
+        }
+        .unwrap_or_default()
+    }
+
+    /// Nonce, read as hex with or without the `0x` prefix; malformed values read as zero.
+    pub fn nonce_value(&self) -> u64 {
+        u64::from_str_radix(self.nonce.trim_start_matches("0x"), 16).unwrap_or_default()
+    }
+}
+
+pub fn load_genesis_from_file(path: impl AsRef<Path>) -> Result<Genesis, Error> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("failed to read genesis file {}: {}", path.display(), e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| anyhow!("failed to parse genesis file {}: {}", path.display(), e))
+}
+
+pub fn save_genesis_to_file(path: impl AsRef<Path>, genesis: &Genesis) -> Result<(), Error> {
+    let file = File::create(path)?;
+    serde_json::to_writer_pretty(BufWriter::new(file), genesis)?;
+    Ok(())
 }
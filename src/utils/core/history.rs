@@ -0,0 +1,380 @@
+use ethers::types::{Block, Bytes, Transaction, H160, H256, U256};
+use revm::db::{AccountState as DbAccountState, CacheDB, EmptyDB};
+use revm::primitives::{
+    AccountInfo, Address, Bytecode, EvmState, B256, KECCAK_EMPTY, U256 as rU256,
+};
+use revm::DatabaseRef;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Account fields as of some block; storage is versioned separately.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoricalAccount {
+    pub nonce: u64,
+    pub balance: U256,
+    pub code_hash: H256,
+}
+
+/// How far back `BLOCKHASH` can look.
+const BLOCK_HASH_WINDOW: u64 = 256;
+
+/// Values keyed by the block that wrote them; a key is `None` while the account does not exist.
+type Versions<T> = BTreeMap<u64, T>;
+
+/// Archive of per-block account and storage changes, answering queries "as of block N".
+///
+/// With a retention of `r` blocks, at least the last `r` blocks stay queryable; older
+/// versions are folded into a single baseline entry per key.
+#[derive(Debug, Clone, Default)]
+pub struct StateHistory {
+    pub retention: Option<u64>,
+    /// Earliest block that can be queried.
+    pub earliest: u64,
+    pub head: u64,
+    accounts: HashMap<H160, Versions<Option<HistoricalAccount>>>,
+    storage: HashMap<H160, HashMap<H256, Versions<H256>>>,
+    codes: HashMap<H256, Bytes>,
+    /// The retained blocks, kept so that their transactions can be re-executed and traced in
+    /// the same block context.
+    blocks: BTreeMap<u64, Block<Transaction>>,
+    tx_locations: HashMap<H256, (u64, usize)>,
+    /// Hashes of the retained blocks and of the 256 before them, for `BLOCKHASH`.
+    block_hashes: BTreeMap<u64, H256>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryUnavailable {
+    pub block_number: u64,
+    /// Range the history covers, `None` when history is disabled.
+    pub range: Option<(u64, u64)>,
+}
+
+impl fmt::Display for HistoryUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.range {
+            Some((earliest, head)) => write!(
+                f,
+                "historical state not available for block #{}: history covers #{} to #{}",
+                self.block_number, earliest, head
+            ),
+            None => write!(
+                f,
+                "historical state not available for block #{}: history is disabled",
+                self.block_number
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HistoryUnavailable {}
+
+impl StateHistory {
+    /// Starts the history at `block`, seeded with the whole of `db`.
+    pub fn new(retention: Option<u64>, block: u64, db: &CacheDB<EmptyDB>) -> Self {
+        let mut history = Self {
+            retention,
+            earliest: block,
+            head: block,
+            ..Default::default()
+        };
+
+        for (address, account) in &db.accounts {
+            if account.account_state == DbAccountState::NotExisting {
+                continue;
+            }
+            history.set_account(block, *address, Some(&account.info));
+            if let Some(code) = account
+                .info
+                .code
+                .clone()
+                .or_else(|| db.contracts.get(&account.info.code_hash).cloned())
+            {
+                history.insert_code(account.info.code_hash, &code);
+            }
+            for (slot, value) in &account.storage {
+                history.set_slot(block, *address, *slot, *value);
+            }
+        }
+        history
+    }
+
+    /// Records the changes of one transaction of block `number`. Must be called with the
+    /// same changes that are committed to the db.
+    pub fn record(&mut self, number: u64, changes: &EvmState) {
+        self.head = self.head.max(number);
+
+        for (address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+
+            if account.is_selfdestructed() || account.is_created() {
+                self.wipe_storage(number, *address);
+            }
+            if account.is_selfdestructed() {
+                self.set_account(number, *address, None);
+                continue;
+            }
+
+            self.set_account(number, *address, Some(&account.info));
+            if let Some(code) = &account.info.code {
+                self.insert_code(account.info.code_hash, code);
+            }
+            for (slot, value) in account.changed_storage_slots() {
+                self.set_slot(number, *address, *slot, value.present_value);
+            }
+        }
+    }
+
+    /// Keeps block `number` with its transactions.
+    pub fn record_block(&mut self, number: u64, block: &Block<Transaction>) {
+        for (index, tx) in block.transactions.iter().enumerate() {
+            self.tx_locations.insert(tx.hash, (number, index));
+        }
+        self.blocks.insert(number, block.clone());
+    }
+
+    pub fn record_block_hash(&mut self, number: u64, hash: H256) {
+        self.block_hashes.insert(number, hash);
+    }
+
+    /// Marks block `number` as applied, pruning versions that fell out of the retention window.
+    pub fn finish_block(&mut self, number: u64) {
+        self.head = number;
+        let Some(retention) = self.retention else {
+            return;
+        };
+        // prune in batches so that the cost is amortized over `retention` blocks
+        if number.saturating_sub(self.earliest) < retention.saturating_mul(2).max(1) {
+            return;
+        }
+
+        let cutoff = number.saturating_sub(retention);
+        for versions in self.accounts.values_mut() {
+            prune(versions, cutoff);
+        }
+        for slots in self.storage.values_mut() {
+            for versions in slots.values_mut() {
+                prune(versions, cutoff);
+            }
+        }
+        let retained = self.blocks.split_off(&(cutoff + 1));
+        let pruned = std::mem::replace(&mut self.blocks, retained);
+        self.forget_transactions(pruned);
+        self.block_hashes = self
+            .block_hashes
+            .split_off(&cutoff.saturating_sub(BLOCK_HASH_WINDOW));
+        self.earliest = cutoff;
+    }
+
+    /// Drops every version written after block `number`, e.g. when reverting a reorg.
+    pub fn truncate_after(&mut self, number: u64) {
+        for versions in self.accounts.values_mut() {
+            versions.split_off(&(number + 1));
+        }
+        for slots in self.storage.values_mut() {
+            for versions in slots.values_mut() {
+                versions.split_off(&(number + 1));
+            }
+        }
+        let dropped = self.blocks.split_off(&(number + 1));
+        self.forget_transactions(dropped);
+        self.block_hashes.split_off(&(number + 1));
+        self.head = number;
+        self.earliest = self.earliest.min(number);
+    }
+
+    pub fn check(&self, block: u64) -> Result<(), HistoryUnavailable> {
+        if (self.earliest..=self.head).contains(&block) {
+            Ok(())
+        } else {
+            Err(HistoryUnavailable {
+                block_number: block,
+                range: Some((self.earliest, self.head)),
+            })
+        }
+    }
+
+    pub fn account_at(&self, address: H160, block: u64) -> Option<HistoricalAccount> {
+        self.accounts
+            .get(&address)
+            .and_then(|versions| at(versions, block).cloned())
+            .flatten()
+    }
+
+    pub fn storage_at(&self, address: H160, slot: H256, block: u64) -> H256 {
+        self.storage
+            .get(&address)
+            .and_then(|slots| slots.get(&slot))
+            .and_then(|versions| at(versions, block).copied())
+            .unwrap_or_default()
+    }
+
+    /// Every non-zero slot of `address` as of `block`.
+    pub fn all_storage_at(&self, address: H160, block: u64) -> HashMap<H256, H256> {
+        self.storage
+            .get(&address)
+            .map(|slots| {
+                slots
+                    .iter()
+                    .filter_map(|(slot, versions)| {
+                        at(versions, block)
+                            .filter(|value| !value.is_zero())
+                            .map(|value| (*slot, *value))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Every address that had state at some point of the retained history.
+    pub fn addresses(&self) -> impl Iterator<Item = &H160> {
+        self.accounts.keys()
+    }
+
+    pub fn code(&self, code_hash: H256) -> Option<&Bytes> {
+        self.codes.get(&code_hash)
+    }
+
+    pub fn block_hash(&self, number: u64) -> Option<H256> {
+        self.block_hashes.get(&number).copied()
+    }
+
+    /// Block number and index of a transaction of the retained blocks.
+    pub fn transaction_location(&self, hash: H256) -> Option<(u64, usize)> {
+        self.tx_locations.get(&hash).copied()
+    }
+
+    /// Block `number`, if it is retained.
+    pub fn block(&self, number: u64) -> Option<&Block<Transaction>> {
+        self.blocks.get(&number)
+    }
+
+    /// The transactions of block `number`, if it is retained.
+    pub fn transactions(&self, number: u64) -> Option<&[Transaction]> {
+        self.block(number)
+            .map(|block| block.transactions.as_slice())
+    }
+
+    /// Read-only revm database over the state as of `block`.
+    pub fn database_at(&self, block: u64) -> HistoricalDb<'_> {
+        HistoricalDb {
+            history: self,
+            block,
+        }
+    }
+
+    fn set_account(&mut self, number: u64, address: Address, info: Option<&AccountInfo>) {
+        let account = info.map(|info| HistoricalAccount {
+            nonce: info.nonce,
+            balance: U256::from_big_endian(&info.balance.to_be_bytes::<32>()),
+            code_hash: H256::from(info.code_hash.0),
+        });
+        self.accounts
+            .entry(H160::from(address.0 .0))
+            .or_default()
+            .insert(number, account);
+    }
+
+    fn set_slot(&mut self, number: u64, address: Address, slot: rU256, value: rU256) {
+        self.storage
+            .entry(H160::from(address.0 .0))
+            .or_default()
+            .entry(H256::from(slot.to_be_bytes::<32>()))
+            .or_default()
+            .insert(number, H256::from(value.to_be_bytes::<32>()));
+    }
+
+    fn wipe_storage(&mut self, number: u64, address: Address) {
+        if let Some(slots) = self.storage.get_mut(&H160::from(address.0 .0)) {
+            for versions in slots.values_mut() {
+                versions.insert(number, H256::zero());
+            }
+        }
+    }
+
+    fn forget_transactions(&mut self, blocks: BTreeMap<u64, Block<Transaction>>) {
+        for tx in blocks.values().flat_map(|block| &block.transactions) {
+            self.tx_locations.remove(&tx.hash);
+        }
+    }
+
+    fn insert_code(&mut self, code_hash: B256, code: &Bytecode) {
+        if code_hash != KECCAK_EMPTY {
+            self.codes
+                .entry(H256::from(code_hash.0))
+                .or_insert_with(|| code.original_bytes().to_vec().into());
+        }
+    }
+}
+
+fn at<T>(versions: &Versions<T>, block: u64) -> Option<&T> {
+    versions.range(..=block).next_back().map(|(_, value)| value)
+}
+
+/// Keeps the versions after `cutoff` plus the one in effect at `cutoff`.
+fn prune<T>(versions: &mut Versions<T>, cutoff: u64) {
+    let newer = versions.split_off(&(cutoff + 1));
+    let baseline = versions.pop_last();
+    *versions = newer;
+    if let Some((number, value)) = baseline {
+        versions.insert(number, value);
+    }
+}
+
+/// Serves [`StateHistory`] as of a fixed block to revm, for calls against past state.
+pub struct HistoricalDb<'a> {
+    history: &'a StateHistory,
+    block: u64,
+}
+
+impl DatabaseRef for HistoricalDb<'_> {
+    type Error = HistoryUnavailable;
+
+    fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
+        Ok(self
+            .history
+            .account_at(H160::from(address.0 .0), self.block)
+            .map(|account| {
+                let code_hash = B256::from(account.code_hash.0);
+                AccountInfo {
+                    balance: rU256::from_be_bytes({
+                        let mut bytes = [0u8; 32];
+                        account.balance.to_big_endian(&mut bytes);
+                        bytes
+                    }),
+                    nonce: account.nonce,
+                    code_hash,
+                    code: self.code_by_hash_ref(code_hash).ok(),
+                }
+            }))
+    }
+
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Ok(self
+            .history
+            .code(H256::from(code_hash.0))
+            .map(|code| Bytecode::new_raw(code.to_vec().into()))
+            .unwrap_or_default())
+    }
+
+    fn storage_ref(&self, address: Address, index: rU256) -> Result<rU256, Self::Error> {
+        let value = self.history.storage_at(
+            H160::from(address.0 .0),
+            H256::from(index.to_be_bytes::<32>()),
+            self.block,
+        );
+        Ok(rU256::from_be_bytes(value.0))
+    }
+
+    /// Fails for blocks whose hash was never recorded, rather than answering a wrong one.
+    fn block_hash_ref(&self, number: u64) -> Result<B256, Self::Error> {
+        self.history
+            .block_hash(number)
+            .map(|hash| B256::from(hash.0))
+            .ok_or(HistoryUnavailable {
+                block_number: number,
+                range: Some((self.history.earliest, self.history.head)),
+            })
+    }
+}
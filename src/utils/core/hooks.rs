@@ -0,0 +1,137 @@
+use crate::utils::core::evm_exec::CallOutcome;
+use ethereum_types::{H160, H256, U256};
+use ethers::types::{Block, Log, Transaction, TransactionReceipt};
+use revm::db::InMemoryDB;
+use revm::primitives::{EvmState, Log as EvmLog};
+use revm::Inspector;
+
+/// Callbacks run while `evm_exec::StateReconstructor` re-executes blocks, registered with
+/// `with_hooks`. Every method defaults to doing nothing.
+///
+/// For each transaction, `on_tx` is called once it is committed, followed by `on_log` for
+/// each of its logs and `on_state_change` for each account it changed.
+///
+/// Events between `on_block_start` and `on_block_end` are provisional. If a transaction of the
+/// block fails, the block is rolled back and `on_block_discarded` is called instead of
+/// `on_block_end`, so everything reported since `on_block_start` must be dropped. Blocks that
+/// ended can still be rolled back by a reorg, which `on_revert` reports.
+pub trait ReplayHooks: Send + Sync {
+    fn on_block_start(&mut self, _block: &Block<Transaction>) {}
+
+    fn on_tx(&mut self, _tx: &Transaction, _outcome: &CallOutcome) {}
+
+    /// A log emitted by `tx`; reverted transactions have none.
+    fn on_log(&mut self, _tx: &Transaction, _log: &Log) {}
+
+    fn on_state_change(&mut self, _change: &StateChange) {}
+
+    /// The block's receipts as fetched with it, after its last transaction.
+    fn on_receipts(&mut self, _block: &Block<Transaction>, _receipts: &[TransactionReceipt]) {}
+
+    /// Called after the last transaction, once the state is at the end of `block`.
+    fn on_block_end(&mut self, _block: &Block<Transaction>) {}
+
+    /// `block` failed part-way and none of it was applied.
+    fn on_block_discarded(&mut self, _block: &Block<Transaction>) {}
+
+    /// Every block after `number` was rolled back and `number` is the head again.
+    fn on_revert(&mut self, _number: u64) {}
+}
+
+/// A revm inspector for opcode-level hooks, registered with `with_inspector` and run on
+/// every replayed transaction. Any `Inspector` generic over its database qualifies.
+pub trait ReplayInspector: for<'db> Inspector<&'db mut InMemoryDB> + Send + Sync {}
+
+impl<I: for<'db> Inspector<&'db mut InMemoryDB> + Send + Sync> ReplayInspector for I {}
+
+/// How one transaction changed one account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateChange {
+    pub tx_hash: H256,
+    pub address: H160,
+    /// Before and after the transaction.
+    pub nonce: (U256, U256),
+    pub balance: (U256, U256),
+    /// Set when the transaction deployed code at `address`.
+    pub created: bool,
+    pub destroyed: bool,
+    /// Slots whose value changed, as `(slot, before, after)`.
+    pub storage: Vec<(H256, H256, H256)>,
+}
+
+impl StateChange {
+    fn is_noop(&self) -> bool {
+        self.nonce.0 == self.nonce.1
+            && self.balance.0 == self.balance.1
+            && !self.created
+            && !self.destroyed
+            && self.storage.is_empty()
+    }
+}
+
+/// The changes of a transaction's uncommitted `state`, relative to `db` before it is
+/// committed; accounts that were only read are left out. The db is used rather than the
+/// reconstructor's accounts, which may leave out unselected ones.
+pub(crate) fn state_changes(db: &InMemoryDB, state: &EvmState, tx_hash: H256) -> Vec<StateChange> {
+    let mut changes: Vec<StateChange> = state
+        .iter()
+        .filter(|(_, account)| account.is_touched())
+        .map(|(address, account)| {
+            let before = db.accounts.get(address).map(|account| &account.info);
+            let address = H160::from(address.0 .0);
+            let mut storage: Vec<(H256, H256, H256)> = account
+                .storage
+                .iter()
+                .filter(|(_, slot)| slot.is_changed())
+                .map(|(slot, value)| {
+                    (
+                        H256::from(slot.to_be_bytes::<32>()),
+                        H256::from(value.original_value.to_be_bytes::<32>()),
+                        H256::from(value.present_value.to_be_bytes::<32>()),
+                    )
+                })
+                .collect();
+            storage.sort_unstable();
+
+            StateChange {
+                tx_hash,
+                address,
+                nonce: (
+                    before.map(|info| info.nonce).unwrap_or_default().into(),
+                    account.info.nonce.into(),
+                ),
+                balance: (
+                    before
+                        .map(|info| U256::from_big_endian(&info.balance.to_be_bytes::<32>()))
+                        .unwrap_or_default(),
+                    U256::from_big_endian(&account.info.balance.to_be_bytes::<32>()),
+                ),
+                created: account.is_created(),
+                destroyed: account.is_selfdestructed(),
+                storage,
+            }
+        })
+        .filter(|change| !change.is_noop())
+        .collect();
+    changes.sort_unstable_by_key(|change| change.address);
+    changes
+}
+
+/// `log` as emitted by `tx`, the `transaction_log_index`-th log of the transaction.
+pub(crate) fn to_ethers_log(tx: &Transaction, log: &EvmLog, transaction_log_index: usize) -> Log {
+    Log {
+        address: H160::from(log.address.0 .0),
+        topics: log
+            .topics()
+            .iter()
+            .map(|topic| H256::from(topic.0))
+            .collect(),
+        data: log.data.data.to_vec().into(),
+        block_hash: tx.block_hash,
+        block_number: tx.block_number,
+        transaction_hash: Some(tx.hash),
+        transaction_index: tx.transaction_index,
+        transaction_log_index: Some(transaction_log_index.into()),
+        ..Log::default()
+    }
+}
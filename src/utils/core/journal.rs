@@ -0,0 +1,183 @@
+use ethers::types::H256;
+use revm::db::{CacheDB, DbAccount, EmptyDB};
+use revm::primitives::{Address, EvmState, HashMap, U256 as rU256};
+use std::collections::VecDeque;
+use std::fmt;
+
+pub const DEFAULT_JOURNAL_DEPTH: usize = 64;
+
+/// Pre-block state of a single account, enough to undo whatever the block wrote to it.
+#[derive(Debug, Clone, Default)]
+pub struct AccountDiff {
+    /// Account info and status before the block, `None` if the account was not in the db.
+    pub account: Option<DbAccount>,
+    /// Previous values of the slots written by the block, `None` if the slot was unset.
+    pub storage: HashMap<rU256, Option<rU256>>,
+    /// Full storage before the block, recorded when a create or selfdestruct wiped it.
+    pub wiped_storage: Option<HashMap<rU256, rU256>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlockDiff {
+    pub number: u64,
+    pub hash: H256,
+    pub parent_hash: H256,
+    pub accounts: HashMap<Address, AccountDiff>,
+}
+
+impl BlockDiff {
+    pub fn new(number: u64, hash: H256, parent_hash: H256) -> Self {
+        Self {
+            number,
+            hash,
+            parent_hash,
+            accounts: HashMap::default(),
+        }
+    }
+
+    /// Records the pre-state of every account in `changes` that this block has not touched yet.
+    /// Must be called before `changes` is committed to `db`.
+    pub fn record(&mut self, db: &CacheDB<EmptyDB>, changes: &EvmState) {
+        for (address, account) in changes {
+            if !account.is_touched() {
+                continue;
+            }
+
+            let current = db.accounts.get(address);
+            let diff = self
+                .accounts
+                .entry(*address)
+                .or_insert_with(|| AccountDiff {
+                    account: current.map(|acc| DbAccount {
+                        info: acc.info.clone(),
+                        account_state: acc.account_state.clone(),
+                        storage: HashMap::default(),
+                    }),
+                    ..Default::default()
+                });
+
+            if (account.is_selfdestructed() || account.is_created()) && diff.wiped_storage.is_none()
+            {
+                // rebuild the storage as it was at block start, undoing earlier writes of this block
+                let mut storage = current.map(|acc| acc.storage.clone()).unwrap_or_default();
+                for (slot, prior) in &diff.storage {
+                    match prior {
+                        Some(value) => storage.insert(*slot, *value),
+                        None => storage.remove(slot),
+                    };
+                }
+                diff.wiped_storage = Some(storage);
+            }
+
+            for slot in account.storage.keys() {
+                diff.storage
+                    .entry(*slot)
+                    .or_insert_with(|| current.and_then(|acc| acc.storage.get(slot).copied()));
+            }
+        }
+    }
+
+    /// Restores every account touched by this block to its pre-block state.
+    pub fn revert(&self, db: &mut CacheDB<EmptyDB>) {
+        for (address, diff) in &self.accounts {
+            let Some(prior) = &diff.account else {
+                db.accounts.remove(address);
+                continue;
+            };
+
+            let account = db.accounts.entry(*address).or_default();
+            account.info = prior.info.clone();
+            account.account_state = prior.account_state.clone();
+
+            match &diff.wiped_storage {
+                Some(storage) => account.storage = storage.clone(),
+                None => {
+                    for (slot, value) in &diff.storage {
+                        match value {
+                            Some(value) => account.storage.insert(*slot, *value),
+                            None => account.storage.remove(slot),
+                        };
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Bounded journal of the most recent block diffs, used to roll the state back on reorgs.
+#[derive(Debug, Clone)]
+pub struct StateJournal {
+    pub depth: usize,
+    pub blocks: VecDeque<BlockDiff>,
+}
+
+impl Default for StateJournal {
+    fn default() -> Self {
+        Self::new(DEFAULT_JOURNAL_DEPTH)
+    }
+}
+
+impl StateJournal {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            blocks: VecDeque::with_capacity(depth),
+        }
+    }
+
+    pub fn push(&mut self, diff: BlockDiff) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.blocks.len() == self.depth {
+            self.blocks.pop_front();
+        }
+        self.blocks.push_back(diff);
+    }
+
+    pub fn pop(&mut self) -> Option<BlockDiff> {
+        self.blocks.pop_back()
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+    }
+
+    /// Lowest block number the journal can still revert to.
+    pub fn oldest_revertible(&self) -> Option<u64> {
+        self.blocks
+            .front()
+            .map(|diff| diff.number.saturating_sub(1))
+    }
+
+    /// Hash of the journaled block at `number`, including the parent of the oldest entry.
+    pub fn hash_at(&self, number: u64) -> Option<H256> {
+        let oldest = self.blocks.front()?;
+        if number + 1 == oldest.number {
+            return Some(oldest.parent_hash);
+        }
+        self.blocks
+            .iter()
+            .find(|diff| diff.number == number)
+            .map(|diff| diff.hash)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReorgDetected {
+    pub block_number: u64,
+    pub expected_parent: H256,
+    pub parent_hash: H256,
+}
+
+impl fmt::Display for ReorgDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "reorg detected at block #{}: parent hash {:?} does not match head {:?}",
+            self.block_number, self.parent_hash, self.expected_parent
+        )
+    }
+}
+
+impl std::error::Error for ReorgDetected {}
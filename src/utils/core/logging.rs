@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use tracing_subscriber::EnvFilter;
+
+/// How log events are written to stderr.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per event, with the fields of its enclosing spans.
+    Json,
+}
+
+/// Installs a global subscriber that writes events to stderr, keeping stdout for command
+/// output. `filter` uses `RUST_LOG` syntax (e.g. `info` or `warn,evm_state_reconstructing=debug`);
+/// `RUST_LOG` itself takes precedence when set.
+///
+/// The library only emits events; nothing is printed unless the application installs a
+/// subscriber, with this function or its own.
+pub fn init_logging(filter: &str, format: LogFormat) -> Result<(), Error> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(env) if !env.is_empty() => EnvFilter::try_new(env)?,
+        _ => EnvFilter::try_new(filter)?,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(false).try_init(),
+    }
+    .map_err(|e| anyhow!("failed to install the log subscriber: {}", e))
+}
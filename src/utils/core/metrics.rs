@@ -0,0 +1,132 @@
+use crate::utils::core::progress::{Progress, ProgressObserver};
+use anyhow::Error;
+use axum::extract::State;
+use axum::routing::get;
+use axum::Router;
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Gauge, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Buckets, in seconds, for the per-block fetch and execution times.
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Replay progress as Prometheus metrics, in a registry of its own.
+///
+/// Throughput is exposed as counters (`rate(..)` gives blocks, txs or gas per second);
+/// the current height, target, ETA and state size as gauges.
+pub struct ReplayMetrics {
+    registry: Registry,
+    blocks: IntCounter,
+    transactions: IntCounter,
+    gas_used: IntCounter,
+    fetch_errors: IntCounter,
+    fetch_seconds: Histogram,
+    execute_seconds: Histogram,
+    height: IntGauge,
+    target: IntGauge,
+    eta_seconds: Gauge,
+    accounts: IntGauge,
+    storage_slots: IntGauge,
+}
+
+impl ReplayMetrics {
+    pub fn new() -> Result<Self, Error> {
+        let registry = Registry::new_custom(Some("evm_reconstruct".to_string()), None)?;
+        let histogram = |name: &str, help: &str| {
+            Histogram::with_opts(HistogramOpts::new(name, help).buckets(DURATION_BUCKETS.to_vec()))
+        };
+
+        let metrics = Self {
+            blocks: IntCounter::new("blocks_total", "Blocks applied")?,
+            transactions: IntCounter::new("transactions_total", "Transactions applied")?,
+            gas_used: IntCounter::new("gas_used_total", "Gas used by the applied blocks")?,
+            fetch_errors: IntCounter::new("fetch_errors_total", "Blocks that failed to fetch")?,
+            fetch_seconds: histogram("block_fetch_seconds", "Time to fetch a block")?,
+            execute_seconds: histogram("block_execute_seconds", "Time to apply a block")?,
+            height: IntGauge::new("height", "Last applied block")?,
+            target: IntGauge::new("target_height", "Last block of the replay")?,
+            eta_seconds: Gauge::new("eta_seconds", "Estimated time until the target")?,
+            accounts: IntGauge::new("state_accounts", "Accounts in the state")?,
+            storage_slots: IntGauge::new("state_storage_slots", "Storage slots in the state")?,
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 11] = [
+            Box::new(metrics.blocks.clone()),
+            Box::new(metrics.transactions.clone()),
+            Box::new(metrics.gas_used.clone()),
+            Box::new(metrics.fetch_errors.clone()),
+            Box::new(metrics.fetch_seconds.clone()),
+            Box::new(metrics.execute_seconds.clone()),
+            Box::new(metrics.height.clone()),
+            Box::new(metrics.target.clone()),
+            Box::new(metrics.eta_seconds.clone()),
+            Box::new(metrics.accounts.clone()),
+            Box::new(metrics.storage_slots.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    /// The metrics in Prometheus' text exposition format.
+    pub fn render(&self) -> Result<String, Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl ProgressObserver for ReplayMetrics {
+    fn on_block(&self, progress: &Progress) {
+        self.blocks.inc();
+        self.transactions.inc_by(progress.block_transactions);
+        self.gas_used.inc_by(progress.block_gas_used);
+        self.fetch_seconds
+            .observe(progress.fetch_time.as_secs_f64());
+        self.execute_seconds
+            .observe(progress.execute_time.as_secs_f64());
+        self.height.set(progress.block_number as i64);
+        self.target.set(progress.to as i64);
+        self.eta_seconds
+            .set(progress.eta().unwrap_or_default().as_secs_f64());
+        self.accounts.set(progress.state_size.accounts as i64);
+        self.storage_slots
+            .set(progress.state_size.storage_slots as i64);
+    }
+
+    fn on_fetch_error(&self, _block_number: u64) {
+        self.fetch_errors.inc();
+    }
+}
+
+/// Binds `addr` and serves `metrics` on `/metrics` in the background, returning the bound address.
+pub async fn spawn_metrics_server(
+    metrics: Arc<ReplayMetrics>,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, JoinHandle<Result<(), Error>>), Error> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let router = Router::new()
+        .route("/metrics", get(handle_metrics))
+        .with_state(metrics);
+    let handle = tokio::spawn(async move {
+        axum::serve(listener, router).await?;
+        Ok(())
+    });
+    info!(address = %local_addr, "metrics server listening");
+    Ok((local_addr, handle))
+}
+
+async fn handle_metrics(State(metrics): State<Arc<ReplayMetrics>>) -> String {
+    metrics.render().unwrap_or_default()
+}
@@ -1,9 +1,28 @@
+pub mod block_source;
+pub mod bundle;
+pub mod columnar;
 pub mod evm_exec;
 pub mod evm_wvm_types;
 pub mod genesis_load;
+pub mod history;
+pub mod hooks;
+pub mod journal;
+pub mod logging;
+pub mod metrics;
 pub mod networks;
+pub mod progress;
+pub mod proof;
 pub mod reconstruct;
+pub mod reconstructor;
 pub mod rpc;
+pub mod rpc_server;
+pub mod selection;
 pub mod serde_arrays;
+pub mod snapshot;
 pub mod state;
+pub mod state_iter;
+pub mod tokens;
+pub mod tracers;
+pub mod verify;
 pub mod wvm_archiver;
+pub mod wvm_calldata;
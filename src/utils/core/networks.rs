@@ -1,9 +1,16 @@
 use crate::utils::constants::{METIS_RPC_URL, PHALA_RPC_URL, RSS3_VSL_RPC_URL, WVM_RPC_URL};
+use crate::utils::core::block_source::{RpcSource, WvmArchiverSource};
 use crate::utils::core::genesis_load::{load_genesis_from_file, Genesis};
+use crate::utils::core::wvm_calldata::WvmCalldataSource;
+use anyhow::{anyhow, Error};
 use ethers::providers::{Http, Provider};
 
+/// Names accepted by `Networks::by_name`, the networks whose genesis file ships in `./genesis`.
+pub const NETWORK_NAMES: &[&str] = &["weavevm", "metis"];
+
 #[derive(Debug, Clone)]
 pub struct Networks {
+    pub name: String,
     pub rpc_url: String,
     pub wvm_archiver_url: Option<String>,
     pub genesis_file: Genesis,
@@ -11,39 +18,84 @@ pub struct Networks {
 }
 
 impl Networks {
-    pub fn weavevm() -> Networks {
-        Self {
+    /// The registry network called `name`, `None` if there is none.
+    pub fn by_name(name: &str) -> Option<Result<Networks, Error>> {
+        match name.to_lowercase().as_str() {
+            "weavevm" => Some(Self::weavevm()),
+            "metis" => Some(Self::metis()),
+            _ => None,
+        }
+    }
+
+    pub fn weavevm() -> Result<Networks, Error> {
+        Ok(Self {
+            name: "weavevm".to_string(),
             rpc_url: WVM_RPC_URL.to_string(),
             wvm_archiver_url: None,
-            genesis_file: load_genesis_from_file("./genesis/wvm_alphanet.json"),
+            genesis_file: load_genesis_from_file("./genesis/wvm_alphanet_v3.json")?,
             rpc_provider: Provider::<Http>::try_from(WVM_RPC_URL).unwrap(),
-        }
+        })
     }
 
-    pub fn phala() -> Networks {
-        Self {
+    /// Phala mainnet; its genesis file is not shipped, place it at `./genesis/phala_mainnet.json`.
+    pub fn phala() -> Result<Networks, Error> {
+        Ok(Self {
+            name: "phala".to_string(),
             rpc_url: PHALA_RPC_URL.to_string(),
             wvm_archiver_url: Some("https://phala.wvm.network".to_string()),
-            genesis_file: load_genesis_from_file("./genesis/phala_mainnet.json"),
+            genesis_file: load_genesis_from_file("./genesis/phala_mainnet.json")?,
             rpc_provider: Provider::<Http>::try_from(PHALA_RPC_URL).unwrap(),
-        }
+        })
     }
 
-    pub fn rss3() -> Networks {
-        Self {
+    /// RSS3 VSL mainnet; its genesis file is not shipped, place it at `./genesis/rss3_vsl.json`.
+    pub fn rss3() -> Result<Networks, Error> {
+        Ok(Self {
+            name: "rss3".to_string(),
             rpc_url: WVM_RPC_URL.to_string(),
             wvm_archiver_url: Some("https://rss3.wvm.network".to_string()),
-            genesis_file: load_genesis_from_file("./genesis/rss3_vsl.json"),
+            genesis_file: load_genesis_from_file("./genesis/rss3_vsl.json")?,
             rpc_provider: Provider::<Http>::try_from(RSS3_VSL_RPC_URL).unwrap(),
-        }
+        })
     }
 
-    pub fn metis() -> Networks {
-        Self {
+    pub fn metis() -> Result<Networks, Error> {
+        Ok(Self {
+            name: "metis".to_string(),
             rpc_url: WVM_RPC_URL.to_string(),
             wvm_archiver_url: Some("https://metis.wvm.network".to_string()),
-            genesis_file: load_genesis_from_file("./genesis/metis_mainnet.json"),
+            genesis_file: load_genesis_from_file("./genesis/metis_mainnet.json")?,
             rpc_provider: Provider::<Http>::try_from(METIS_RPC_URL).unwrap(),
-        }
+        })
+    }
+
+    /// A network outside the built-in registry, e.g. a local devnet or a test archiver.
+    pub fn custom(
+        rpc_url: &str,
+        wvm_archiver_url: Option<String>,
+        genesis_path: &str,
+    ) -> Result<Networks, Error> {
+        let rpc_provider = Provider::<Http>::try_from(rpc_url)
+            .map_err(|e| anyhow!("invalid RPC URL `{}`: {}", rpc_url, e))?;
+        Ok(Self {
+            name: "custom".to_string(),
+            rpc_url: rpc_url.to_string(),
+            wvm_archiver_url,
+            genesis_file: load_genesis_from_file(genesis_path)?,
+            rpc_provider,
+        })
+    }
+
+    pub fn archiver_source(&self) -> WvmArchiverSource {
+        WvmArchiverSource::new(self.wvm_archiver_url.clone())
+    }
+
+    /// Reads archived blocks from WeaveVM calldata, using the archiver only as a tx index.
+    pub fn calldata_source(&self) -> WvmCalldataSource {
+        WvmCalldataSource::from_archiver(self.wvm_archiver_url.clone().unwrap_or_default())
+    }
+
+    pub fn rpc_source(&self) -> RpcSource {
+        RpcSource::new(self.rpc_provider.clone())
     }
 }
@@ -0,0 +1,134 @@
+use crate::utils::core::reconstructor::{Reconstructor, StateSize};
+use ethers::types::{Block, Transaction};
+use std::time::{Duration, Instant};
+
+/// How often `ProgressTracker` re-measures the state size, which walks every account.
+pub const STATE_SIZE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Where a replay of `from..=to` stands after applying `block_number`.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    pub from: u64,
+    pub to: u64,
+    pub block_number: u64,
+    /// Blocks, transactions and gas applied since the replay started.
+    pub blocks: u64,
+    pub transactions: u64,
+    pub gas_used: u64,
+    /// Transactions, gas and time spent fetching and executing in `block_number` alone.
+    pub block_transactions: u64,
+    pub block_gas_used: u64,
+    pub fetch_time: Duration,
+    pub execute_time: Duration,
+    pub elapsed: Duration,
+    /// Measured at most every `STATE_SIZE_INTERVAL`, and on the last block.
+    pub state_size: StateSize,
+}
+
+impl Progress {
+    pub fn blocks_per_sec(&self) -> f64 {
+        per_sec(self.blocks, self.elapsed)
+    }
+
+    pub fn txs_per_sec(&self) -> f64 {
+        per_sec(self.transactions, self.elapsed)
+    }
+
+    pub fn gas_per_sec(&self) -> f64 {
+        per_sec(self.gas_used, self.elapsed)
+    }
+
+    pub fn remaining_blocks(&self) -> u64 {
+        self.to.saturating_sub(self.block_number)
+    }
+
+    /// Time left at the average rate so far; `None` before the first block.
+    pub fn eta(&self) -> Option<Duration> {
+        let rate = self.blocks_per_sec();
+        (rate > 0.0).then(|| Duration::from_secs_f64(self.remaining_blocks() as f64 / rate))
+    }
+}
+
+fn per_sec(count: u64, elapsed: Duration) -> f64 {
+    match elapsed.as_secs_f64() {
+        secs if secs > 0.0 => count as f64 / secs,
+        _ => 0.0,
+    }
+}
+
+/// Notified by the replay functions of `reconstruct` as blocks are fetched and applied.
+pub trait ProgressObserver: Send + Sync {
+    fn on_block(&self, progress: &Progress);
+
+    /// Block `block_number` could not be fetched and was skipped.
+    fn on_fetch_error(&self, _block_number: u64) {}
+}
+
+impl<F: Fn(&Progress) + Send + Sync> ProgressObserver for F {
+    fn on_block(&self, progress: &Progress) {
+        self(progress)
+    }
+}
+
+/// Ignores every update.
+pub struct NoProgress;
+
+impl ProgressObserver for NoProgress {
+    fn on_block(&self, _progress: &Progress) {}
+}
+
+/// Accumulates `Progress` over a replay, for loops that drive a reconstructor themselves.
+#[derive(Debug)]
+pub struct ProgressTracker {
+    progress: Progress,
+    started: Instant,
+    size_measured: Option<Instant>,
+}
+
+impl ProgressTracker {
+    pub fn new(from: u64, to: u64) -> Self {
+        Self {
+            progress: Progress {
+                from,
+                to,
+                block_number: from.saturating_sub(1),
+                ..Progress::default()
+            },
+            started: Instant::now(),
+            size_measured: None,
+        }
+    }
+
+    /// Records that `block` was fetched and applied to `state` in the given times.
+    pub fn record<R: Reconstructor + ?Sized>(
+        &mut self,
+        block: &Block<Transaction>,
+        fetch_time: Duration,
+        execute_time: Duration,
+        state: &R,
+    ) -> &Progress {
+        let progress = &mut self.progress;
+        progress.block_number = block.number.unwrap_or_default().as_u64();
+        progress.block_transactions = block.transactions.len() as u64;
+        progress.block_gas_used = block.gas_used.low_u64();
+        progress.blocks += 1;
+        progress.transactions += progress.block_transactions;
+        progress.gas_used += progress.block_gas_used;
+        progress.fetch_time = fetch_time;
+        progress.execute_time = execute_time;
+        progress.elapsed = self.started.elapsed();
+
+        let stale = self
+            .size_measured
+            .is_none_or(|measured| measured.elapsed() >= STATE_SIZE_INTERVAL);
+        if stale || progress.block_number >= progress.to {
+            progress.state_size = state.state_size();
+            self.size_measured = Some(Instant::now());
+        }
+        &self.progress
+    }
+
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+}
@@ -0,0 +1,195 @@
+use alloy_trie::proof::{verify_proof, ProofRetainer};
+use alloy_trie::{HashBuilder, Nibbles, TrieAccount, EMPTY_ROOT_HASH};
+use anyhow::{anyhow, Error};
+use ethers::types::{Bytes, EIP1186ProofResponse, StorageProof, H160, H256, U256, U64};
+use revm::db::{AccountState as DbAccountState, CacheDB, DbAccount, EmptyDB};
+use revm::primitives::{keccak256, Address, Bytes as rBytes, B256, KECCAK_EMPTY, U256 as rU256};
+
+/// Key of `address` in the secure account trie.
+pub fn hashed_address(address: H160) -> H256 {
+    H256::from(keccak256(address.0).0)
+}
+
+/// Key of a storage slot in the secure storage trie.
+pub fn hashed_slot(slot: H256) -> H256 {
+    H256::from(keccak256(slot.0).0)
+}
+
+/// Root of the account trie over every non-empty account in `db`.
+pub fn state_root(db: &CacheDB<EmptyDB>) -> H256 {
+    let (root, _) = build_trie(account_leaves(db), &[]);
+    H256::from(root.0)
+}
+
+/// EIP-1186 proof of `address` and the given storage `slots` against `state_root(db)`.
+///
+/// The whole trie is rebuilt for every proof, so this is meant for occasional queries.
+pub fn account_proof(db: &CacheDB<EmptyDB>, address: H160, slots: &[U256]) -> EIP1186ProofResponse {
+    let hashed_address = keccak256(address.0);
+    let (_, mut account_proofs) = build_trie(account_leaves(db), &[hashed_address]);
+
+    let account = db
+        .accounts
+        .get(&Address::from(address.0))
+        .filter(|account| is_in_trie(account));
+    let trie_account = account.map(trie_account).unwrap_or_default();
+
+    let storage_leaves = account.map(storage_leaves).unwrap_or_default();
+    let slot_keys: Vec<B256> = slots
+        .iter()
+        .map(|slot| keccak256(u256_bytes(*slot)))
+        .collect();
+    let (storage_root, storage_proofs) = build_trie(storage_leaves, &slot_keys);
+
+    let storage_proof = slots
+        .iter()
+        .zip(storage_proofs)
+        .map(|(slot, proof)| StorageProof {
+            key: *slot,
+            value: account
+                .and_then(|account| {
+                    account
+                        .storage
+                        .get(&rU256::from_be_bytes(u256_bytes(*slot)))
+                })
+                .map(|value| U256::from_big_endian(&value.to_be_bytes::<32>()))
+                .unwrap_or_default(),
+            proof,
+        })
+        .collect();
+
+    EIP1186ProofResponse {
+        address,
+        balance: U256::from_big_endian(&trie_account.balance.to_be_bytes::<32>()),
+        code_hash: H256::from(trie_account.code_hash.0),
+        nonce: U64::from(trie_account.nonce),
+        storage_hash: H256::from(storage_root.0),
+        account_proof: account_proofs.pop().unwrap_or_default(),
+        storage_proof,
+    }
+}
+
+/// Checks an EIP-1186 proof, e.g. one served by a third party, against `state_root`.
+/// An all-default account is treated as a proof of absence.
+pub fn verify_account_proof(state_root: H256, proof: &EIP1186ProofResponse) -> Result<(), Error> {
+    let account = TrieAccount {
+        nonce: proof.nonce.as_u64(),
+        balance: rU256::from_be_bytes(u256_bytes(proof.balance)),
+        storage_root: B256::from(proof.storage_hash.0),
+        code_hash: B256::from(proof.code_hash.0),
+    };
+    let expected = (account != TrieAccount::default()).then(|| alloy_rlp::encode(account));
+
+    verify_proof(
+        B256::from(state_root.0),
+        Nibbles::unpack(keccak256(proof.address.0)),
+        expected,
+        &to_alloy_nodes(&proof.account_proof),
+    )
+    .map_err(|e| anyhow!("invalid account proof for {:?}: {}", proof.address, e))?;
+
+    for slot in &proof.storage_proof {
+        let expected = (!slot.value.is_zero())
+            .then(|| alloy_rlp::encode(rU256::from_be_bytes(u256_bytes(slot.value))));
+        verify_proof(
+            account.storage_root,
+            Nibbles::unpack(keccak256(u256_bytes(slot.key))),
+            expected,
+            &to_alloy_nodes(&slot.proof),
+        )
+        .map_err(|e| {
+            anyhow!(
+                "invalid storage proof for {:?} slot {:#x}: {}",
+                proof.address,
+                slot.key,
+                e
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Empty accounts are left out of the trie, as EIP-161 clears them from the state.
+fn is_in_trie(account: &DbAccount) -> bool {
+    account.account_state != DbAccountState::NotExisting
+        && !(account.info.is_empty() && account.storage.values().all(|value| value.is_zero()))
+}
+
+fn trie_account(account: &DbAccount) -> TrieAccount {
+    let (storage_root, _) = build_trie(storage_leaves(account), &[]);
+    TrieAccount {
+        nonce: account.info.nonce,
+        balance: account.info.balance,
+        storage_root,
+        code_hash: if account.info.code_hash.is_zero() {
+            KECCAK_EMPTY
+        } else {
+            account.info.code_hash
+        },
+    }
+}
+
+fn account_leaves(db: &CacheDB<EmptyDB>) -> Vec<(B256, Vec<u8>)> {
+    db.accounts
+        .iter()
+        .filter(|(_, account)| is_in_trie(account))
+        .map(|(address, account)| (keccak256(address), alloy_rlp::encode(trie_account(account))))
+        .collect()
+}
+
+fn storage_leaves(account: &DbAccount) -> Vec<(B256, Vec<u8>)> {
+    account
+        .storage
+        .iter()
+        .filter(|(_, value)| !value.is_zero())
+        .map(|(slot, value)| {
+            (
+                keccak256(slot.to_be_bytes::<32>()),
+                alloy_rlp::encode(value),
+            )
+        })
+        .collect()
+}
+
+/// Builds the trie over `leaves`, returning its root and a proof for each of `targets`.
+fn build_trie(mut leaves: Vec<(B256, Vec<u8>)>, targets: &[B256]) -> (B256, Vec<Vec<Bytes>>) {
+    if leaves.is_empty() {
+        return (EMPTY_ROOT_HASH, vec![Vec::new(); targets.len()]);
+    }
+    leaves.sort_unstable_by_key(|(key, _)| *key);
+
+    let targets: Vec<Nibbles> = targets.iter().map(Nibbles::unpack).collect();
+    let mut builder =
+        HashBuilder::default().with_proof_retainer(ProofRetainer::new(targets.clone()));
+    for (key, value) in &leaves {
+        builder.add_leaf(Nibbles::unpack(key), value);
+    }
+    let root = builder.root();
+
+    let nodes = builder.take_proof_nodes();
+    let proofs = targets
+        .iter()
+        .map(|target| {
+            nodes
+                .matching_nodes_sorted(target)
+                .into_iter()
+                .map(|(_, node)| Bytes::from(node.to_vec()))
+                .collect()
+        })
+        .collect();
+    (root, proofs)
+}
+
+fn to_alloy_nodes(nodes: &[Bytes]) -> Vec<rBytes> {
+    nodes
+        .iter()
+        .map(|node| rBytes::from(node.to_vec()))
+        .collect()
+}
+
+fn u256_bytes(value: U256) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    bytes
+}
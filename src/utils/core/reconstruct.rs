@@ -1,16 +1,32 @@
+use crate::utils::core::block_source::{BlockSource, BlockWithReceipts};
+use crate::utils::core::bundle::BundleSource;
 use crate::utils::core::evm_exec::StateReconstructor;
+use crate::utils::core::genesis_load::Genesis;
 use crate::utils::core::networks::Networks;
-use crate::utils::core::wvm_archiver::{get_block_from_wvm, load_network_archiver_info};
-use anyhow::Error;
-use ethers::types::H256;
+use crate::utils::core::progress::{NoProgress, ProgressObserver, ProgressTracker};
+use crate::utils::core::reconstructor::{ReconstructionMode, Reconstructor};
+use crate::utils::core::rpc::fetch_block_traces;
+use crate::utils::core::snapshot::StateSnapshot;
+use crate::utils::core::state;
+use crate::utils::core::state_iter::KeyRange;
+use crate::utils::core::wvm_archiver::load_network_archiver_info;
+use anyhow::{anyhow, Error};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Block, Transaction, TransactionReceipt};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument, Level};
 
 pub async fn reconstruct_network(network: Networks) -> Result<StateReconstructor, Error> {
-    let mut reconstructor = StateReconstructor::from_genesis(&network.genesis_file);
-
-    for (address, state) in &reconstructor.accounts {
-        println!("Address: {:?}, State: {:?}", address, state);
-    }
+    reconstruct_network_with_progress(network, &NoProgress).await
+}
 
+/// Like `reconstruct_network`, reporting each applied block to `observer`.
+#[tracing::instrument(name = "reconstruct_network", skip_all, fields(network = %network.name))]
+pub async fn reconstruct_network_with_progress(
+    network: Networks,
+    observer: &dyn ProgressObserver,
+) -> Result<StateReconstructor, Error> {
     let wvm_archiver_info = load_network_archiver_info(network.clone()).await?;
     let backfill_start_block = wvm_archiver_info
         .first_backfill_archived_block
@@ -19,46 +35,306 @@ pub async fn reconstruct_network(network: Networks) -> Result<StateReconstructor
         .last_backfill_archived_block
         .unwrap_or_default();
 
-    println!("\n[*] Fetching and reconstructing blocks");
-
-    for block_nr in backfill_start_block..backfill_end_block {
-        match get_block_from_wvm(network.wvm_archiver_url.clone(), block_nr).await {
-            Ok((block, receipts)) => {
-                // apply block to the chain state
-                let _ = reconstructor.apply_block(&block, &receipts);
-
-                for tx in &block.transactions {
-                    let sender = tx.from;
-                    if let Some(state) = reconstructor.get_account_state(H256::from(sender)) {
-                        println!("Sender state after transaction: {:?}", state);
-                    }
-
-                    if let Some(recipient) = tx.to {
-                        if let Some(state) = reconstructor.get_account_state(H256::from(recipient))
-                        {
-                            println!("Recipient state after transaction: {:?}", state);
-                        }
-                    }
-                }
-
-                println!(
-                    "\n[*] Fetched from WeaveVM & reconstructed block #{:?}",
-                    block_nr
-                );
+    let mut reconstructor = StateReconstructor::from_genesis(&network.genesis_file);
+    replay_blocks_with_progress(
+        &mut reconstructor,
+        &network.archiver_source(),
+        backfill_start_block,
+        backfill_end_block,
+        observer,
+    )
+    .await?;
+    Ok(reconstructor)
+}
+
+/// Replays every block of a bundle exported with `bundle::export_bundle`, fully offline.
+pub async fn reconstruct_from_bundle(
+    genesis: &Genesis,
+    path: impl AsRef<Path>,
+) -> Result<StateReconstructor, Error> {
+    let source = BundleSource::open(path.as_ref())?;
+    let (from, to) = source.block_range().await?;
+    reconstruct_from_source(genesis, &source, from, to).await
+}
+
+/// Replays blocks `from..=to` of `source` on top of `genesis`.
+pub async fn reconstruct_from_source<S: BlockSource + ?Sized>(
+    genesis: &Genesis,
+    source: &S,
+    from: u64,
+    to: u64,
+) -> Result<StateReconstructor, Error> {
+    let mut reconstructor = StateReconstructor::from_genesis(genesis);
+    replay_blocks(&mut reconstructor, source, from, to).await?;
+    Ok(reconstructor)
+}
+
+/// Replays blocks `from..=to` of `source` on top of `genesis` in the given `mode`.
+pub async fn reconstruct_with_mode<S: BlockSource + ?Sized>(
+    mode: ReconstructionMode,
+    genesis: &Genesis,
+    source: &S,
+    from: u64,
+    to: u64,
+) -> Result<Box<dyn Reconstructor>, Error> {
+    let mut reconstructor = mode.from_genesis(genesis);
+    replay_blocks(reconstructor.as_mut(), source, from, to).await?;
+    Ok(reconstructor)
+}
+
+/// Restores `snapshot` and checks its state root (and hash, if recorded) against the
+/// header `source` has at the snapshot's block.
+pub async fn import_snapshot<S: BlockSource + ?Sized>(
+    genesis: &Genesis,
+    snapshot: &StateSnapshot,
+    source: &S,
+) -> Result<StateReconstructor, Error> {
+    let (header, _) = source.get_block(snapshot.block_number).await?;
+    let mut reconstructor =
+        StateReconstructor::from_snapshot(genesis, snapshot).map_err(|e| anyhow!(e.to_string()))?;
+
+    let root = reconstructor.state_root();
+    if root != header.state_root {
+        return Err(anyhow!(
+            "imported state root {:?} does not match the state root {:?} of block #{}",
+            root,
+            header.state_root,
+            snapshot.block_number
+        ));
+    }
+    if let (Some(hash), Some(expected)) = (header.hash, snapshot.block_hash()) {
+        if hash != expected {
+            return Err(anyhow!(
+                "snapshot was taken at block {:?} but block #{} is {:?}",
+                expected,
+                snapshot.block_number,
+                hash
+            ));
+        }
+    }
+
+    reconstructor.head_hash = header.hash;
+    Ok(reconstructor)
+}
+
+/// Imports `snapshot` and replays the blocks after it up to `to`.
+pub async fn resume_from_snapshot<S: BlockSource + ?Sized>(
+    genesis: &Genesis,
+    snapshot: &StateSnapshot,
+    source: &S,
+    to: u64,
+) -> Result<StateReconstructor, Error> {
+    let mut reconstructor = import_snapshot(genesis, snapshot, source).await?;
+    replay_blocks(&mut reconstructor, source, snapshot.block_number + 1, to).await?;
+    Ok(reconstructor)
+}
+
+/// Replays blocks `from..=to` of `source` on top of an existing `reconstructor`.
+pub async fn replay_blocks<R: Reconstructor + ?Sized, S: BlockSource + ?Sized>(
+    reconstructor: &mut R,
+    source: &S,
+    from: u64,
+    to: u64,
+) -> Result<(), Error> {
+    replay_blocks_with_progress(reconstructor, source, from, to, &NoProgress).await
+}
+
+/// Like `replay_blocks`, reporting each applied block and each fetch failure to `observer`.
+///
+/// Stops at the first block that cannot be fetched or applied, leaving the head at the last
+/// block applied, since replaying past a gap would build on a wrong state.
+#[tracing::instrument(
+    name = "replay_blocks",
+    skip(reconstructor, source, observer),
+    fields(mode = ?reconstructor.mode())
+)]
+pub async fn replay_blocks_with_progress<R: Reconstructor + ?Sized, S: BlockSource + ?Sized>(
+    reconstructor: &mut R,
+    source: &S,
+    from: u64,
+    to: u64,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    if tracing::enabled!(Level::TRACE) {
+        for account in reconstructor.accounts(KeyRange::all()).flatten() {
+            if let Some(state) = reconstructor.account(account.address) {
+                trace!(address = ?account.address, ?state, "initial account");
             }
+        }
+    }
+
+    info!("fetching and reconstructing blocks");
+    let mut tracker = ProgressTracker::new(from, to);
+
+    for block_nr in from..=to {
+        let span = info_span!("block", number = block_nr);
+        let fetch_started = Instant::now();
+        let (block, receipts) = match source.get_block(block_nr).instrument(span.clone()).await {
+            Ok(fetched) => fetched,
             Err(e) => {
-                println!(
-                    "[!] Error fetching & reconstructing block #{}: {:?}",
-                    block_nr, e
-                );
+                error!(parent: &span, error = ?e, "failed to fetch block");
+                observer.on_fetch_error(block_nr);
+                return Err(e.context(format!("failed to fetch block #{}", block_nr)));
             }
+        };
+        let _entered = span.enter();
+        let fetch_time = fetch_started.elapsed();
+        let execute_started = Instant::now();
+        // apply block to the chain state
+        if let Err(e) = reconstructor.apply_block(&block, &receipts) {
+            error!(error = %e, "failed to apply block");
+            return Err(e.context(format!("failed to apply block #{}", block_nr)));
+        }
+        let execute_time = execute_started.elapsed();
+
+        for tx in &block.transactions {
+            debug!(tx_hash = ?tx.hash, from = ?tx.from, to = ?tx.to, "applied transaction");
+            trace!(
+                tx_hash = ?tx.hash,
+                sender = ?reconstructor.account(tx.from),
+                recipient = ?tx.to.and_then(|to| reconstructor.account(to)),
+                "account states after transaction"
+            );
         }
+
+        let progress = tracker.record(&block, fetch_time, execute_time, &*reconstructor);
+        info!(
+            transactions = block.transactions.len(),
+            duration_ms = (fetch_time + execute_time).as_millis() as u64,
+            eta_s = progress.eta().map(|eta| eta.as_secs()),
+            "reconstructed block"
+        );
+        observer.on_block(progress);
     }
 
-    println!(
-        "\n[*] State reconstruction completed from block #{} until block #{}",
-        backfill_start_block, backfill_end_block
+    let progress = tracker.progress();
+    info!(
+        duration_ms = progress.elapsed.as_millis() as u64,
+        blocks_per_sec = progress.blocks_per_sec(),
+        "state reconstruction completed"
     );
 
-    Ok(reconstructor)
+    Ok(())
+}
+
+/// Receipt-driven replay of blocks `from..=to`, with the call traces of each block taken from
+/// `provider`'s `trace_block` so that value moved inside contracts is accounted for.
+pub async fn replay_receipts_with_traces<S: BlockSource + ?Sized>(
+    reconstructor: &mut state::StateReconstructor,
+    source: &S,
+    provider: &Provider<Http>,
+    from: u64,
+    to: u64,
+    observer: &dyn ProgressObserver,
+) -> Result<(), Error> {
+    let mut tracker = ProgressTracker::new(from, to);
+    for block_nr in from..=to {
+        let fetch_started = Instant::now();
+        let (block, receipts) = source.get_block(block_nr).await?;
+        let traces = fetch_block_traces(provider, block_nr).await?;
+        let fetch_time = fetch_started.elapsed();
+
+        let execute_started = Instant::now();
+        reconstructor.apply_block_with_traces(&block, &receipts, Some(&traces))?;
+        info!(
+            number = block_nr,
+            traces = traces.len(),
+            "applied block with traces"
+        );
+        observer.on_block(tracker.record(
+            &block,
+            fetch_time,
+            execute_started.elapsed(),
+            &*reconstructor,
+        ));
+    }
+    Ok(())
+}
+
+/// Keeps `reconstructor` in sync with the archiver's livesync head, rolling back
+/// through the reorg journal whenever the archived chain switches branches.
+#[tracing::instrument(skip_all, fields(network = %network.name))]
+pub async fn follow_network(
+    network: Networks,
+    reconstructor: &mut StateReconstructor,
+    poll_interval: Duration,
+) -> Result<(), Error> {
+    follow_source(&network.archiver_source(), reconstructor, poll_interval).await
+}
+
+/// Keeps `reconstructor` in sync with the head of `source`, handling reorgs.
+pub async fn follow_source<S: BlockSource + ?Sized>(
+    source: &S,
+    reconstructor: &mut StateReconstructor,
+    poll_interval: Duration,
+) -> Result<(), Error> {
+    loop {
+        let (_, head) = source.block_range().await?;
+
+        while reconstructor.block_number < head {
+            let block_nr = reconstructor.block_number + 1;
+            let (block, receipts) = source.get_block(block_nr).await?;
+
+            if reconstructor.is_reorg(&block) {
+                handle_reorg(source, reconstructor, block, receipts).await?;
+            } else {
+                reconstructor
+                    .apply_block(&block, &receipts)
+                    .map_err(|e| anyhow!(e.to_string()))?;
+            }
+
+            info!(number = reconstructor.block_number, "followed block");
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Walks the new branch back to the last block we share with it, reverts to that
+/// ancestor and applies the new branch on top.
+pub async fn handle_reorg<S: BlockSource + ?Sized>(
+    source: &S,
+    reconstructor: &mut StateReconstructor,
+    block: Block<Transaction>,
+    receipts: Vec<TransactionReceipt>,
+) -> Result<(), Error> {
+    let oldest = reconstructor
+        .journal
+        .oldest_revertible()
+        .ok_or_else(|| anyhow!("reorg detected but the journal is empty"))?;
+    let mut branch: Vec<BlockWithReceipts> = vec![(block, receipts)];
+
+    let ancestor = loop {
+        let (tip, _) = branch.last().unwrap();
+        let parent_nr = tip.number.unwrap_or_default().as_u64().saturating_sub(1);
+
+        if reconstructor.journal.hash_at(parent_nr) == Some(tip.parent_hash) {
+            break parent_nr;
+        }
+        if parent_nr <= oldest {
+            return Err(anyhow!(
+                "reorg at block #{} is deeper than the journal (oldest revertible block #{})",
+                parent_nr,
+                oldest
+            ));
+        }
+
+        branch.push(source.get_block(parent_nr).await?);
+    };
+
+    warn!(
+        depth = reconstructor.block_number - ancestor,
+        ancestor, "reorg detected, reverting"
+    );
+    reconstructor
+        .revert_to(ancestor)
+        .map_err(|e| anyhow!(e.to_string()))?;
+
+    for (block, receipts) in branch.iter().rev() {
+        reconstructor
+            .apply_block(block, receipts)
+            .map_err(|e| anyhow!(e.to_string()))?;
+    }
+
+    Ok(())
 }
@@ -0,0 +1,178 @@
+use crate::utils::core::evm_exec::{self, AccountState};
+use crate::utils::core::genesis_load::{AccountAlloc, Genesis};
+use crate::utils::core::snapshot::StateSnapshot;
+use crate::utils::core::state;
+use crate::utils::core::state_iter::StateStore;
+use anyhow::{anyhow, Error};
+use ethers::types::{Block, Transaction, TransactionReceipt, H160, H256};
+use serde::Serialize;
+
+/// How archived blocks are turned into state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ReconstructionMode {
+    /// Fast approximation from transactions and receipts, see `state::StateReconstructor`.
+    ReceiptDriven,
+    /// Full EVM re-execution, see `evm_exec::StateReconstructor`.
+    #[default]
+    Reexecution,
+}
+
+impl ReconstructionMode {
+    pub fn from_genesis(self, genesis: &Genesis) -> Box<dyn Reconstructor> {
+        match self {
+            Self::ReceiptDriven => Box::new(state::StateReconstructor::from_genesis(genesis)),
+            Self::Reexecution => Box::new(evm_exec::StateReconstructor::from_genesis(genesis)),
+        }
+    }
+}
+
+/// Fields of an account that a reconstruction mode could not derive, and which may
+/// therefore differ from the real chain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UnknownFields {
+    pub balance: bool,
+    pub code: bool,
+    pub storage: bool,
+}
+
+impl UnknownFields {
+    pub fn is_empty(&self) -> bool {
+        !(self.balance || self.code || self.storage)
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.balance, "balance"),
+            (self.code, "code"),
+            (self.storage, "storage"),
+        ]
+        .into_iter()
+        .filter_map(|(unknown, name)| unknown.then_some(name))
+        .collect()
+    }
+}
+
+/// How much state a reconstructor holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StateSize {
+    pub accounts: usize,
+    pub storage_slots: usize,
+}
+
+impl StateSize {
+    fn of<'a>(accounts: impl ExactSizeIterator<Item = &'a AccountState>) -> Self {
+        Self {
+            accounts: accounts.len(),
+            storage_slots: accounts.map(|account| account.storage.len()).sum(),
+        }
+    }
+}
+
+/// What every reconstruction mode provides, so that callers can switch modes without
+/// rewriting. Mode-specific features (history, proofs, calls) stay on the concrete types.
+pub trait Reconstructor: StateStore + Send + Sync {
+    fn mode(&self) -> ReconstructionMode;
+
+    fn apply_block(
+        &mut self,
+        block: &Block<Transaction>,
+        receipts: &[TransactionReceipt],
+    ) -> Result<(), Error>;
+
+    fn account(&self, address: H160) -> Option<&AccountState>;
+
+    /// Value of a storage slot, zero when unset.
+    fn storage_slot(&self, address: H160, slot: H256) -> H256 {
+        self.account(address)
+            .and_then(|account| account.storage.get(&slot).copied())
+            .unwrap_or_default()
+    }
+
+    /// Fields of `address` this mode could not derive; none for full re-execution.
+    fn unknown_fields(&self, _address: H160) -> UnknownFields {
+        UnknownFields::default()
+    }
+
+    /// Number and hash of the last applied block.
+    fn head(&self) -> (u64, Option<H256>);
+
+    /// Walks every account, so callers that poll it should do so sparingly.
+    fn state_size(&self) -> StateSize;
+
+    fn snapshot(&self) -> Result<StateSnapshot, Error>;
+}
+
+impl Reconstructor for evm_exec::StateReconstructor {
+    fn mode(&self) -> ReconstructionMode {
+        ReconstructionMode::Reexecution
+    }
+
+    fn apply_block(
+        &mut self,
+        block: &Block<Transaction>,
+        receipts: &[TransactionReceipt],
+    ) -> Result<(), Error> {
+        evm_exec::StateReconstructor::apply_block(self, block, receipts)
+            .map_err(|e| anyhow!(e.to_string()))
+    }
+
+    fn account(&self, address: H160) -> Option<&AccountState> {
+        self.get_account_state(address)
+    }
+
+    fn head(&self) -> (u64, Option<H256>) {
+        (self.block_number, self.head_hash)
+    }
+
+    fn state_size(&self) -> StateSize {
+        StateSize::of(self.accounts.values())
+    }
+
+    fn snapshot(&self) -> Result<StateSnapshot, Error> {
+        StateSnapshot::capture(self)
+    }
+}
+
+impl Reconstructor for state::StateReconstructor {
+    fn mode(&self) -> ReconstructionMode {
+        ReconstructionMode::ReceiptDriven
+    }
+
+    fn apply_block(
+        &mut self,
+        block: &Block<Transaction>,
+        receipts: &[TransactionReceipt],
+    ) -> Result<(), Error> {
+        state::StateReconstructor::apply_block(self, block, receipts)
+    }
+
+    fn account(&self, address: H160) -> Option<&AccountState> {
+        self.get_account_state(address)
+    }
+
+    fn unknown_fields(&self, address: H160) -> UnknownFields {
+        state::StateReconstructor::unknown_fields(self, address)
+    }
+
+    fn head(&self) -> (u64, Option<H256>) {
+        (self.block_number, self.head_hash)
+    }
+
+    fn state_size(&self) -> StateSize {
+        StateSize::of(self.accounts.values())
+    }
+
+    /// Carries no state root: an approximated state would not match the header's.
+    fn snapshot(&self) -> Result<StateSnapshot, Error> {
+        let alloc = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| !account.is_empty())
+            .map(|(address, account)| (*address, AccountAlloc::from(account)))
+            .collect();
+        let mut snapshot = StateSnapshot::from_alloc(self.block_number, &alloc);
+        snapshot.block_hash = self.head_hash.unwrap_or_default().0;
+        Ok(snapshot)
+    }
+}
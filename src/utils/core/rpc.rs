@@ -1,32 +1,225 @@
+use crate::utils::core::block_source::BlockWithReceipts;
 use crate::utils::core::evm_wvm_types::WvmTransactionReceipt;
+use anyhow::{anyhow, Error};
 use ethers::middleware::Middleware;
-use ethers::providers::{Http, Provider};
-use ethers::types::{Block, Transaction, TransactionReceipt};
-use std::sync::Arc;
+use ethers::providers::{Http, JsonRpcError, Provider, ProviderError, RpcError};
+use ethers::types::{Block, BlockNumber, Trace, Transaction, TransactionReceipt, H256};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use tracing::debug;
 
+/// Max in-flight `eth_getTransactionReceipt` calls when neither block receipts nor batching work.
+pub const RECEIPT_FETCH_CONCURRENCY: usize = 16;
+
+/// JSON-RPC error code for a method the node does not implement.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcResponse {
+    id: u64,
+    result: Option<Value>,
+    error: Option<Value>,
+}
+
+#[tracing::instrument(skip(provider))]
 pub async fn get_block_txs_receipts(
     provider: Provider<Http>,
     block_number: u64,
-) -> Result<(Block<Transaction>, Vec<TransactionReceipt>), Box<dyn std::error::Error>> {
-    let provider = Arc::new(provider);
-
+) -> Result<BlockWithReceipts, Error> {
     // fetch block with full transactions
-    let block: Option<Block<Transaction>> = provider.get_block_with_txs(block_number).await?;
-    if let Some(block) = block {
-        let mut receipts = vec![];
-
-        // fetch receipts for each transaction
-        for tx in &block.transactions {
-            if let Some(receipt) = provider.get_transaction_receipt(tx.hash).await? {
-                let wvm_receipt: WvmTransactionReceipt = receipt.into();
-                let receipt: TransactionReceipt = wvm_receipt.into();
-                receipts.push(receipt);
-            } else {
-                println!("Receipt not found for transaction: {:?}", tx.hash);
+    let block: Block<Transaction> = provider
+        .get_block_with_txs(block_number)
+        .await?
+        .ok_or_else(|| anyhow!("Block #{} not found", block_number))?;
+
+    // only fall back when the node lacks a method; a missing receipt stays missing either way
+    let receipts = match fetch_block_receipts(&provider, &block).await {
+        Err(e) if is_unsupported(&e) => {
+            debug!(error = %e, "eth_getBlockReceipts unsupported, falling back to a batch request");
+            match fetch_receipts_batch(&provider, &block).await {
+                Err(e) if is_unsupported(&e) => {
+                    debug!(error = %e, "batch requests unsupported, fetching receipts one by one");
+                    fetch_receipts_concurrent(&provider, &block).await?
+                }
+                result => result?,
             }
         }
-        Ok((block, receipts))
-    } else {
-        Err(format!("Block #{} not found", block_number).into())
+        result => result?,
+    };
+
+    // normalize through the archived receipt shape so every source yields the same fields
+    let receipts = receipts
+        .into_iter()
+        .map(|receipt| WvmTransactionReceipt::from(receipt).into())
+        .collect();
+
+    Ok((block, receipts))
+}
+
+/// Fetches every receipt of the block with a single `eth_getBlockReceipts` call.
+pub async fn fetch_block_receipts(
+    provider: &Provider<Http>,
+    block: &Block<Transaction>,
+) -> Result<Vec<TransactionReceipt>, Error> {
+    let number = block
+        .number
+        .ok_or_else(|| anyhow!("block is missing its number"))?;
+    let receipts = provider.get_block_receipts(number).await?;
+    order_receipts(block, receipts)
+}
+
+/// Parity-style call traces of every transaction in a block, from `trace_block`.
+pub async fn fetch_block_traces(
+    provider: &Provider<Http>,
+    block_number: u64,
+) -> Result<Vec<Trace>, Error> {
+    provider
+        .trace_block(BlockNumber::Number(block_number.into()))
+        .await
+        .map_err(|e| anyhow!("trace_block #{} failed: {}", block_number, e))
+}
+
+/// Fetches the receipts with one JSON-RPC batch request of `eth_getTransactionReceipt` calls.
+pub async fn fetch_receipts_batch(
+    provider: &Provider<Http>,
+    block: &Block<Transaction>,
+) -> Result<Vec<TransactionReceipt>, Error> {
+    if block.transactions.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let batch: Vec<Value> = block
+        .transactions
+        .iter()
+        .enumerate()
+        .map(|(id, tx)| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "eth_getTransactionReceipt",
+                "params": [tx.hash],
+            })
+        })
+        .collect();
+
+    let response: Value = reqwest::Client::new()
+        .post(provider.as_ref().url().clone())
+        .json(&batch)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    // servers without batch support may answer with a single error object
+    if let Some(error) = response.get("error") {
+        return Err(rpc_error(error));
+    }
+    let responses: Vec<JsonRpcResponse> = serde_json::from_value(response)?;
+
+    let mut receipts: Vec<Option<TransactionReceipt>> = vec![None; block.transactions.len()];
+    for response in responses {
+        if let Some(error) = response.error {
+            return Err(rpc_error(&error));
+        }
+        let slot = receipts
+            .get_mut(response.id as usize)
+            .ok_or_else(|| anyhow!("unexpected batch response id {}", response.id))?;
+        *slot = match response.result {
+            Some(Value::Null) | None => None,
+            Some(result) => Some(serde_json::from_value(result)?),
+        };
     }
+
+    collect_receipts(block, receipts)
+}
+
+/// Fetches the receipts one call per transaction, at most `RECEIPT_FETCH_CONCURRENCY` at a time.
+pub async fn fetch_receipts_concurrent(
+    provider: &Provider<Http>,
+    block: &Block<Transaction>,
+) -> Result<Vec<TransactionReceipt>, Error> {
+    let hashes: Vec<H256> = block.transactions.iter().map(|tx| tx.hash).collect();
+    let receipts: Vec<Option<TransactionReceipt>> = stream::iter(hashes)
+        .map(|hash| provider.get_transaction_receipt(hash))
+        .buffered(RECEIPT_FETCH_CONCURRENCY)
+        .try_collect()
+        .await?;
+
+    collect_receipts(block, receipts)
+}
+
+fn rpc_error(error: &Value) -> Error {
+    match serde_json::from_value::<JsonRpcError>(error.clone()) {
+        Ok(error) => error.into(),
+        Err(_) => anyhow!("batch request failed: {}", error),
+    }
+}
+
+/// Whether `error` means the node does not support a way of fetching receipts, as opposed
+/// to a failure the other ways would run into as well: only "method not found" errors and
+/// HTTP 404 or 405 count, so rate limits and malformed answers are returned to the caller.
+fn is_unsupported(error: &Error) -> bool {
+    if let Some(ProviderError::UnsupportedRPC) = error.downcast_ref() {
+        return true;
+    }
+    let response = error
+        .downcast_ref::<ProviderError>()
+        .and_then(|e| e.as_error_response())
+        .or_else(|| error.downcast_ref::<JsonRpcError>());
+    if let Some(response) = response {
+        return response.code == METHOD_NOT_FOUND
+            || response.message.to_lowercase().contains("method not found");
+    }
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .is_some_and(|status| {
+            status == StatusCode::NOT_FOUND || status == StatusCode::METHOD_NOT_ALLOWED
+        })
+}
+
+fn collect_receipts(
+    block: &Block<Transaction>,
+    receipts: Vec<Option<TransactionReceipt>>,
+) -> Result<Vec<TransactionReceipt>, Error> {
+    let missing: Vec<H256> = block
+        .transactions
+        .iter()
+        .zip(&receipts)
+        .filter(|(_, receipt)| receipt.is_none())
+        .map(|(tx, _)| tx.hash)
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Receipts not found for {} transaction(s) of block #{}: {:?}",
+            missing.len(),
+            block.number.unwrap_or_default(),
+            missing
+        ));
+    }
+
+    Ok(receipts.into_iter().flatten().collect())
+}
+
+/// Puts block receipts into transaction order, failing if any transaction has no receipt.
+fn order_receipts(
+    block: &Block<Transaction>,
+    receipts: Vec<TransactionReceipt>,
+) -> Result<Vec<TransactionReceipt>, Error> {
+    let mut by_hash: HashMap<H256, TransactionReceipt> = receipts
+        .into_iter()
+        .map(|receipt| (receipt.transaction_hash, receipt))
+        .collect();
+
+    let ordered = block
+        .transactions
+        .iter()
+        .map(|tx| by_hash.remove(&tx.hash))
+        .collect();
+
+    collect_receipts(block, ordered)
 }
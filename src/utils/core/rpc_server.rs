@@ -0,0 +1,352 @@
+use crate::utils::core::evm_exec::{AccountState, CallRequest, StateReconstructor};
+use crate::utils::core::tracers::Tracer;
+use anyhow::Error;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use ethers::types::{Address, Bytes, H256, U256, U64};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::info;
+
+/// Reconstructed state shared between the server and whoever keeps updating it.
+pub type SharedState = Arc<RwLock<StateReconstructor>>;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// `eth_call` reverted; the revert data is in the error's `data`.
+pub const EXECUTION_REVERTED: i64 = 3;
+/// Returned for well-formed queries the reconstructed state cannot answer.
+pub const SERVER_ERROR: i64 = -32000;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Most requests accepted in one batch.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct JsonRpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonRpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<JsonRpcError>,
+}
+
+impl JsonRpcResponse {
+    fn new(id: Value, outcome: Result<Value, JsonRpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+/// Axum router answering JSON-RPC requests, single or batched, on `/`.
+pub fn router(state: SharedState) -> Router {
+    Router::new()
+        .route("/", post(handle_request))
+        .with_state(state)
+}
+
+/// Binds `addr` and serves the state in the background, returning the bound address.
+pub async fn spawn_server(
+    state: SharedState,
+    addr: SocketAddr,
+) -> Result<(SocketAddr, JoinHandle<Result<(), Error>>), Error> {
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let handle = tokio::spawn(async move {
+        axum::serve(listener, router(state)).await?;
+        Ok(())
+    });
+    Ok((local_addr, handle))
+}
+
+/// Serves the state on `addr` until the server fails.
+pub async fn serve(state: SharedState, addr: SocketAddr) -> Result<(), Error> {
+    let (local_addr, handle) = spawn_server(state, addr).await?;
+    info!(address = %local_addr, "JSON-RPC server listening");
+    handle.await?
+}
+
+async fn handle_request(State(state): State<SharedState>, body: axum::body::Bytes) -> Json<Value> {
+    let body: Value = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return Json(error_response(PARSE_ERROR, e)),
+    };
+    match &body {
+        Value::Array(batch) if batch.is_empty() => {
+            return Json(error_response(INVALID_REQUEST, "empty batch"))
+        }
+        Value::Array(batch) if batch.len() > MAX_BATCH_SIZE => {
+            return Json(error_response(
+                INVALID_REQUEST,
+                format!(
+                    "batch of {} requests exceeds the limit of {}",
+                    batch.len(),
+                    MAX_BATCH_SIZE
+                ),
+            ))
+        }
+        _ => {}
+    }
+
+    // execution is synchronous and can be long; keep it off the async workers, and take the
+    // lock per request so that writers are not held off for a whole batch
+    let response = tokio::task::spawn_blocking(move || match body {
+        Value::Array(batch) => Value::Array(
+            batch
+                .into_iter()
+                .map(|request| respond(&state.blocking_read(), request))
+                .collect(),
+        ),
+        request => respond(&state.blocking_read(), request),
+    })
+    .await
+    .unwrap_or_else(|e| error_response(INTERNAL_ERROR, e));
+    Json(response)
+}
+
+/// A response without an id, for bodies that could not be read as requests.
+fn error_response(code: i64, error: impl ToString) -> Value {
+    let response =
+        JsonRpcResponse::new(Value::Null, Err(JsonRpcError::new(code, error.to_string())));
+    serde_json::to_value(response).unwrap_or_default()
+}
+
+fn respond(state: &StateReconstructor, request: Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let response = match serde_json::from_value::<JsonRpcRequest>(request) {
+        Ok(request) => JsonRpcResponse::new(
+            request.id,
+            dispatch(state, &request.method, &request.params),
+        ),
+        Err(e) => JsonRpcResponse::new(id, Err(JsonRpcError::new(INVALID_REQUEST, e.to_string()))),
+    };
+    serde_json::to_value(response).unwrap_or_default()
+}
+
+/// Answers a single call against the reconstructed state.
+pub fn dispatch(
+    state: &StateReconstructor,
+    method: &str,
+    params: &[Value],
+) -> Result<Value, JsonRpcError> {
+    match method {
+        "eth_chainId" => to_result(U64::from(state.env.cfg.chain_id)),
+        "eth_blockNumber" => to_result(U64::from(state.block_number)),
+        "eth_getBalance" => {
+            let address: Address = param(params, 0)?;
+            let block = resolve_block(state, params.get(1))?;
+            to_result(
+                account_field(state, address, block, |account| account.balance)?
+                    .unwrap_or_default(),
+            )
+        }
+        "eth_getTransactionCount" => {
+            let address: Address = param(params, 0)?;
+            let block = resolve_block(state, params.get(1))?;
+            to_result(
+                account_field(state, address, block, |account| account.nonce)?.unwrap_or_default(),
+            )
+        }
+        "eth_getCode" => {
+            let address: Address = param(params, 0)?;
+            let block = resolve_block(state, params.get(1))?;
+            to_result(
+                account_field(state, address, block, |account| Bytes::from(account.code))?
+                    .unwrap_or_default(),
+            )
+        }
+        "eth_getStorageAt" => {
+            let address: Address = param(params, 0)?;
+            let slot: U256 = param(params, 1)?;
+            let block = resolve_block(state, params.get(2))?;
+            let mut key = [0u8; 32];
+            slot.to_big_endian(&mut key);
+            let value = state
+                .storage_at(address, H256::from(key), block)
+                .map_err(server_error)?;
+            to_result(value)
+        }
+        "eth_getProof" => {
+            let address: Address = param(params, 0)?;
+            let slots: Vec<U256> = param(params, 1)?;
+            let block = resolve_block(state, params.get(2))?;
+            if block != state.block_number {
+                return Err(JsonRpcError::new(
+                    SERVER_ERROR,
+                    format!(
+                        "proofs are only available at the head block #{}",
+                        state.block_number
+                    ),
+                ));
+            }
+            let slots: Vec<H256> = slots
+                .into_iter()
+                .map(|slot| {
+                    let mut key = [0u8; 32];
+                    slot.to_big_endian(&mut key);
+                    H256::from(key)
+                })
+                .collect();
+            to_result(state.get_proof(address, &slots))
+        }
+        "eth_call" => {
+            let request: CallRequest = param(params, 0)?;
+            let block = resolve_block(state, params.get(1))?;
+            let outcome = state.call_at(&request, block).map_err(server_error)?;
+            match (outcome.success, outcome.halt_reason) {
+                (true, _) => to_result(outcome.output),
+                (false, None) => Err(JsonRpcError {
+                    data: Some(json!(outcome.output)),
+                    ..JsonRpcError::new(EXECUTION_REVERTED, "execution reverted")
+                }),
+                (false, Some(reason)) => Err(JsonRpcError::new(
+                    SERVER_ERROR,
+                    format!("execution halted: {}", reason),
+                )),
+            }
+        }
+        "eth_estimateGas" => {
+            let request: CallRequest = param(params, 0)?;
+            let block = resolve_block(state, params.get(1))?;
+            let gas = state
+                .estimate_gas_at(&request, block)
+                .map_err(server_error)?;
+            to_result(U64::from(gas))
+        }
+        "debug_traceTransaction" => {
+            let hash: H256 = param(params, 0)?;
+            let tracer = tracer(params.get(1))?;
+            let trace = state
+                .trace_transaction(hash, &tracer)
+                .map_err(server_error)?;
+            to_result(trace)
+        }
+        "debug_traceBlockByNumber" => {
+            let block = resolve_block(state, params.first())?;
+            let tracer = tracer(params.get(1))?;
+            let traces = state
+                .trace_block_by_number(block, &tracer)
+                .map_err(server_error)?;
+            to_result(
+                traces
+                    .into_iter()
+                    .map(|(tx_hash, result)| json!({ "txHash": tx_hash, "result": result }))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        _ => Err(JsonRpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method {} is not supported", method),
+        )),
+    }
+}
+
+fn account_field<T>(
+    state: &StateReconstructor,
+    address: Address,
+    block: u64,
+    field: impl FnOnce(AccountState) -> T,
+) -> Result<Option<T>, JsonRpcError> {
+    Ok(state
+        .account_state_at(address, block)
+        .map_err(server_error)?
+        .map(field))
+}
+
+/// Resolves a block tag or number to a block number; availability is checked by the state.
+fn resolve_block(state: &StateReconstructor, block: Option<&Value>) -> Result<u64, JsonRpcError> {
+    let tag = match block {
+        None | Some(Value::Null) => return Ok(state.block_number),
+        Some(Value::String(tag)) => tag.as_str(),
+        Some(other) => {
+            return Err(JsonRpcError::new(
+                INVALID_PARAMS,
+                format!("invalid block parameter {}", other),
+            ))
+        }
+    };
+
+    match tag {
+        "latest" | "pending" | "safe" | "finalized" => Ok(state.block_number),
+        "earliest" => Ok(state
+            .history
+            .as_ref()
+            .map(|history| history.earliest)
+            .unwrap_or_default()),
+        number => serde_json::from_value::<U64>(Value::String(number.to_string()))
+            .map(|number| number.as_u64())
+            .map_err(|_| {
+                JsonRpcError::new(INVALID_PARAMS, format!("invalid block parameter {}", tag))
+            }),
+    }
+}
+
+fn tracer(options: Option<&Value>) -> Result<Tracer, JsonRpcError> {
+    Tracer::from_options(options.unwrap_or(&Value::Null))
+        .map_err(|e| JsonRpcError::new(INVALID_PARAMS, format!("invalid tracer options: {}", e)))
+}
+
+fn server_error(error: impl ToString) -> JsonRpcError {
+    JsonRpcError::new(SERVER_ERROR, error.to_string())
+}
+
+fn param<T: DeserializeOwned>(params: &[Value], index: usize) -> Result<T, JsonRpcError> {
+    let value = params
+        .get(index)
+        .ok_or_else(|| JsonRpcError::new(INVALID_PARAMS, format!("missing parameter {}", index)))?;
+    serde_json::from_value(value.clone()).map_err(|e| {
+        JsonRpcError::new(
+            INVALID_PARAMS,
+            format!("invalid parameter {}: {}", index, e),
+        )
+    })
+}
+
+fn to_result<T: Serialize>(value: T) -> Result<Value, JsonRpcError> {
+    Ok(json!(value))
+}
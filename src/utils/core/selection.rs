@@ -0,0 +1,306 @@
+use crate::utils::core::hooks::ReplayInspector;
+use ethers::types::{Block, Transaction, TransactionReceipt, H160};
+use revm::db::InMemoryDB;
+use revm::interpreter::opcode::{EXTCODECOPY, EXTCODEHASH, EXTCODESIZE};
+use revm::interpreter::{
+    CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, EOFCreateInputs, Interpreter,
+};
+use revm::primitives::{Address, Log, B256, U256};
+use revm::{EvmContext, Inspector};
+use std::collections::{BTreeSet, HashSet};
+
+/// The addresses a selective reconstruction keeps, set with `StateReconstructor::with_selection`.
+///
+/// Every transaction is still executed against the full state, but only the selected
+/// addresses and their dependencies are kept in `accounts`, and so in snapshots, exports and
+/// the JSON-RPC server. A dependency is a contract whose code a selected contract calls,
+/// delegates to or reads with `EXTCODESIZE`, `EXTCODECOPY` or `EXTCODEHASH`, found while
+/// replaying; dependencies are selected in turn.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    addresses: HashSet<H160>,
+    dependencies: BTreeSet<H160>,
+    /// Dependencies found by the transaction being executed, not yet synced.
+    pending: Vec<H160>,
+    /// Skip executing blocks that touch neither a selected address nor any account the
+    /// executed state knows.
+    pub skip_untouched_blocks: bool,
+    /// Blocks skipped so far.
+    pub skipped_blocks: u64,
+    /// Accounts of skipped blocks, whose state in the db is stale.
+    stale: HashSet<H160>,
+}
+
+impl Selection {
+    pub fn new(addresses: impl IntoIterator<Item = H160>) -> Self {
+        Self {
+            addresses: addresses.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Skips blocks whose senders, recipients, created contracts, log emitters and paid
+    /// coinbase are neither selected nor known to the db, i.e. never read or written by an
+    /// executed block. Their accounts go stale, and replaying a later transaction that loads
+    /// one of them fails instead of running on wrong nonces or balances.
+    ///
+    /// Internal calls of skipped transactions are not seen: a known contract they reach
+    /// without emitting a log misses those changes.
+    pub fn skip_untouched_blocks(mut self) -> Self {
+        self.skip_untouched_blocks = true;
+        self
+    }
+
+    /// Whether `address` is selected or a dependency of a selected address.
+    pub fn contains(&self, address: H160) -> bool {
+        self.addresses.contains(&address) || self.dependencies.contains(&address)
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &H160> {
+        self.addresses.iter()
+    }
+
+    pub fn dependencies(&self) -> impl Iterator<Item = &H160> {
+        self.dependencies.iter()
+    }
+
+    /// Whether `block` can be skipped: none of its accounts is selected or in `db`.
+    pub fn can_skip(
+        &self,
+        block: &Block<Transaction>,
+        receipts: &[TransactionReceipt],
+        db: &InMemoryDB,
+    ) -> bool {
+        block_accounts(block, receipts).all(|address| {
+            !self.contains(address) && !db.accounts.contains_key(&Address::from(address.0))
+        })
+    }
+
+    /// Marks the accounts of a skipped block as stale.
+    pub(crate) fn skip(&mut self, block: &Block<Transaction>, receipts: &[TransactionReceipt]) {
+        self.stale.extend(block_accounts(block, receipts));
+        self.skipped_blocks += 1;
+    }
+
+    /// Whether `address` was touched by a skipped block.
+    pub fn is_stale(&self, address: H160) -> bool {
+        self.stale.contains(&address)
+    }
+
+    /// Dependencies found since the last call.
+    pub(crate) fn take_pending(&mut self) -> Vec<H160> {
+        std::mem::take(&mut self.pending)
+    }
+
+    fn add_dependency(&mut self, address: H160) {
+        if !self.contains(address) {
+            self.dependencies.insert(address);
+            self.pending.push(address);
+        }
+    }
+}
+
+/// The accounts a block names: senders, recipients, created contracts and log emitters, and
+/// the coinbase when a transaction pays more than the base fee.
+fn block_accounts<'a>(
+    block: &'a Block<Transaction>,
+    receipts: &'a [TransactionReceipt],
+) -> impl Iterator<Item = H160> + 'a {
+    let base_fee = block.base_fee_per_gas.unwrap_or_default();
+    let coinbase = block
+        .author
+        .filter(|_| {
+            block
+                .transactions
+                .iter()
+                .any(|tx| tx.gas_price.unwrap_or_default() > base_fee)
+        })
+        .into_iter();
+    let transactions = block
+        .transactions
+        .iter()
+        .flat_map(|tx| std::iter::once(tx.from).chain(tx.to));
+    let receipts = receipts.iter().flat_map(|receipt| {
+        receipt
+            .contract_address
+            .into_iter()
+            .chain(receipt.logs.iter().map(|log| log.address))
+    });
+    coinbase.chain(transactions).chain(receipts)
+}
+
+/// Finds the dependencies of selected contracts while running the user's inspector, if any.
+pub(crate) struct DependencyTracker<'a> {
+    selection: &'a mut Selection,
+    inspector: Option<&'a mut (dyn ReplayInspector + 'static)>,
+    /// Account whose code the current opcode reads, checked once the opcode has loaded it.
+    code_read: Option<Address>,
+}
+
+impl<'a> DependencyTracker<'a> {
+    pub fn new(
+        selection: &'a mut Selection,
+        inspector: Option<&'a mut (dyn ReplayInspector + 'static)>,
+    ) -> Self {
+        Self {
+            selection,
+            inspector,
+            code_read: None,
+        }
+    }
+}
+
+impl<'db> Inspector<&'db mut InMemoryDB> for DependencyTracker<'_> {
+    fn initialize_interp(
+        &mut self,
+        interp: &mut Interpreter,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+    ) {
+        if let Some(inspector) = self.inspector.as_deref_mut() {
+            inspector.initialize_interp(interp, context);
+        }
+    }
+
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<&'db mut InMemoryDB>) {
+        if matches!(
+            interp.current_opcode(),
+            EXTCODESIZE | EXTCODECOPY | EXTCODEHASH
+        ) && self
+            .selection
+            .contains(to_h160(interp.contract.target_address))
+        {
+            self.code_read = interp
+                .stack
+                .peek(0)
+                .ok()
+                .map(|word| Address::from_word(B256::from(word.to_be_bytes::<32>())));
+        }
+        if let Some(inspector) = self.inspector.as_deref_mut() {
+            inspector.step(interp, context);
+        }
+    }
+
+    fn step_end(
+        &mut self,
+        interp: &mut Interpreter,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+    ) {
+        if let Some(address) = self.code_read.take() {
+            if has_code(context, address) {
+                self.selection.add_dependency(to_h160(address));
+            }
+        }
+        if let Some(inspector) = self.inspector.as_deref_mut() {
+            inspector.step_end(interp, context);
+        }
+    }
+
+    fn log(
+        &mut self,
+        interp: &mut Interpreter,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        log: &Log,
+    ) {
+        if let Some(inspector) = self.inspector.as_deref_mut() {
+            inspector.log(interp, context, log);
+        }
+    }
+
+    fn call(
+        &mut self,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        self.inspector
+            .as_deref_mut()
+            .and_then(|inspector| inspector.call(context, inputs))
+    }
+
+    fn call_end(
+        &mut self,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        // delegated code runs in the calling contract, which revm reports as the target
+        let caller = match inputs.scheme {
+            CallScheme::DelegateCall | CallScheme::ExtDelegateCall | CallScheme::CallCode => {
+                inputs.target_address
+            }
+            _ => inputs.caller,
+        };
+        let callee = inputs.bytecode_address;
+        if has_code(context, callee) && self.selection.contains(to_h160(caller)) {
+            self.selection.add_dependency(to_h160(callee));
+        }
+
+        match self.inspector.as_deref_mut() {
+            Some(inspector) => inspector.call_end(context, inputs, outcome),
+            None => outcome,
+        }
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.inspector
+            .as_deref_mut()
+            .and_then(|inspector| inspector.create(context, inputs))
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        match self.inspector.as_deref_mut() {
+            Some(inspector) => inspector.create_end(context, inputs, outcome),
+            None => outcome,
+        }
+    }
+
+    fn eofcreate(
+        &mut self,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        inputs: &mut EOFCreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.inspector
+            .as_deref_mut()
+            .and_then(|inspector| inspector.eofcreate(context, inputs))
+    }
+
+    fn eofcreate_end(
+        &mut self,
+        context: &mut EvmContext<&'db mut InMemoryDB>,
+        inputs: &EOFCreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        match self.inspector.as_deref_mut() {
+            Some(inspector) => inspector.eofcreate_end(context, inputs, outcome),
+            None => outcome,
+        }
+    }
+
+    fn selfdestruct(&mut self, contract: Address, target: Address, value: U256) {
+        if let Some(inspector) = self.inspector.as_deref_mut() {
+            Inspector::<&'db mut InMemoryDB>::selfdestruct(inspector, contract, target, value);
+        }
+    }
+}
+
+/// Whether `address` has code, as loaded by the transaction so far.
+fn has_code(context: &EvmContext<&mut InMemoryDB>, address: Address) -> bool {
+    context
+        .journaled_state
+        .state
+        .get(&address)
+        .and_then(|account| account.info.code.as_ref())
+        .is_some_and(|code| !code.is_empty())
+}
+
+fn to_h160(address: Address) -> H160 {
+    H160(address.0 .0)
+}
@@ -1,4 +1,4 @@
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serializer};
 
 pub fn serialize_256<S>(bytes: &Option<[u8; 256]>, serializer: S) -> Result<S::Ok, S::Error>
 where
@@ -0,0 +1,484 @@
+use crate::utils::core::evm_exec::StateReconstructor;
+use crate::utils::core::genesis_load::{AccountAlloc, Genesis};
+use crate::utils::core::state_iter::{
+    AccountEntry, AccountIter, KeyRange, StateStore, StorageIter,
+};
+use crate::utils::core::wvm_calldata::{brotli_compress, brotli_decompress};
+use anyhow::{anyhow, Error};
+use borsh::{BorshDeserialize as _, BorshSerialize as _};
+use borsh_derive::{BorshDeserialize, BorshSerialize};
+use ethers::types::{H160, H256, U256};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+/// Snapshot file layout: `MAGIC | header | chunk*`, with accounts by ascending address. The
+/// header is `borsh((block_number, block_hash, state_root, account_count))`; each chunk is
+/// `borsh((first_address, account_count, length))` followed by `length` bytes of
+/// `brotli(borsh(account)*)`, so that `SnapshotReader` decompresses only the chunks it needs.
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"WVMSNAP2";
+
+/// Snapshots written before accounts were chunked: `MAGIC | brotli(borsh(StateSnapshot))`.
+const SNAPSHOT_MAGIC_V1: &[u8; 8] = b"WVMSNAP1";
+
+/// Uncompressed size at which a chunk of accounts is closed.
+pub const SNAPSHOT_CHUNK_SIZE: usize = 1 << 18;
+
+type SnapshotHeader = (u64, [u8; 32], [u8; 32], u32);
+type ChunkHeader = ([u8; 20], u32, u32);
+
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct SnapshotAccount {
+    pub address: [u8; 20],
+    pub nonce: u64,
+    pub balance: [u8; 32],
+    pub code: Vec<u8>,
+    pub storage: Vec<([u8; 32], [u8; 32])>,
+}
+
+/// Full state at the end of one block.
+#[derive(Debug, Clone, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct StateSnapshot {
+    pub block_number: u64,
+    /// Zero when unknown, e.g. for snapshots made from an alloc file.
+    pub block_hash: [u8; 32],
+    /// Zero when unknown; otherwise checked against the imported state.
+    pub state_root: [u8; 32],
+    pub accounts: Vec<SnapshotAccount>,
+}
+
+impl StateSnapshot {
+    /// Snapshot of the head state of `state`; a selective one records no state root.
+    pub fn capture(state: &StateReconstructor) -> Result<Self, Error> {
+        let alloc = state.alloc_at(state.block_number)?;
+        let mut snapshot = Self::from_alloc(state.block_number, &alloc);
+        snapshot.block_hash = state.head_hash.unwrap_or_default().0;
+        snapshot.state_root = state.snapshot_root().unwrap_or_default().0;
+        Ok(snapshot)
+    }
+
+    /// Snapshot of an alloc at `block_number`; hash and root are filled in on import.
+    pub fn from_alloc(block_number: u64, alloc: &BTreeMap<H160, AccountAlloc>) -> Self {
+        let accounts = alloc
+            .iter()
+            .map(|(address, account)| {
+                let mut balance = [0u8; 32];
+                account.balance_value().to_big_endian(&mut balance);
+                SnapshotAccount {
+                    address: address.0,
+                    nonce: account.nonce_value(),
+                    balance,
+                    code: hex::decode(account.code.trim_start_matches("0x")).unwrap_or_default(),
+                    storage: account
+                        .storage
+                        .iter()
+                        .map(|(slot, value)| (slot.0, value.0))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Self {
+            block_number,
+            block_hash: [0u8; 32],
+            state_root: [0u8; 32],
+            accounts,
+        }
+    }
+
+    pub fn alloc(&self) -> BTreeMap<H160, AccountAlloc> {
+        self.accounts
+            .iter()
+            .map(|account| {
+                let alloc = AccountAlloc {
+                    balance: U256::from_big_endian(&account.balance).to_string(),
+                    code: if account.code.is_empty() {
+                        String::new()
+                    } else {
+                        format!("0x{}", hex::encode(&account.code))
+                    },
+                    storage: account
+                        .storage
+                        .iter()
+                        .map(|(slot, value)| (H256::from(*slot), H256::from(*value)))
+                        .collect(),
+                    nonce: format!("{:#x}", account.nonce),
+                };
+                (H160::from(account.address), alloc)
+            })
+            .collect()
+    }
+
+    /// `template` with its alloc replaced by this snapshot's accounts.
+    pub fn genesis(&self, template: &Genesis) -> Genesis {
+        Genesis {
+            alloc: self.alloc(),
+            ..template.clone()
+        }
+    }
+
+    pub fn block_hash(&self) -> Option<H256> {
+        Some(H256::from(self.block_hash)).filter(|hash| !hash.is_zero())
+    }
+
+    pub fn state_root(&self) -> Option<H256> {
+        Some(H256::from(self.state_root)).filter(|root| !root.is_zero())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let header = (
+            self.block_number,
+            self.block_hash,
+            self.state_root,
+            u32::try_from(self.accounts.len())?,
+        );
+        let mut writer = ChunkWriter::new(BufWriter::new(File::create(path)?), header)?;
+        for account in &self.accounts {
+            writer.push(account)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Reads a binary snapshot, or a genesis / bare alloc JSON file for which
+    /// `block_number` must be given.
+    pub fn load(path: impl AsRef<Path>, block_number: Option<u64>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+
+        let snapshot = if let Some(payload) = bytes.strip_prefix(SNAPSHOT_MAGIC.as_slice()) {
+            Self::decode(payload)?
+        } else if let Some(payload) = bytes.strip_prefix(SNAPSHOT_MAGIC_V1.as_slice()) {
+            borsh::from_slice(&brotli_decompress(payload)?)?
+        } else {
+            let block_number = block_number.ok_or_else(|| {
+                anyhow!(
+                    "{} is an alloc file, which needs the block number it was taken at",
+                    path.display()
+                )
+            })?;
+            let mut json: Value = serde_json::from_slice(&bytes)?;
+            let alloc = match json.get_mut("alloc") {
+                Some(alloc) => alloc.take(),
+                None => json,
+            };
+            return Ok(Self::from_alloc(
+                block_number,
+                &serde_json::from_value(alloc)?,
+            ));
+        };
+
+        if let Some(expected) = block_number.filter(|n| *n != snapshot.block_number) {
+            return Err(anyhow!(
+                "snapshot {} is at block #{}, not #{}",
+                path.display(),
+                snapshot.block_number,
+                expected
+            ));
+        }
+        Ok(snapshot)
+    }
+
+    fn decode(mut payload: &[u8]) -> Result<Self, Error> {
+        let (block_number, block_hash, state_root, count) =
+            SnapshotHeader::deserialize(&mut payload)?;
+        let mut accounts = Vec::with_capacity(count as usize);
+        while accounts.len() < count as usize {
+            let (_, chunk_accounts, length) = ChunkHeader::deserialize(&mut payload)?;
+            let (compressed, rest) = payload
+                .split_at_checked(length as usize)
+                .ok_or_else(|| anyhow!("snapshot ends in the middle of a chunk"))?;
+            accounts.extend(decode_chunk(compressed, chunk_accounts)?);
+            payload = rest;
+        }
+        Ok(Self {
+            block_number,
+            block_hash,
+            state_root,
+            accounts,
+        })
+    }
+}
+
+/// Groups accounts into chunks of about `SNAPSHOT_CHUNK_SIZE` bytes and writes them after the
+/// snapshot header; accounts must come by ascending address.
+struct ChunkWriter<W: Write> {
+    out: W,
+    chunk: Vec<u8>,
+    first: Option<[u8; 20]>,
+    accounts: u32,
+    written: u32,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    fn new(mut out: W, header: SnapshotHeader) -> Result<Self, Error> {
+        out.write_all(SNAPSHOT_MAGIC)?;
+        header.serialize(&mut out)?;
+        Ok(Self {
+            out,
+            chunk: Vec::new(),
+            first: None,
+            accounts: 0,
+            written: 0,
+        })
+    }
+
+    fn push(&mut self, account: &SnapshotAccount) -> Result<(), Error> {
+        self.first.get_or_insert(account.address);
+        account.serialize(&mut self.chunk)?;
+        self.accounts += 1;
+        if self.chunk.len() >= SNAPSHOT_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn write_chunk(&mut self) -> Result<(), Error> {
+        let Some(first) = self.first.take() else {
+            return Ok(());
+        };
+        let compressed = brotli_compress(&std::mem::take(&mut self.chunk));
+        (first, self.accounts, u32::try_from(compressed.len())?).serialize(&mut self.out)?;
+        self.out.write_all(&compressed)?;
+        self.written += std::mem::take(&mut self.accounts);
+        Ok(())
+    }
+
+    /// Writes the last chunk and returns the number of accounts written.
+    fn finish(mut self) -> Result<u32, Error> {
+        self.write_chunk()?;
+        self.out.flush()?;
+        Ok(self.written)
+    }
+}
+
+fn decode_chunk(compressed: &[u8], accounts: u32) -> Result<Vec<SnapshotAccount>, Error> {
+    if accounts == 0 {
+        return Err(anyhow!("snapshot has an empty chunk"));
+    }
+    let data = brotli_decompress(compressed)?;
+    let mut cursor = data.as_slice();
+    (0..accounts)
+        .map(|_| Ok(SnapshotAccount::deserialize(&mut cursor)?))
+        .collect()
+}
+
+/// Writes the state of `store` as a snapshot file one account at a time, leaving out empty
+/// accounts. Returns the number of accounts written.
+pub fn write_snapshot<S: StateStore + ?Sized>(
+    store: &S,
+    block_number: u64,
+    block_hash: Option<H256>,
+    state_root: Option<H256>,
+    path: impl AsRef<Path>,
+) -> Result<u32, Error> {
+    // the header holds the account count, so count before writing
+    let mut count = 0u32;
+    for account in store.accounts(KeyRange::all()) {
+        if !is_empty(store, &account?)? {
+            count += 1;
+        }
+    }
+
+    let header = (
+        block_number,
+        block_hash.unwrap_or_default().0,
+        state_root.unwrap_or_default().0,
+        count,
+    );
+    let mut writer = ChunkWriter::new(BufWriter::new(File::create(path)?), header)?;
+    for account in store.accounts(KeyRange::all()) {
+        let account = account?;
+        let storage: Vec<_> = store
+            .storage(account.address, KeyRange::all())
+            .map(|slot| slot.map(|(slot, value)| (slot.0, value.0)))
+            .collect::<Result<_, _>>()?;
+        if account.has_no_info() && storage.is_empty() {
+            continue;
+        }
+        let nonce = u64::try_from(account.nonce).map_err(|_| {
+            anyhow!(
+                "nonce {} of {:?} does not fit in a snapshot",
+                account.nonce,
+                account.address
+            )
+        })?;
+        let mut balance = [0u8; 32];
+        account.balance.to_big_endian(&mut balance);
+        writer.push(&SnapshotAccount {
+            address: account.address.0,
+            nonce,
+            balance,
+            code: account.code.to_vec(),
+            storage,
+        })?;
+    }
+    if writer.finish()? != count {
+        return Err(anyhow!("state changed while writing the snapshot"));
+    }
+    Ok(count)
+}
+
+/// Only accounts without nonce, balance or code need their storage looked at.
+fn is_empty<S: StateStore + ?Sized>(store: &S, account: &AccountEntry) -> Result<bool, Error> {
+    Ok(account.has_no_info()
+        && store
+            .storage(account.address, KeyRange::all())
+            .next()
+            .transpose()?
+            .is_none())
+}
+
+/// A binary snapshot file read in place: opening it reads only the chunk headers, and each
+/// query decompresses the chunks it needs one at a time.
+#[derive(Debug, Clone)]
+pub struct SnapshotReader {
+    pub path: PathBuf,
+    pub block_number: u64,
+    pub block_hash: Option<H256>,
+    pub state_root: Option<H256>,
+    pub account_count: u32,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Clone)]
+struct Chunk {
+    first: H160,
+    /// Where the compressed accounts start in the file.
+    offset: u64,
+    length: u32,
+    accounts: u32,
+}
+
+impl SnapshotReader {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, Error> {
+        let path = path.into();
+        let mut file = BufReader::new(File::open(&path)?);
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)?;
+        if &magic == SNAPSHOT_MAGIC_V1 {
+            return Err(anyhow!(
+                "{} uses the old unchunked format; load and save it again to read it in place",
+                path.display()
+            ));
+        }
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(anyhow!("{} is not a binary snapshot", path.display()));
+        }
+
+        let (block_number, block_hash, state_root, account_count) =
+            SnapshotHeader::deserialize_reader(&mut file)?;
+        let mut chunks = Vec::new();
+        let mut indexed = 0u32;
+        while indexed < account_count {
+            let (first, accounts, length) = ChunkHeader::deserialize_reader(&mut file)?;
+            if accounts == 0 {
+                return Err(anyhow!("{} has an empty chunk", path.display()));
+            }
+            chunks.push(Chunk {
+                first: H160::from(first),
+                offset: file.stream_position()?,
+                length,
+                accounts,
+            });
+            file.seek_relative(length.into())?;
+            indexed = indexed.saturating_add(accounts);
+        }
+
+        Ok(Self {
+            path,
+            block_number,
+            block_hash: Some(H256::from(block_hash)).filter(|hash| !hash.is_zero()),
+            state_root: Some(H256::from(state_root)).filter(|root| !root.is_zero()),
+            account_count,
+            chunks,
+        })
+    }
+
+    /// Every account in the file, storage included.
+    pub fn snapshot_accounts(&self) -> impl Iterator<Item = Result<SnapshotAccount, Error>> + '_ {
+        self.accounts_from(0)
+    }
+
+    /// The accounts of the chunks from `first_chunk` on, decompressing one chunk at a time.
+    fn accounts_from(
+        &self,
+        first_chunk: usize,
+    ) -> impl Iterator<Item = Result<SnapshotAccount, Error>> + '_ {
+        self.chunks[first_chunk.min(self.chunks.len())..]
+            .iter()
+            .flat_map(|chunk| match self.read_chunk(chunk) {
+                Ok(accounts) => accounts.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            })
+    }
+
+    /// Index of the only chunk that can hold `address`.
+    fn chunk_for(&self, address: &H160) -> usize {
+        self.chunks
+            .partition_point(|chunk| chunk.first <= *address)
+            .saturating_sub(1)
+    }
+
+    fn read_chunk(&self, chunk: &Chunk) -> Result<Vec<SnapshotAccount>, Error> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(chunk.offset))?;
+        let mut compressed = vec![0u8; chunk.length as usize];
+        file.read_exact(&mut compressed)?;
+        decode_chunk(&compressed, chunk.accounts)
+    }
+}
+
+impl StateStore for SnapshotReader {
+    fn accounts(&self, range: KeyRange<H160>) -> AccountIter<'_> {
+        let first_chunk = match &range.start {
+            Bound::Included(start) | Bound::Excluded(start) => self.chunk_for(start),
+            Bound::Unbounded => 0,
+        };
+        Box::new(
+            self.accounts_from(first_chunk)
+                .take_while({
+                    let range = range.clone();
+                    move |account| {
+                        !matches!(account, Ok(account) if range.is_past(&H160::from(account.address)))
+                    }
+                })
+                .filter(move |account| {
+                    account
+                        .as_ref()
+                        .map_or(true, |account| range.contains(&H160::from(account.address)))
+                })
+                .map(|account| {
+                    account.map(|account| AccountEntry {
+                        address: H160::from(account.address),
+                        nonce: account.nonce.into(),
+                        balance: U256::from_big_endian(&account.balance),
+                        code: account.code.into(),
+                    })
+                }),
+        )
+    }
+
+    fn storage(&self, address: H160, range: KeyRange<H256>) -> StorageIter<'_> {
+        let Some(chunk) = self.chunks.get(self.chunk_for(&address)) else {
+            return Box::new(std::iter::empty());
+        };
+        match self.read_chunk(chunk) {
+            Ok(accounts) => Box::new(
+                accounts
+                    .into_iter()
+                    .find(|account| H160::from(account.address) == address)
+                    .map(|account| account.storage)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(slot, value)| (H256::from(slot), H256::from(value)))
+                    .filter(move |(slot, value)| !value.is_zero() && range.contains(slot))
+                    .map(Ok),
+            ),
+            Err(e) => Box::new(std::iter::once(Err(e))),
+        }
+    }
+}
@@ -1,20 +1,33 @@
+pub use crate::utils::core::evm_exec::AccountState;
 use crate::utils::core::genesis_load::Genesis;
-use ethereum_types::{H256, U256};
-use ethers::types::{Block, Transaction, TransactionReceipt};
+use crate::utils::core::reconstructor::UnknownFields;
+use anyhow::{anyhow, Error};
+use ethereum_types::{H160, H256, U256};
+use ethers::types::{Action, Block, CallType, Res, Trace, Transaction, TransactionReceipt};
 use std::collections::HashMap;
 
-#[derive(Debug, Clone)]
-pub struct AccountState {
-    pub nonce: U256,
-    pub balance: U256,
-    pub storage: HashMap<H256, H256>,
-    pub code: Vec<u8>,
-}
+/// An account and its unknown fields as they were, `None` where there was no entry.
+type PriorEntries = (Option<AccountState>, Option<UnknownFields>);
 
+/// Approximates the state from transactions and receipts, without executing anything.
+///
+/// Nonces, gas fees and top-level value transfers are exact. Value moved by contracts,
+/// runtime code and storage are only known from call traces (`apply_block_with_traces`);
+/// `unknown` records, per account, which fields could not be derived.
 #[derive(Debug, Clone)]
 pub struct StateReconstructor {
-    pub accounts: HashMap<H256, AccountState>,
+    pub accounts: HashMap<H160, AccountState>,
     pub block_number: u64,
+    pub head_hash: Option<H256>,
+    pub unknown: HashMap<H160, UnknownFields>,
+    /// Entries as they were before the block being applied, for the accounts it changed.
+    undo: Option<HashMap<H160, PriorEntries>>,
+}
+
+impl Default for StateReconstructor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StateReconstructor {
@@ -22,105 +35,286 @@ impl StateReconstructor {
         Self {
             accounts: HashMap::new(),
             block_number: 0,
+            head_hash: None,
+            unknown: HashMap::new(),
+            undo: None,
         }
     }
 
-    pub fn apply_transaction(&mut self, tx: &Transaction, receipt: &TransactionReceipt) {
-        // update sender account
-        if let from = tx.from {
-            let sender = self
-                .accounts
-                .entry(H256::from(from))
-                .or_insert(AccountState {
-                    nonce: U256::zero(),
-                    balance: U256::zero(),
-                    storage: HashMap::new(),
-                    code: Vec::new(),
-                });
-
-            let gas_cost =
-                tx.gas_price.unwrap_or_default() * U256::from(receipt.gas_used.unwrap_or_default());
-
-            // Verify the sender has enough balance
-            if sender.balance >= gas_cost + tx.value {
-                sender.balance -= gas_cost + tx.value;
-                sender.nonce += U256::one();
-            } else {
-                panic!(
-                    "Insufficient balance: Balance is {:?}, but transaction requires {:?} (value: {:?}, gas cost: {:?})",
-                    sender.balance, gas_cost + tx.value, tx.value, gas_cost
-                );
+    pub fn from_genesis(genesis: &Genesis) -> Self {
+        let mut state = Self::new();
+        state.initialize_from_genesis(genesis.clone());
+        state
+    }
+
+    /// Applies one transaction of `block`; `traces` are its parity-style call traces, if known.
+    pub fn apply_transaction(
+        &mut self,
+        block: &Block<Transaction>,
+        tx: &Transaction,
+        receipt: &TransactionReceipt,
+        traces: Option<&[&Trace]>,
+    ) -> Result<(), Error> {
+        let gas_price = receipt
+            .effective_gas_price
+            .or(tx.gas_price)
+            .unwrap_or_default();
+        let gas_used = receipt.gas_used.unwrap_or_default();
+        let fee = gas_price * gas_used;
+
+        // the fee is paid and the nonce used whether or not the transaction succeeds
+        {
+            let sender = self.account_mut(tx.from);
+            if sender.balance < fee {
+                return Err(anyhow!(
+                    "Insufficient balance: Balance is {:?}, but transaction {:?} pays {:?} in gas",
+                    sender.balance,
+                    tx.hash,
+                    fee
+                ));
             }
+            sender.balance -= fee;
+            sender.nonce = sender.nonce.max(tx.nonce + 1);
+        }
+        // the base fee is burned, the rest goes to the block's coinbase; without one we
+        // cannot tell who was paid, so the tip is left out rather than credited to 0x0
+        let burned = block.base_fee_per_gas.unwrap_or_default().min(gas_price) * gas_used;
+        if let Some(author) = block.author {
+            self.account_mut(author).balance += fee - burned;
+        }
+
+        match traces {
+            Some(traces) if !traces.is_empty() => self.apply_traces(traces),
+            _ if receipt.status == Some(0.into()) => Ok(()),
+            _ => self.apply_receipt(tx, receipt),
         }
+    }
+
+    /// Effects visible from the receipt alone: the top-level value transfer and, for
+    /// deployments, the created account.
+    fn apply_receipt(
+        &mut self,
+        tx: &Transaction,
+        receipt: &TransactionReceipt,
+    ) -> Result<(), Error> {
+        let Some(target) = tx.to.or(receipt.contract_address) else {
+            return Ok(());
+        };
+        self.transfer(tx.from, target, tx.value, true)?;
 
-        // update recipient account
-        if let Some(to) = tx.to {
-            let recipient = self.accounts.entry(H256::from(to)).or_insert(AccountState {
-                nonce: U256::zero(),
-                balance: U256::zero(),
-                storage: HashMap::new(),
-                code: Vec::new(),
+        if tx.to.is_none() {
+            // only the init code is archived; what it returns and stores needs execution
+            self.account_mut(target).nonce = U256::one();
+            self.mark_unknown(target, |unknown| {
+                unknown.code = true;
+                unknown.storage = true;
             });
+        } else if self.is_contract(target) {
+            // a contract may forward value and write storage, which receipts do not show
+            self.mark_unknown(target, |unknown| {
+                unknown.balance = true;
+                unknown.storage = true;
+            });
+        }
+        Ok(())
+    }
+
+    /// Effects of every call frame that did not revert, including the top-level one.
+    fn apply_traces(&mut self, traces: &[&Trace]) -> Result<(), Error> {
+        let reverted: Vec<&Vec<usize>> = traces
+            .iter()
+            .filter(|trace| trace.error.is_some())
+            .map(|trace| &trace.trace_address)
+            .collect();
 
-            recipient.balance += tx.value;
+        for trace in traces {
+            if reverted
+                .iter()
+                .any(|frame| trace.trace_address.starts_with(frame))
+            {
+                continue;
+            }
+            let top_level = trace.trace_address.is_empty();
 
-            // if the transaction deploys a contract, set its code
-            if tx.input.len() > 0 {
-                recipient.code = tx.input.to_vec();
+            match &trace.action {
+                Action::Call(call) => {
+                    if call.call_type == CallType::Call {
+                        self.transfer(call.from, call.to, call.value, top_level)?;
+                    }
+                    if self.is_contract(call.to) && call.call_type != CallType::StaticCall {
+                        self.mark_unknown(call.to, |unknown| unknown.storage = true);
+                    }
+                }
+                Action::Create(create) => {
+                    let Some(Res::Create(created)) = &trace.result else {
+                        continue;
+                    };
+                    if !top_level {
+                        self.account_mut(create.from).nonce += U256::one();
+                    }
+                    self.transfer(create.from, created.address, create.value, top_level)?;
+                    let account = self.account_mut(created.address);
+                    account.nonce = U256::one();
+                    account.code = created.code.to_vec();
+                    self.mark_unknown(created.address, |unknown| unknown.storage = true);
+                }
+                Action::Suicide(suicide) => {
+                    self.transfer(
+                        suicide.address,
+                        suicide.refund_address,
+                        suicide.balance,
+                        false,
+                    )?;
+                    self.mark_unknown(suicide.address, |unknown| {
+                        unknown.code = true;
+                        unknown.storage = true;
+                    });
+                }
+                Action::Reward(_) => {}
             }
         }
+        Ok(())
+    }
 
-        // update storage based on transaction logs
-        for log in &receipt.logs {
-            let account = self
-                .accounts
-                .entry(H256::from(log.address))
-                .or_insert(AccountState {
-                    nonce: U256::zero(),
-                    balance: U256::zero(),
-                    storage: HashMap::new(),
-                    code: Vec::new(),
-                });
-
-            if log.topics.len() >= 2 {
-                let storage_key = log.topics[0];
-                let storage_value = log.topics[1];
-                account.storage.insert(storage_key, storage_value);
+    /// Moves `value` between accounts. Only a transaction's own transfer must be covered
+    /// by the sender's balance; an internal one that is not means our balance was off.
+    fn transfer(&mut self, from: H160, to: H160, value: U256, strict: bool) -> Result<(), Error> {
+        if value.is_zero() {
+            return Ok(());
+        }
+        let sender = self.account_mut(from);
+        if sender.balance < value {
+            if strict {
+                return Err(anyhow!(
+                    "Insufficient balance: Balance of {:?} is {:?}, but it sends {:?}",
+                    from,
+                    sender.balance,
+                    value
+                ));
             }
+            sender.balance = U256::zero();
+            self.mark_unknown(from, |unknown| unknown.balance = true);
+        } else {
+            sender.balance -= value;
         }
+        self.account_mut(to).balance += value;
+        Ok(())
     }
 
-    pub fn apply_block(&mut self, block: &Block<Transaction>, receipts: &[TransactionReceipt]) {
-        assert_eq!(
-            block.transactions.len(),
-            receipts.len(),
-            "mismatched transaction and receipt counts"
-        );
+    fn is_contract(&self, address: H160) -> bool {
+        self.get_account_state(address)
+            .is_some_and(|account| !account.code.is_empty())
+            || self.unknown_fields(address).code
+    }
+
+    fn account_mut(&mut self, address: H160) -> &mut AccountState {
+        self.remember(address);
+        self.accounts.entry(address).or_insert(AccountState {
+            nonce: U256::zero(),
+            balance: U256::zero(),
+            storage: HashMap::new(),
+            code: Vec::new(),
+        })
+    }
+
+    fn mark_unknown(&mut self, address: H160, mark: impl FnOnce(&mut UnknownFields)) {
+        self.remember(address);
+        mark(self.unknown.entry(address).or_default());
+    }
+
+    /// Saves the entries of `address` before the current block first changes them.
+    fn remember(&mut self, address: H160) {
+        if let Some(undo) = &mut self.undo {
+            undo.entry(address).or_insert_with(|| {
+                (
+                    self.accounts.get(&address).cloned(),
+                    self.unknown.get(&address).copied(),
+                )
+            });
+        }
+    }
+
+    /// Fields of `address` that this mode could not derive.
+    pub fn unknown_fields(&self, address: H160) -> UnknownFields {
+        self.unknown.get(&address).copied().unwrap_or_default()
+    }
+
+    pub fn apply_block(
+        &mut self,
+        block: &Block<Transaction>,
+        receipts: &[TransactionReceipt],
+    ) -> Result<(), Error> {
+        self.apply_block_with_traces(block, receipts, None)
+    }
+
+    /// Applies `block`, using `traces` (as returned by `trace_block`) for the value moved
+    /// inside contracts and for created code.
+    pub fn apply_block_with_traces(
+        &mut self,
+        block: &Block<Transaction>,
+        receipts: &[TransactionReceipt],
+        traces: Option<&[Trace]>,
+    ) -> Result<(), Error> {
+        if block.transactions.len() != receipts.len() {
+            return Err(anyhow!(
+                "block #{}: {} transactions but {} receipts",
+                block.number.unwrap_or_default(),
+                block.transactions.len(),
+                receipts.len()
+            ));
+        }
 
-        for (tx, receipt) in block.transactions.iter().zip(receipts.iter()) {
-            self.apply_transaction(tx, receipt);
+        // a block is applied whole or not at all
+        self.undo = Some(HashMap::new());
+        for (position, (tx, receipt)) in block.transactions.iter().zip(receipts.iter()).enumerate()
+        {
+            let tx_traces: Option<Vec<&Trace>> = traces.map(|traces| {
+                traces
+                    .iter()
+                    .filter(|trace| trace.transaction_position == Some(position))
+                    .collect()
+            });
+            if let Err(err) = self.apply_transaction(block, tx, receipt, tx_traces.as_deref()) {
+                self.discard_block();
+                return Err(err);
+            }
         }
+        self.undo = None;
 
         self.block_number = block.number.unwrap_or_default().as_u64();
+        self.head_hash = block.hash;
+        Ok(())
+    }
+
+    /// Restores the entries changed by the block being applied.
+    fn discard_block(&mut self) {
+        for (address, (account, unknown)) in self.undo.take().unwrap_or_default() {
+            match account {
+                Some(account) => self.accounts.insert(address, account),
+                None => self.accounts.remove(&address),
+            };
+            match unknown {
+                Some(unknown) => self.unknown.insert(address, unknown),
+                None => self.unknown.remove(&address),
+            };
+        }
     }
 
-    pub fn get_account_state(&self, address: H256) -> Option<&AccountState> {
+    pub fn get_account_state(&self, address: H160) -> Option<&AccountState> {
         self.accounts.get(&address)
     }
 
     pub fn initialize_from_genesis(&mut self, genesis: Genesis) {
         for (address, alloc) in genesis.alloc {
-            let balance = U256::from_dec_str(&alloc.balance).unwrap_or_else(|_| U256::zero());
             let code =
                 hex::decode(alloc.code.strip_prefix("0x").unwrap_or("")).unwrap_or_else(|_| vec![]);
-            let storage = alloc.storage;
 
             self.accounts.insert(
-                H256::from(address),
+                address,
                 AccountState {
-                    nonce: U256::zero(),
-                    balance,
-                    storage,
+                    nonce: alloc.nonce_value().into(),
+                    balance: alloc.balance_value(),
+                    storage: alloc.storage.into_iter().collect(),
                     code,
                 },
             );
@@ -0,0 +1,204 @@
+use crate::utils::core::evm_exec::{AccountState, StateReconstructor};
+use crate::utils::core::state;
+use anyhow::Error;
+use ethers::types::{Bytes, H160, H256, U256};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Bound;
+
+/// An account without its storage, which is iterated separately with `StateStore::storage`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountEntry {
+    pub address: H160,
+    pub nonce: U256,
+    pub balance: U256,
+    pub code: Bytes,
+}
+
+impl AccountEntry {
+    /// No nonce, balance or code; the account may still have storage.
+    pub fn has_no_info(&self) -> bool {
+        self.nonce.is_zero() && self.balance.is_zero() && self.code.is_empty()
+    }
+}
+
+/// Keys between two bounds that start with `prefix`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange<K> {
+    pub start: Bound<K>,
+    pub end: Bound<K>,
+    pub prefix: Vec<u8>,
+}
+
+impl<K> Default for KeyRange<K> {
+    fn default() -> Self {
+        Self {
+            start: Bound::Unbounded,
+            end: Bound::Unbounded,
+            prefix: Vec::new(),
+        }
+    }
+}
+
+impl<K: AsRef<[u8]> + Ord> KeyRange<K> {
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    pub fn with_prefix(prefix: impl Into<Vec<u8>>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Starts at `key`, inclusive.
+    pub fn from(mut self, key: K) -> Self {
+        self.start = Bound::Included(key);
+        self
+    }
+
+    /// Starts right after `key`; pass the `next` cursor of a page to get the following page.
+    pub fn after(mut self, key: K) -> Self {
+        self.start = Bound::Excluded(key);
+        self
+    }
+
+    /// Ends before `key`, exclusive.
+    pub fn until(mut self, key: K) -> Self {
+        self.end = Bound::Excluded(key);
+        self
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => key >= start,
+            Bound::Excluded(start) => key > start,
+            Bound::Unbounded => true,
+        };
+        after_start && !self.is_past(key) && key.as_ref().starts_with(&self.prefix)
+    }
+
+    /// Whether neither `key` nor any key above it is in range, so ordered scans can stop.
+    pub fn is_past(&self, key: &K) -> bool {
+        let past_end = match &self.end {
+            Bound::Included(end) => key > end,
+            Bound::Excluded(end) => key >= end,
+            Bound::Unbounded => false,
+        };
+        let bytes = key.as_ref();
+        past_end || bytes[..self.prefix.len().min(bytes.len())] > *self.prefix.as_slice()
+    }
+}
+
+/// Up to `limit` items and the cursor to continue from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Page<T, K> {
+    pub items: Vec<T>,
+    /// Pass to `KeyRange::after` for the next page; `None` on the last page.
+    pub next: Option<K>,
+}
+
+pub type AccountIter<'a> = Box<dyn Iterator<Item = Result<AccountEntry, Error>> + 'a>;
+pub type StorageIter<'a> = Box<dyn Iterator<Item = Result<(H256, H256), Error>> + 'a>;
+
+/// Ordered access to a state one account or slot at a time, so that exports and analytics
+/// never need the whole state in memory.
+pub trait StateStore {
+    /// Accounts in `range`, by ascending address.
+    fn accounts(&self, range: KeyRange<H160>) -> AccountIter<'_>;
+
+    /// Non-zero storage slots of `address` in `range`, by ascending slot.
+    fn storage(&self, address: H160, range: KeyRange<H256>) -> StorageIter<'_>;
+
+    fn accounts_page(
+        &self,
+        range: KeyRange<H160>,
+        limit: usize,
+    ) -> Result<Page<AccountEntry, H160>, Error> {
+        paginate(self.accounts(range), limit, |account| account.address)
+    }
+
+    fn storage_page(
+        &self,
+        address: H160,
+        range: KeyRange<H256>,
+        limit: usize,
+    ) -> Result<Page<(H256, H256), H256>, Error> {
+        paginate(self.storage(address, range), limit, |(slot, _)| *slot)
+    }
+}
+
+fn paginate<T, K>(
+    mut iter: impl Iterator<Item = Result<T, Error>>,
+    limit: usize,
+    key: impl Fn(&T) -> K,
+) -> Result<Page<T, K>, Error> {
+    let items = iter.by_ref().take(limit).collect::<Result<Vec<_>, _>>()?;
+    let next = match iter.next() {
+        Some(Err(e)) => return Err(e),
+        Some(Ok(_)) => items.last().map(key),
+        None => None,
+    };
+    Ok(Page { items, next })
+}
+
+/// The head state of a full re-execution.
+impl StateStore for StateReconstructor {
+    fn accounts(&self, range: KeyRange<H160>) -> AccountIter<'_> {
+        accounts_in(&self.accounts, range)
+    }
+
+    fn storage(&self, address: H160, range: KeyRange<H256>) -> StorageIter<'_> {
+        storage_in(&self.accounts, address, range)
+    }
+}
+
+/// The approximate state of a receipt-driven replay.
+impl StateStore for state::StateReconstructor {
+    fn accounts(&self, range: KeyRange<H160>) -> AccountIter<'_> {
+        accounts_in(&self.accounts, range)
+    }
+
+    fn storage(&self, address: H160, range: KeyRange<H256>) -> StorageIter<'_> {
+        storage_in(&self.accounts, address, range)
+    }
+}
+
+/// Only the keys in range are sorted up front; accounts and slots are copied out one at a time.
+fn accounts_in(accounts: &HashMap<H160, AccountState>, range: KeyRange<H160>) -> AccountIter<'_> {
+    let mut addresses: Vec<H160> = accounts
+        .keys()
+        .copied()
+        .filter(|address| range.contains(address))
+        .collect();
+    addresses.sort_unstable();
+
+    Box::new(addresses.into_iter().map(move |address| {
+        let account = &accounts[&address];
+        Ok(AccountEntry {
+            address,
+            nonce: account.nonce,
+            balance: account.balance,
+            code: account.code.clone().into(),
+        })
+    }))
+}
+
+fn storage_in(
+    accounts: &HashMap<H160, AccountState>,
+    address: H160,
+    range: KeyRange<H256>,
+) -> StorageIter<'_> {
+    let Some(account) = accounts.get(&address) else {
+        return Box::new(std::iter::empty());
+    };
+    let mut slots: Vec<(H256, H256)> = account
+        .storage
+        .iter()
+        .filter(|(slot, value)| !value.is_zero() && range.contains(slot))
+        .map(|(slot, value)| (*slot, *value))
+        .collect();
+    slots.sort_unstable();
+    Box::new(slots.into_iter().map(Ok))
+}
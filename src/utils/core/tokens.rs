@@ -0,0 +1,307 @@
+use crate::utils::core::evm_exec::{CallRequest, StateReconstructor};
+use anyhow::{anyhow, Error};
+use ethers::abi::{self, ParamType, Token};
+use ethers::types::{Block, Log, Transaction, TransactionReceipt, H160, H256, U256};
+use ethers::utils::keccak256;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::LazyLock;
+
+static TRANSFER: LazyLock<H256> =
+    LazyLock::new(|| keccak256("Transfer(address,address,uint256)").into());
+static TRANSFER_SINGLE: LazyLock<H256> =
+    LazyLock::new(|| keccak256("TransferSingle(address,address,address,uint256,uint256)").into());
+static TRANSFER_BATCH: LazyLock<H256> = LazyLock::new(|| {
+    keccak256("TransferBatch(address,address,address,uint256[],uint256[])").into()
+});
+
+/// `balanceOf(address)` and ERC-1155's `balanceOf(address,uint256)`.
+const BALANCE_OF: [u8; 4] = [0x70, 0xa0, 0x82, 0x31];
+const BALANCE_OF_ID: [u8; 4] = [0x00, 0xfd, 0xd5, 0x8e];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TokenStandard {
+    Erc20,
+    Erc721,
+    Erc1155,
+}
+
+/// A token movement decoded from a log; mints come from and burns go to the zero address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenTransfer {
+    pub token: H160,
+    pub standard: TokenStandard,
+    pub from: H160,
+    pub to: H160,
+    /// Token id, `None` for ERC-20.
+    pub id: Option<U256>,
+    /// Always 1 for ERC-721.
+    pub amount: U256,
+}
+
+/// Decodes the ERC-20 / ERC-721 `Transfer` or ERC-1155 `TransferSingle` / `TransferBatch`
+/// transfers in `log`; other and malformed logs yield nothing.
+pub fn decode_transfers(log: &Log) -> Vec<TokenTransfer> {
+    let Some(&signature) = log.topics.first() else {
+        return Vec::new();
+    };
+    let topic_address = |index: usize| H160::from_slice(&log.topics[index][12..]);
+    let transfer = |standard, from, to, id, amount| TokenTransfer {
+        token: log.address,
+        standard,
+        from,
+        to,
+        id,
+        amount,
+    };
+
+    // ERC-20 and ERC-721 share the event and differ in whether the value is indexed
+    if signature == *TRANSFER {
+        return match (log.topics.len(), log.data.len()) {
+            (3, 32) => vec![transfer(
+                TokenStandard::Erc20,
+                topic_address(1),
+                topic_address(2),
+                None,
+                U256::from_big_endian(&log.data),
+            )],
+            (4, 0) => vec![transfer(
+                TokenStandard::Erc721,
+                topic_address(1),
+                topic_address(2),
+                Some(U256::from_big_endian(log.topics[3].as_bytes())),
+                U256::one(),
+            )],
+            _ => Vec::new(),
+        };
+    }
+
+    if log.topics.len() != 4 {
+        return Vec::new();
+    }
+    let (from, to) = (topic_address(2), topic_address(3));
+    if signature == *TRANSFER_SINGLE && log.data.len() == 64 {
+        return vec![transfer(
+            TokenStandard::Erc1155,
+            from,
+            to,
+            Some(U256::from_big_endian(&log.data[..32])),
+            U256::from_big_endian(&log.data[32..]),
+        )];
+    }
+    if signature == *TRANSFER_BATCH {
+        let array = || ParamType::Array(Box::new(ParamType::Uint(256)));
+        let Ok(decoded) = abi::decode(&[array(), array()], &log.data) else {
+            return Vec::new();
+        };
+        let uints = |token: &Token| -> Vec<U256> {
+            token
+                .clone()
+                .into_array()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(Token::into_uint)
+                .collect()
+        };
+        let (ids, amounts) = (uints(&decoded[0]), uints(&decoded[1]));
+        if ids.len() != amounts.len() {
+            return Vec::new();
+        }
+        return ids
+            .into_iter()
+            .zip(amounts)
+            .map(|(id, amount)| transfer(TokenStandard::Erc1155, from, to, Some(id), amount))
+            .collect();
+    }
+    Vec::new()
+}
+
+/// One holder's balance of a token; ERC-721 and ERC-1155 balances are per token id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenHolder {
+    pub holder: H160,
+    pub id: Option<U256>,
+    pub balance: U256,
+}
+
+/// Balances keyed by holder and token id, each versioned by the block that wrote it.
+type Balances = HashMap<(H160, Option<U256>), BTreeMap<u64, U256>>;
+
+/// Token balances derived from transfer logs in receipts, enabled on the re-executing
+/// reconstructor with `with_token_index`.
+///
+/// Balances only reflect transfers seen since indexing started, so tokens minted earlier (or
+/// moved without events) show up as mismatches in `check_token_balances`.
+#[derive(Debug, Clone, Default)]
+pub struct TokenIndexer {
+    /// Last indexed block.
+    pub head: u64,
+    standards: HashMap<H160, TokenStandard>,
+    balances: HashMap<H160, Balances>,
+}
+
+impl TokenIndexer {
+    pub fn new(block: u64) -> Self {
+        Self {
+            head: block,
+            ..Self::default()
+        }
+    }
+
+    /// Applies the transfers logged by the successful transactions of `block`.
+    pub fn index_block(&mut self, block: &Block<Transaction>, receipts: &[TransactionReceipt]) {
+        let number = block.number.unwrap_or_default().as_u64();
+        for receipt in receipts {
+            if receipt.status == Some(0.into()) {
+                continue;
+            }
+            for log in &receipt.logs {
+                for transfer in decode_transfers(log) {
+                    self.apply(number, &transfer);
+                }
+            }
+        }
+        self.head = number;
+    }
+
+    fn apply(&mut self, number: u64, transfer: &TokenTransfer) {
+        self.standards
+            .entry(transfer.token)
+            .or_insert(transfer.standard);
+        let balances = self.balances.entry(transfer.token).or_default();
+        for (holder, credit) in [(transfer.from, false), (transfer.to, true)] {
+            if holder.is_zero() {
+                continue;
+            }
+            let versions = balances.entry((holder, transfer.id)).or_default();
+            let balance = versions.values().next_back().copied().unwrap_or_default();
+            let balance = if credit {
+                balance.saturating_add(transfer.amount)
+            } else {
+                balance.saturating_sub(transfer.amount)
+            };
+            versions.insert(number, balance);
+        }
+    }
+
+    /// Drops every balance written after block `number`, e.g. when reverting a reorg.
+    pub fn truncate_after(&mut self, number: u64) {
+        for versions in self.balances.values_mut().flat_map(|b| b.values_mut()) {
+            versions.split_off(&(number + 1));
+        }
+        self.head = self.head.min(number);
+    }
+
+    /// Every token seen so far and the standard its first transfer followed.
+    pub fn tokens(&self) -> impl Iterator<Item = (H160, TokenStandard)> + '_ {
+        self.standards
+            .iter()
+            .map(|(token, standard)| (*token, *standard))
+    }
+
+    pub fn standard(&self, token: H160) -> Option<TokenStandard> {
+        self.standards.get(&token).copied()
+    }
+
+    /// Balance of `holder` as of the end of `block`; `id` is `None` for ERC-20.
+    pub fn balance_at(&self, token: H160, holder: H160, id: Option<U256>, block: u64) -> U256 {
+        self.balances
+            .get(&token)
+            .and_then(|balances| balances.get(&(holder, id)))
+            .and_then(|versions| versions.range(..=block).next_back())
+            .map(|(_, balance)| *balance)
+            .unwrap_or_default()
+    }
+
+    /// Holders with a non-zero balance of `token` as of the end of `block`, by address and id.
+    pub fn holders_at(&self, token: H160, block: u64) -> Vec<TokenHolder> {
+        let mut holders: Vec<TokenHolder> = self
+            .balances
+            .get(&token)
+            .into_iter()
+            .flatten()
+            .filter_map(|((holder, id), versions)| {
+                let (_, balance) = versions.range(..=block).next_back()?;
+                (!balance.is_zero()).then_some(TokenHolder {
+                    holder: *holder,
+                    id: *id,
+                    balance: *balance,
+                })
+            })
+            .collect();
+        holders.sort_unstable_by_key(|holder| (holder.holder, holder.id));
+        holders
+    }
+
+    pub fn holders(&self, token: H160) -> Vec<TokenHolder> {
+        self.holders_at(token, self.head)
+    }
+}
+
+/// An indexed balance that `balanceOf` on the reconstructed state disagrees with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BalanceMismatch {
+    pub holder: H160,
+    pub id: Option<U256>,
+    pub indexed: U256,
+    /// `None` when the call failed.
+    pub on_chain: Option<U256>,
+}
+
+/// Calls `balanceOf` for every indexed holder of `token` on the state as of `block` and
+/// returns the balances that differ. ERC-721 holders are compared by token count.
+pub fn check_token_balances(
+    state: &StateReconstructor,
+    token: H160,
+    block: u64,
+) -> Result<Vec<BalanceMismatch>, Error> {
+    let tokens = state
+        .tokens
+        .as_ref()
+        .ok_or_else(|| anyhow!("token balances are not indexed"))?;
+    let standard = tokens
+        .standard(token)
+        .ok_or_else(|| anyhow!("no transfers of token {:?} were indexed", token))?;
+
+    // includes holders whose balance dropped to zero, which balanceOf should agree with
+    let mut expected: BTreeMap<(H160, Option<U256>), U256> = BTreeMap::new();
+    for (holder, id) in tokens.balances[&token].keys() {
+        let balance = tokens.balance_at(token, *holder, *id, block);
+        match standard {
+            TokenStandard::Erc721 => *expected.entry((*holder, None)).or_default() += balance,
+            _ => *expected.entry((*holder, *id)).or_default() += balance,
+        }
+    }
+
+    let mut mismatches = Vec::new();
+    for ((holder, id), indexed) in expected {
+        let mut data = match id {
+            Some(_) => BALANCE_OF_ID.to_vec(),
+            None => BALANCE_OF.to_vec(),
+        };
+        let mut args = vec![Token::Address(holder)];
+        args.extend(id.map(Token::Uint));
+        data.extend(abi::encode(&args));
+
+        let request = CallRequest {
+            to: Some(token),
+            data: Some(data.into()),
+            ..Default::default()
+        };
+        let outcome = state
+            .call_at(&request, block)
+            .map_err(|e| anyhow!(e.to_string()))?;
+        let on_chain = (outcome.success && outcome.output.len() == 32)
+            .then(|| U256::from_big_endian(&outcome.output));
+        if on_chain != Some(indexed) {
+            mismatches.push(BalanceMismatch {
+                holder,
+                id,
+                indexed,
+                on_chain,
+            });
+        }
+    }
+    Ok(mismatches)
+}
@@ -0,0 +1,481 @@
+use anyhow::{anyhow, Error};
+use ethereum_types::{H160, H256, U256};
+use ethers::types::{
+    CallConfig, CallFrame, CallLogFrame, DefaultFrame, DiffMode, GethDebugTracingOptions,
+    GethTraceFrame, PreStateConfig, PreStateFrame, PreStateMode, StructLog,
+};
+use revm::db::CacheDB;
+use revm::interpreter::{
+    opcode, CallInputs, CallOutcome, CallScheme, CreateInputs, CreateOutcome, CreateScheme,
+    Interpreter, InterpreterResult, OpCode,
+};
+use revm::primitives::{
+    Address, EVMError, Env, EvmState, ExecutionResult, Log, ResultAndState, U256 as rU256,
+};
+use revm::{inspector_handle_register, Database, DatabaseRef, Evm, EvmContext, Inspector};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+/// Which trace to produce for a re-executed transaction, mirroring geth's `debug_trace*`
+/// tracers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tracer {
+    /// geth's default opcode logger, configured by `disableStack`, `disableStorage`,
+    /// `enableMemory` and `enableReturnData`.
+    StructLogs(GethDebugTracingOptions),
+    /// `callTracer`: the tree of calls and creations.
+    Call(CallConfig),
+    /// `prestateTracer`: the accounts the transaction touched, before it (or, in diff mode,
+    /// before and after it).
+    Prestate(PreStateConfig),
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::StructLogs(GethDebugTracingOptions::default())
+    }
+}
+
+impl Tracer {
+    /// Parses a geth tracing options object, e.g. `{"tracer": "callTracer",
+    /// "tracerConfig": {"onlyTopCall": true}}`; `null` selects the struct logger.
+    pub fn from_options(options: &Value) -> Result<Self, Error> {
+        if options.is_null() {
+            return Ok(Self::default());
+        }
+        let config = || match options.get("tracerConfig") {
+            None | Some(Value::Null) => json!({}),
+            Some(config) => config.clone(),
+        };
+
+        match options.get("tracer").and_then(Value::as_str) {
+            None => {
+                let mut options = options.clone();
+                if let Some(options) = options.as_object_mut() {
+                    options.remove("tracerConfig");
+                }
+                Ok(Self::StructLogs(serde_json::from_value(options)?))
+            }
+            Some("callTracer") => Ok(Self::Call(serde_json::from_value(config())?)),
+            Some("prestateTracer") => Ok(Self::Prestate(serde_json::from_value(config())?)),
+            Some(other) => Err(anyhow!("tracer {} is not supported", other)),
+        }
+    }
+
+    /// Executes the transaction set up in `env` on `db` without committing it, returning
+    /// its trace and its changes.
+    pub(crate) fn trace<DB: DatabaseRef>(
+        &self,
+        db: &mut CacheDB<DB>,
+        env: Env,
+    ) -> Result<(GethTraceFrame, EvmState), EVMError<DB::Error>> {
+        let gas_limit = env.tx.gas_limit;
+        match self {
+            Self::StructLogs(options) => {
+                let mut logger = StructLogger::new(options.clone());
+                let ResultAndState { result, state } = inspect(db, env, &mut logger)?;
+                Ok((logger.into_frame(&result).into(), state))
+            }
+            Self::Call(config) => {
+                let mut tracer = CallTracer::new(config.clone());
+                let ResultAndState { result, state } = inspect(db, env, &mut tracer)?;
+                Ok((tracer.into_frame(gas_limit, &result).into(), state))
+            }
+            Self::Prestate(config) => {
+                let ResultAndState { state, .. } = inspect(db, env, NoInspector)?;
+                let frame = prestate_frame(&*db, &state, config.diff_mode.unwrap_or_default())
+                    .map_err(EVMError::Database)?;
+                Ok((frame.into(), state))
+            }
+        }
+    }
+}
+
+struct NoInspector;
+
+impl<DB: Database> Inspector<DB> for NoInspector {}
+
+fn inspect<'a, DB: Database, I: Inspector<&'a mut DB>>(
+    db: &'a mut DB,
+    env: Env,
+    inspector: I,
+) -> Result<ResultAndState, EVMError<DB::Error>> {
+    Evm::builder()
+        .with_db(db)
+        .with_external_context(inspector)
+        .with_env(Box::new(env))
+        .append_handler_register(inspector_handle_register)
+        .build()
+        .transact()
+}
+
+/// Builds geth `callTracer` frames from revm's call and create hooks.
+#[derive(Debug, Default)]
+pub struct CallTracer {
+    config: CallConfig,
+    /// Frames still executing, innermost last.
+    open: Vec<CallFrame>,
+    root: Option<CallFrame>,
+}
+
+impl CallTracer {
+    pub fn new(config: CallConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// The top-level frame; geth reports the transaction's gas limit and total gas used
+    /// there, intrinsic gas included.
+    pub fn into_frame(self, gas_limit: u64, result: &ExecutionResult) -> CallFrame {
+        let mut frame = self.root.unwrap_or_default();
+        frame.gas = gas_limit.into();
+        frame.gas_used = result.gas_used().into();
+        frame
+    }
+
+    fn enter(&mut self, frame: CallFrame) {
+        self.open.push(frame);
+    }
+
+    fn exit(&mut self, result: &InterpreterResult, created: Option<Address>) {
+        let Some(mut frame) = self.open.pop() else {
+            return;
+        };
+        frame.gas_used = result.gas.spent().into();
+        if !result.output.is_empty() {
+            frame.output = Some(result.output.to_vec().into());
+        }
+        if let Some(address) = created {
+            frame.to = Some(to_h160(address).into());
+        }
+        if !result.is_ok() {
+            frame.error = Some(if result.is_revert() {
+                "execution reverted".to_string()
+            } else {
+                format!("{:?}", result.result)
+            });
+            // logs of failed frames are discarded
+            frame.logs = None;
+        }
+
+        match self.open.last_mut() {
+            Some(parent) if self.config.only_top_call != Some(true) => {
+                parent.calls.get_or_insert_with(Vec::new).push(frame)
+            }
+            Some(_) => {}
+            None => self.root = Some(frame),
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for CallTracer {
+    fn log(&mut self, _interp: &mut Interpreter, _context: &mut EvmContext<DB>, log: &Log) {
+        if self.config.with_log != Some(true) {
+            return;
+        }
+        if let Some(frame) = self.open.last_mut() {
+            frame.logs.get_or_insert_with(Vec::new).push(CallLogFrame {
+                address: Some(to_h160(log.address)),
+                topics: Some(log.topics().iter().map(|topic| H256(topic.0)).collect()),
+                data: Some(log.data.data.to_vec().into()),
+            });
+        }
+    }
+
+    fn call(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        // delegated code runs in the calling contract, which geth reports as the caller
+        let (typ, from, value) = match inputs.scheme {
+            CallScheme::CallCode => ("CALLCODE", inputs.target_address, true),
+            CallScheme::DelegateCall | CallScheme::ExtDelegateCall => {
+                ("DELEGATECALL", inputs.target_address, false)
+            }
+            CallScheme::StaticCall | CallScheme::ExtStaticCall => {
+                ("STATICCALL", inputs.caller, false)
+            }
+            CallScheme::Call | CallScheme::ExtCall => ("CALL", inputs.caller, true),
+        };
+        self.enter(CallFrame {
+            typ: typ.to_string(),
+            from: to_h160(from),
+            to: Some(to_h160(inputs.bytecode_address).into()),
+            value: value.then(|| to_u256(inputs.call_value())),
+            gas: inputs.gas_limit.into(),
+            input: inputs.input.to_vec().into(),
+            ..CallFrame::default()
+        });
+        None
+    }
+
+    fn call_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        self.exit(&outcome.result, None);
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        let typ = match inputs.scheme {
+            CreateScheme::Create => "CREATE",
+            CreateScheme::Create2 { .. } => "CREATE2",
+        };
+        self.enter(CallFrame {
+            typ: typ.to_string(),
+            from: to_h160(inputs.caller),
+            value: Some(to_u256(inputs.value)),
+            gas: inputs.gas_limit.into(),
+            input: inputs.init_code.to_vec().into(),
+            ..CallFrame::default()
+        });
+        None
+    }
+
+    fn create_end(
+        &mut self,
+        _context: &mut EvmContext<DB>,
+        _inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        self.exit(&outcome.result, outcome.address);
+        outcome
+    }
+
+    fn selfdestruct(&mut self, contract: Address, target: Address, value: rU256) {
+        if self.config.only_top_call == Some(true) {
+            return;
+        }
+        if let Some(parent) = self.open.last_mut() {
+            parent.calls.get_or_insert_with(Vec::new).push(CallFrame {
+                typ: "SELFDESTRUCT".to_string(),
+                from: to_h160(contract),
+                to: Some(to_h160(target).into()),
+                value: Some(to_u256(value)),
+                ..CallFrame::default()
+            });
+        }
+    }
+}
+
+/// geth's default tracer: one `StructLog` per executed opcode.
+#[derive(Debug, Default)]
+pub struct StructLogger {
+    options: GethDebugTracingOptions,
+    logs: Vec<StructLog>,
+    /// Slots each contract has read or written so far, as geth reports them.
+    storage: HashMap<Address, BTreeMap<H256, H256>>,
+    /// Gas left before the opcode being executed.
+    gas_before: u64,
+}
+
+impl StructLogger {
+    pub fn new(options: GethDebugTracingOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    pub fn into_frame(self, result: &ExecutionResult) -> DefaultFrame {
+        let output = match result {
+            ExecutionResult::Success { output, .. } => output.data().to_vec(),
+            ExecutionResult::Revert { output, .. } => output.to_vec(),
+            ExecutionResult::Halt { .. } => Vec::new(),
+        };
+        DefaultFrame {
+            failed: !result.is_success(),
+            gas: result.gas_used().into(),
+            return_value: output.into(),
+            struct_logs: self.logs,
+        }
+    }
+
+    fn record_slot(&mut self, contract: Address, slot: rU256, value: rU256) {
+        let storage = self.storage.entry(contract).or_default();
+        storage.insert(to_h256(slot), to_h256(value));
+        if let Some(log) = self.logs.last_mut() {
+            log.storage = Some(storage.clone());
+        }
+    }
+}
+
+impl<DB: Database> Inspector<DB> for StructLogger {
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        let op = interp.current_opcode();
+        self.gas_before = interp.gas.remaining();
+
+        let memory = (self.options.enable_memory == Some(true)).then(|| {
+            interp
+                .shared_memory
+                .context_memory()
+                .chunks(32)
+                .map(hex::encode)
+                .collect()
+        });
+        let stack = (self.options.disable_stack != Some(true))
+            .then(|| interp.stack.data().iter().copied().map(to_u256).collect());
+        let return_data = (self.options.enable_return_data == Some(true)
+            && !interp.return_data_buffer.is_empty())
+        .then(|| format!("0x{}", hex::encode(&interp.return_data_buffer)));
+
+        self.logs.push(StructLog {
+            depth: context.journaled_state.depth(),
+            gas: self.gas_before,
+            op: OpCode::new(op)
+                .map(|op| op.as_str().to_string())
+                .unwrap_or_else(|| format!("opcode {:#04x}", op)),
+            pc: interp.program_counter() as u64,
+            memory,
+            stack,
+            mem_size: Some(interp.shared_memory.context_memory().len() as u64),
+            return_data,
+            refund_counter: Some(interp.gas.refunded().max(0) as u64),
+            ..StructLog::default()
+        });
+
+        if op == opcode::SSTORE && self.options.disable_storage != Some(true) {
+            if let (Ok(slot), Ok(value)) = (interp.stack.peek(0), interp.stack.peek(1)) {
+                self.record_slot(interp.contract.target_address, slot, value);
+            }
+        }
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        let gas_cost = self.gas_before.saturating_sub(interp.gas.remaining());
+        let Some(log) = self.logs.last_mut() else {
+            return;
+        };
+        log.gas_cost = gas_cost;
+        if interp.instruction_result.is_error() {
+            log.error = Some(format!("{:?}", interp.instruction_result));
+        }
+
+        // the loaded value is only on the stack once SLOAD ran
+        if log.op == "SLOAD" && self.options.disable_storage != Some(true) {
+            let slot = log.stack.as_ref().and_then(|stack| stack.last().copied());
+            if let (Some(slot), Ok(value)) = (slot, interp.stack.peek(0)) {
+                self.record_slot(interp.contract.target_address, to_revm(slot), value);
+            }
+        }
+    }
+}
+
+/// geth `prestateTracer` output for a transaction's uncommitted `state`, reading the values
+/// before it from `db`.
+pub fn prestate_frame<DB: DatabaseRef>(
+    db: &DB,
+    state: &EvmState,
+    diff_mode: bool,
+) -> Result<PreStateFrame, DB::Error> {
+    let mut pre = BTreeMap::new();
+    let mut post = BTreeMap::new();
+
+    for (address, account) in state {
+        let before = db.basic_ref(*address)?;
+        let code_before = match &before {
+            Some(info) => match &info.code {
+                Some(code) => code.original_bytes(),
+                None => db.code_by_hash_ref(info.code_hash)?.original_bytes(),
+            },
+            None => Default::default(),
+        };
+        let balance_before = before.as_ref().map(|info| info.balance).unwrap_or_default();
+        let nonce_before = before.as_ref().map(|info| info.nonce).unwrap_or_default();
+
+        let slots = account
+            .storage
+            .iter()
+            .filter(|(_, slot)| !diff_mode || slot.is_changed());
+        let mut pre_account = ethers::types::AccountState {
+            balance: Some(to_u256(balance_before)),
+            nonce: (nonce_before != 0).then(|| nonce_before.into()),
+            code: (!code_before.is_empty()).then(|| format!("0x{}", hex::encode(&code_before))),
+            storage: Some(
+                slots
+                    .clone()
+                    .map(|(slot, value)| (to_h256(*slot), to_h256(value.original_value)))
+                    .collect(),
+            ),
+        };
+        if pre_account.storage.as_ref().is_some_and(BTreeMap::is_empty) {
+            pre_account.storage = None;
+        }
+
+        if !diff_mode {
+            pre.insert(to_h160(*address), pre_account);
+            continue;
+        }
+
+        // diff mode: accounts the transaction changed, with only the changed fields after it
+        let code_after = account
+            .info
+            .code
+            .as_ref()
+            .map(|code| code.original_bytes())
+            .unwrap_or_default();
+        let storage_after: BTreeMap<H256, H256> = slots
+            .map(|(slot, value)| (to_h256(*slot), to_h256(value.present_value)))
+            .collect();
+        let changed = account.is_touched()
+            && (account.info.balance != balance_before
+                || account.info.nonce != nonce_before
+                || code_after != code_before
+                || !storage_after.is_empty()
+                || account.is_selfdestructed());
+        if !changed {
+            continue;
+        }
+
+        if before.is_some() {
+            pre.insert(to_h160(*address), pre_account);
+        }
+        if !account.is_selfdestructed() {
+            post.insert(
+                to_h160(*address),
+                ethers::types::AccountState {
+                    balance: (account.info.balance != balance_before)
+                        .then(|| to_u256(account.info.balance)),
+                    nonce: (account.info.nonce != nonce_before).then(|| account.info.nonce.into()),
+                    code: (code_after != code_before)
+                        .then(|| format!("0x{}", hex::encode(&code_after))),
+                    storage: (!storage_after.is_empty()).then_some(storage_after),
+                },
+            );
+        }
+    }
+
+    Ok(if diff_mode {
+        PreStateFrame::Diff(DiffMode { pre, post })
+    } else {
+        PreStateFrame::Default(PreStateMode(pre))
+    })
+}
+
+fn to_h160(address: Address) -> H160 {
+    H160(address.0 .0)
+}
+
+fn to_h256(value: rU256) -> H256 {
+    H256(value.to_be_bytes::<32>())
+}
+
+fn to_u256(value: rU256) -> U256 {
+    U256::from_big_endian(&value.to_be_bytes::<32>())
+}
+
+fn to_revm(value: U256) -> rU256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    rU256::from_be_bytes(bytes)
+}
@@ -0,0 +1,261 @@
+use crate::utils::core::block_source::{BlockSource, BlockWithReceipts};
+use crate::utils::core::evm_wvm_types::{WvmBlock, WvmTransaction, WvmTransactionReceipt};
+use crate::utils::core::networks::Networks;
+use anyhow::Error;
+use ethers::types::{Block, Transaction, TransactionReceipt};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffSection {
+    Header,
+    Transaction,
+    Receipt,
+    Log,
+}
+
+/// A single field that differs between the two sources.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDiff {
+    pub section: DiffSection,
+    /// Location of the field, e.g. `receipts[2].logs[0].data`.
+    pub path: String,
+    pub left: Value,
+    pub right: Value,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockReport {
+    pub block_number: u64,
+    pub diffs: Vec<FieldDiff>,
+    /// Set when either side could not serve the block.
+    pub error: Option<String>,
+}
+
+impl BlockReport {
+    pub fn is_consistent(&self) -> bool {
+        self.error.is_none() && self.diffs.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerificationReport {
+    pub from: u64,
+    pub to: u64,
+    pub blocks_checked: u64,
+    pub blocks_matching: u64,
+    /// Only the blocks that failed to fetch or did not match.
+    pub mismatches: Vec<BlockReport>,
+}
+
+impl VerificationReport {
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Audits an archiver instance against its network RPC over `from..=to`.
+#[tracing::instrument(skip(network), fields(network = %network.name))]
+pub async fn verify_archiver(
+    network: &Networks,
+    from: u64,
+    to: u64,
+) -> Result<VerificationReport, Error> {
+    verify_sources(&network.archiver_source(), &network.rpc_source(), from, to).await
+}
+
+/// Fetches every block of `from..=to` from both sources and diffs them field by field.
+pub async fn verify_sources<L, R>(
+    left: &L,
+    right: &R,
+    from: u64,
+    to: u64,
+) -> Result<VerificationReport, Error>
+where
+    L: BlockSource + ?Sized,
+    R: BlockSource + ?Sized,
+{
+    let mut report = VerificationReport {
+        from,
+        to,
+        ..Default::default()
+    };
+
+    for block_nr in from..=to {
+        let (left_block, right_block) =
+            futures::join!(left.get_block(block_nr), right.get_block(block_nr));
+
+        let block_report = match (left_block, right_block) {
+            (Ok(left_block), Ok(right_block)) => BlockReport {
+                block_number: block_nr,
+                diffs: diff_blocks(&left_block, &right_block),
+                error: None,
+            },
+            (left_block, right_block) => BlockReport {
+                block_number: block_nr,
+                diffs: Vec::new(),
+                error: Some(
+                    [("left", left_block.err()), ("right", right_block.err())]
+                        .into_iter()
+                        .filter_map(|(side, err)| err.map(|e| format!("{}: {}", side, e)))
+                        .collect::<Vec<_>>()
+                        .join("; "),
+                ),
+            },
+        };
+
+        report.blocks_checked += 1;
+        if block_report.is_consistent() {
+            report.blocks_matching += 1;
+        } else {
+            warn!(
+                number = block_nr,
+                fields = block_report.diffs.len(),
+                "block differs between sources"
+            );
+            report.mismatches.push(block_report);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Field-level diff of two copies of the same block. Both sides are first normalized
+/// through the archived types so that fields the archiver never stores don't show up.
+pub fn diff_blocks(left: &BlockWithReceipts, right: &BlockWithReceipts) -> Vec<FieldDiff> {
+    let mut diffs = Vec::new();
+
+    let (left_header, left_txs) = split_block(&left.0);
+    let (right_header, right_txs) = split_block(&right.0);
+    diff_values(
+        DiffSection::Header,
+        "header",
+        &Value::Object(left_header),
+        &Value::Object(right_header),
+        &mut diffs,
+    );
+    diff_lists(
+        DiffSection::Transaction,
+        "transactions",
+        &left_txs,
+        &right_txs,
+        &mut diffs,
+    );
+
+    let left_receipts: Vec<Value> = left.1.iter().map(normalize_receipt).collect();
+    let right_receipts: Vec<Value> = right.1.iter().map(normalize_receipt).collect();
+    if left_receipts.len() != right_receipts.len() {
+        diffs.push(FieldDiff {
+            section: DiffSection::Receipt,
+            path: "receipts.length".to_string(),
+            left: left_receipts.len().into(),
+            right: right_receipts.len().into(),
+        });
+    }
+
+    for (i, (left_receipt, right_receipt)) in left_receipts.iter().zip(&right_receipts).enumerate()
+    {
+        let path = format!("receipts[{}]", i);
+        let (mut left_receipt, mut right_receipt) =
+            (as_object(left_receipt), as_object(right_receipt));
+        let left_logs = take_array(&mut left_receipt, "logs");
+        let right_logs = take_array(&mut right_receipt, "logs");
+
+        diff_values(
+            DiffSection::Receipt,
+            &path,
+            &Value::Object(left_receipt),
+            &Value::Object(right_receipt),
+            &mut diffs,
+        );
+        diff_lists(
+            DiffSection::Log,
+            &format!("{}.logs", path),
+            &left_logs,
+            &right_logs,
+            &mut diffs,
+        );
+    }
+
+    diffs
+}
+
+fn split_block(block: &Block<Transaction>) -> (Map<String, Value>, Vec<Value>) {
+    let normalized: Block<Transaction> = WvmBlock::<WvmTransaction>::from(block.clone()).into();
+    let mut header = as_object(&serde_json::to_value(normalized).unwrap_or_default());
+    let txs = take_array(&mut header, "transactions");
+    (header, txs)
+}
+
+fn normalize_receipt(receipt: &TransactionReceipt) -> Value {
+    let normalized: TransactionReceipt = WvmTransactionReceipt::from(receipt.clone()).into();
+    serde_json::to_value(normalized).unwrap_or_default()
+}
+
+fn as_object(value: &Value) -> Map<String, Value> {
+    value.as_object().cloned().unwrap_or_default()
+}
+
+fn take_array(object: &mut Map<String, Value>, key: &str) -> Vec<Value> {
+    match object.remove(key) {
+        Some(Value::Array(items)) => items,
+        _ => Vec::new(),
+    }
+}
+
+fn diff_lists(
+    section: DiffSection,
+    path: &str,
+    left: &[Value],
+    right: &[Value],
+    diffs: &mut Vec<FieldDiff>,
+) {
+    if left.len() != right.len() {
+        diffs.push(FieldDiff {
+            section,
+            path: format!("{}.length", path),
+            left: left.len().into(),
+            right: right.len().into(),
+        });
+    }
+    for (i, (left, right)) in left.iter().zip(right).enumerate() {
+        diff_values(section, &format!("{}[{}]", path, i), left, right, diffs);
+    }
+}
+
+fn diff_values(
+    section: DiffSection,
+    path: &str,
+    left: &Value,
+    right: &Value,
+    diffs: &mut Vec<FieldDiff>,
+) {
+    match (left, right) {
+        (Value::Object(left), Value::Object(right)) => {
+            let mut keys: Vec<&String> = left.keys().chain(right.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                diff_values(
+                    section,
+                    &format!("{}.{}", path, key),
+                    left.get(key).unwrap_or(&Value::Null),
+                    right.get(key).unwrap_or(&Value::Null),
+                    diffs,
+                );
+            }
+        }
+        (Value::Array(left), Value::Array(right)) => {
+            diff_lists(section, path, left, right, diffs);
+        }
+        (left, right) if left != right => diffs.push(FieldDiff {
+            section,
+            path: path.to_string(),
+            left: left.clone(),
+            right: right.clone(),
+        }),
+        _ => {}
+    }
+}
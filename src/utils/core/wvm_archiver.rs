@@ -1,6 +1,8 @@
+use crate::utils::core::block_source::BlockWithReceipts;
 use crate::utils::core::evm_wvm_types::{WvmBlock, WvmTransaction, WvmTransactionReceipt};
 use crate::utils::core::networks::Networks;
-use anyhow::Error;
+use anyhow::{anyhow, Error};
+use borsh_derive::{BorshDeserialize, BorshSerialize};
 use ethereum_types::U256;
 use ethers::types::{Block, Transaction, TransactionReceipt};
 use serde::{Deserialize, Serialize};
@@ -23,35 +25,80 @@ pub struct ArchiverInfo {
     pub network_rpc: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, BorshSerialize, BorshDeserialize)]
 pub struct WvmArchiverDataBlock {
     pub block: Option<WvmBlock<WvmTransaction>>,
     pub txs_receipts: Option<Vec<WvmTransactionReceipt>>,
 }
 
+impl ArchiverInfo {
+    /// Inclusive range of archived blocks, spanning backfill and livesync.
+    pub fn archived_range(&self) -> (u64, u64) {
+        let first = self
+            .first_backfill_archived_block
+            .or(self.first_livesync_archived_block)
+            .unwrap_or_default();
+        let last = self
+            .last_livesync_archived_block
+            .max(self.last_backfill_archived_block)
+            .unwrap_or_default();
+        (first, last)
+    }
+}
+
+impl From<BlockWithReceipts> for WvmArchiverDataBlock {
+    fn from((block, receipts): BlockWithReceipts) -> Self {
+        Self {
+            block: Some(block.into()),
+            txs_receipts: Some(receipts.into_iter().map(Into::into).collect()),
+        }
+    }
+}
+
+impl WvmArchiverDataBlock {
+    pub fn into_block_receipts(self) -> Result<BlockWithReceipts, Error> {
+        let block: Block<Transaction> = self
+            .block
+            .ok_or_else(|| anyhow!("archived block data is missing the block"))?
+            .into();
+        let receipts: Vec<TransactionReceipt> = self
+            .txs_receipts
+            .ok_or_else(|| anyhow!("archived block data is missing the receipts"))?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok((block, receipts))
+    }
+}
+
 pub async fn load_network_archiver_info(network: Networks) -> Result<ArchiverInfo, Error> {
-    let info_url = format!("{}/v1/info", network.wvm_archiver_url.unwrap_or_default());
-    let archiver_info: ArchiverInfo = reqwest::get(info_url).await?.json().await?;
+    load_archiver_info(network.wvm_archiver_url).await
+}
+
+pub async fn load_archiver_info(wvm_archiver_url: Option<String>) -> Result<ArchiverInfo, Error> {
+    let info_url = format!("{}/v1/info", wvm_archiver_url.unwrap_or_default());
+    let archiver_info: ArchiverInfo = reqwest::get(info_url)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
     Ok(archiver_info)
 }
 
 pub async fn get_block_from_wvm(
     wvm_archiver_url: Option<String>,
     block_nr: u64,
-) -> Result<(Block<Transaction>, Vec<TransactionReceipt>), Error> {
+) -> Result<BlockWithReceipts, Error> {
     let info_url = format!(
         "{}/v1/block/raw/{}",
         wvm_archiver_url.unwrap_or_default(),
         block_nr
     );
-    let block_info: WvmArchiverDataBlock = reqwest::get(info_url).await?.json().await?;
-    let block: Block<Transaction> = block_info.block.unwrap().into();
-    let txs_receipts = block_info.txs_receipts.unwrap();
-    let mut receipts: Vec<TransactionReceipt> = Vec::new();
-
-    for receipt in txs_receipts {
-        receipts.push(receipt.into());
-    }
-
-    Ok((block, receipts))
+    let block_info: WvmArchiverDataBlock = reqwest::get(info_url)
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    block_info.into_block_receipts()
 }
@@ -0,0 +1,147 @@
+use crate::utils::constants::WVM_RPC_URL;
+use crate::utils::core::block_source::{BlockSource, BlockWithReceipts};
+use crate::utils::core::wvm_archiver::{load_archiver_info, WvmArchiverDataBlock};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use ethers::middleware::Middleware;
+use ethers::providers::{Http, Provider};
+use ethers::types::H256;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+/// Brotli parameters used by wvm-archiver when it posts blocks to WeaveVM.
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_QUALITY: u32 = 11;
+const BROTLI_LG_WINDOW_SIZE: u32 = 22;
+
+pub fn brotli_compress(input: &[u8]) -> Vec<u8> {
+    let mut writer = brotli::CompressorWriter::new(
+        Vec::new(),
+        BROTLI_BUFFER_SIZE,
+        BROTLI_QUALITY,
+        BROTLI_LG_WINDOW_SIZE,
+    );
+    // writing into a Vec cannot fail
+    writer.write_all(input).unwrap();
+    writer.into_inner()
+}
+
+pub fn brotli_decompress(input: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    brotli::Decompressor::new(input, BROTLI_BUFFER_SIZE).read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Encodes a block the way wvm-archiver stores it in WeaveVM calldata: Borsh, then Brotli.
+pub fn encode_archived_calldata(data: &WvmArchiverDataBlock) -> Result<Vec<u8>, Error> {
+    Ok(brotli_compress(&borsh::to_vec(data)?))
+}
+
+pub fn decode_archived_calldata(calldata: &[u8]) -> Result<WvmArchiverDataBlock, Error> {
+    let decompressed = brotli_decompress(calldata)?;
+    Ok(borsh::from_slice(&decompressed)?)
+}
+
+/// Maps archived block numbers to the WeaveVM transactions that carry them.
+#[derive(Debug, Clone)]
+pub enum WvmTxIndex {
+    /// Looks the transaction up through an archiver's `/v1/block/{n}` endpoint. The archiver
+    /// is only trusted for the pointer: the data itself is read from WeaveVM and checked.
+    Archiver(String),
+    /// A fixed block number to transaction hash table, e.g. exported from a previous run.
+    Static(BTreeMap<u64, H256>),
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchivedBlockPointer {
+    wvm_archive_txid: String,
+}
+
+impl WvmTxIndex {
+    pub async fn lookup(&self, block_nr: u64) -> Result<H256, Error> {
+        match self {
+            WvmTxIndex::Archiver(url) => {
+                let pointer_url = format!("{}/v1/block/{}", url, block_nr);
+                let pointer: ArchivedBlockPointer = reqwest::get(pointer_url)
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(pointer.wvm_archive_txid.parse()?)
+            }
+            WvmTxIndex::Static(index) => index
+                .get(&block_nr)
+                .copied()
+                .ok_or_else(|| anyhow!("no WeaveVM transaction indexed for block #{}", block_nr)),
+        }
+    }
+}
+
+/// Reads archived blocks straight from WeaveVM transaction calldata.
+#[derive(Debug, Clone)]
+pub struct WvmCalldataSource {
+    pub wvm_provider: Provider<Http>,
+    pub index: WvmTxIndex,
+}
+
+impl WvmCalldataSource {
+    pub fn new(wvm_provider: Provider<Http>, index: WvmTxIndex) -> Self {
+        Self {
+            wvm_provider,
+            index,
+        }
+    }
+
+    /// Reads from the public WeaveVM RPC, resolving transactions through `wvm_archiver_url`.
+    pub fn from_archiver(wvm_archiver_url: String) -> Self {
+        Self::new(
+            Provider::<Http>::try_from(WVM_RPC_URL).unwrap(),
+            WvmTxIndex::Archiver(wvm_archiver_url),
+        )
+    }
+
+    pub async fn get_archived_block(&self, block_nr: u64) -> Result<WvmArchiverDataBlock, Error> {
+        let txid = self.index.lookup(block_nr).await?;
+        let tx = self
+            .wvm_provider
+            .get_transaction(txid)
+            .await?
+            .ok_or_else(|| anyhow!("WeaveVM transaction {:?} not found", txid))?;
+
+        let data = decode_archived_calldata(&tx.input)?;
+        let archived_nr = data.block.as_ref().and_then(|block| block.number);
+        if archived_nr != Some(block_nr) {
+            return Err(anyhow!(
+                "WeaveVM transaction {:?} archives block {:?}, expected #{}",
+                txid,
+                archived_nr,
+                block_nr
+            ));
+        }
+
+        Ok(data)
+    }
+}
+
+#[async_trait]
+impl BlockSource for WvmCalldataSource {
+    async fn block_range(&self) -> Result<(u64, u64), Error> {
+        match &self.index {
+            WvmTxIndex::Archiver(url) => {
+                let info = load_archiver_info(Some(url.clone())).await?;
+                Ok(info.archived_range())
+            }
+            WvmTxIndex::Static(index) => match (index.keys().next(), index.keys().next_back()) {
+                (Some(first), Some(last)) => Ok((*first, *last)),
+                _ => Err(anyhow!("WeaveVM transaction index is empty")),
+            },
+        }
+    }
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error> {
+        self.get_archived_block(block_nr)
+            .await?
+            .into_block_receipts()
+    }
+}
@@ -0,0 +1,66 @@
+use ethers::types::{Address, U256};
+use evm_state_reconstructing::utils::core::block_source::{BlockSource, FileSource};
+use evm_state_reconstructing::utils::core::bundle::{export_bundle, BundleSource};
+use evm_state_reconstructing::utils::core::genesis_load::load_genesis_from_file;
+use evm_state_reconstructing::utils::core::reconstruct::reconstruct_from_bundle;
+use std::path::PathBuf;
+
+fn bundle_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}.wvmb", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn exported_bundle_serves_the_same_blocks() {
+    let files = FileSource::new("tests/fixtures/blocks");
+    let path = bundle_path("same-blocks");
+    let index = export_bundle(&files, 1, 3, &path).await.unwrap();
+    assert_eq!(index.len(), 3);
+
+    let bundle = BundleSource::open(&path).unwrap();
+    assert_eq!(bundle.block_range().await.unwrap(), (1, 3));
+    for block_nr in 1..=3 {
+        let (block, receipts) = bundle.get_block(block_nr).await.unwrap();
+        let (expected_block, expected_receipts) = files.get_block(block_nr).await.unwrap();
+        assert_eq!(block.hash, expected_block.hash);
+        assert_eq!(block.transactions, expected_block.transactions);
+        assert_eq!(receipts, expected_receipts);
+    }
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn corrupted_frame_fails_checksum() {
+    let path = bundle_path("corrupted");
+    let index = export_bundle(&FileSource::new("tests/fixtures/blocks"), 1, 3, &path)
+        .await
+        .unwrap();
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    bytes[index[1].offset as usize + 10] ^= 0xff;
+    std::fs::write(&path, bytes).unwrap();
+
+    let bundle = BundleSource::open(&path).unwrap();
+    assert!(bundle.get_block(1).await.is_ok());
+    let err = bundle.get_block(2).await.unwrap_err();
+    assert!(err.to_string().contains("checksum mismatch"));
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn reconstructs_offline_from_bundle() {
+    let path = bundle_path("offline");
+    export_bundle(&FileSource::new("tests/fixtures/blocks"), 1, 3, &path)
+        .await
+        .unwrap();
+
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let state = reconstruct_from_bundle(&genesis, &path).await.unwrap();
+
+    assert_eq!(state.block_number, 3);
+    let recipient = state.get_account_state(Address::repeat_byte(0xcc)).unwrap();
+    assert_eq!(recipient.balance, U256::exp10(18) * 3);
+
+    std::fs::remove_file(path).unwrap();
+}
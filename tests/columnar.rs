@@ -0,0 +1,231 @@
+use arrow::array::{Array, AsArray};
+use arrow::datatypes::UInt64Type;
+use ethers::types::{Block, Transaction, TransactionReceipt, H160, U256};
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::columnar::{ColumnarExporter, ExportFormat};
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::load_genesis_from_file;
+use evm_state_reconstructing::utils::core::hooks::ReplayHooks;
+use evm_state_reconstructing::utils::core::reconstruct::replay_blocks;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+async fn export(dir: &Path, format: ExportFormat) -> Vec<PathBuf> {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let exporter = ColumnarExporter::new(dir, format, 2);
+    let mut state = StateReconstructor::from_genesis(&genesis).with_hooks(exporter.clone());
+    replay_blocks(&mut state, &FileSource::new("tests/fixtures/blocks"), 1, 3)
+        .await
+        .unwrap();
+    exporter.finish().unwrap()
+}
+
+#[tokio::test]
+async fn writes_partitioned_parquet() {
+    let dir = temp_dir("columnar-parquet");
+    let files = export(&dir, ExportFormat::Parquet).await;
+
+    // blocks 1..=3 fall into the partitions 0-1 and 2-3
+    let blocks: Vec<PathBuf> = files
+        .iter()
+        .filter(|path| path.starts_with(dir.join("blocks")))
+        .cloned()
+        .collect();
+    assert_eq!(
+        blocks,
+        vec![
+            dir.join("blocks/0000000000-0000000001.parquet"),
+            dir.join("blocks/0000000002-0000000003.parquet"),
+        ]
+    );
+    for table in ["transactions", "receipts", "state_diffs", "storage_diffs"] {
+        assert!(
+            files.iter().any(|path| path.starts_with(dir.join(table))),
+            "no {} written",
+            table
+        );
+    }
+
+    let read = |path: &Path| {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+    };
+    let numbers: Vec<u64> = blocks
+        .iter()
+        .flat_map(|path| read(path))
+        .flat_map(|batch| {
+            batch
+                .column_by_name("number")
+                .unwrap()
+                .as_primitive::<UInt64Type>()
+                .values()
+                .to_vec()
+        })
+        .collect();
+    assert_eq!(numbers, vec![1, 2, 3]);
+
+    let transactions: usize = files
+        .iter()
+        .filter(|path| path.starts_with(dir.join("transactions")))
+        .flat_map(|path| read(path))
+        .map(|batch| batch.num_rows())
+        .sum();
+    assert_eq!(transactions, 4);
+
+    // the contract deployed in block 3 writes its first slot
+    let storage = read(&dir.join("storage_diffs/0000000002-0000000003.parquet"));
+    let values = storage[0]
+        .column_by_name("value_after")
+        .unwrap()
+        .as_string::<i32>();
+    assert_eq!(values.len(), 1);
+    assert_eq!(values.value(0), format!("0x{:064x}", 42));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn writes_csv_with_headers() {
+    let dir = temp_dir("columnar-csv");
+    let files = export(&dir, ExportFormat::Csv).await;
+    assert!(files.iter().all(|path| path.extension().unwrap() == "csv"));
+
+    let receipts = fs::read_to_string(dir.join("receipts/0000000002-0000000003.csv")).unwrap();
+    let mut lines = receipts.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .starts_with("block_number,transaction_index,transaction_hash,from,to"));
+    assert_eq!(lines.count(), 3);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn writes_the_other_tables_when_one_fails() {
+    let dir = temp_dir("columnar-failure");
+    fs::create_dir_all(&dir).unwrap();
+    // a file in the way of the blocks directory
+    fs::write(dir.join("blocks"), b"").unwrap();
+
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let exporter = ColumnarExporter::new(&dir, ExportFormat::Csv, 2);
+    let mut state = StateReconstructor::from_genesis(&genesis).with_hooks(exporter.clone());
+    replay_blocks(&mut state, &FileSource::new("tests/fixtures/blocks"), 1, 3)
+        .await
+        .unwrap();
+
+    let error = exporter.finish().unwrap_err().to_string();
+    assert!(error.contains("blocks"), "{}", error);
+    for table in ["transactions", "receipts", "state_diffs", "storage_diffs"] {
+        assert!(
+            dir.join(table).join("0000000002-0000000003.csv").exists(),
+            "no {} written",
+            table
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn rejects_quantities_too_large_for_their_column() {
+    let dir = temp_dir("columnar-overflow");
+    let mut exporter = ColumnarExporter::new(&dir, ExportFormat::Csv, 2);
+    exporter.on_block_start(&Block::<Transaction> {
+        number: Some(1.into()),
+        gas_limit: U256::MAX,
+        ..Default::default()
+    });
+
+    let error = exporter.finish().unwrap_err().to_string();
+    assert!(error.contains("gas_limit"), "{}", error);
+    assert!(!dir.join("blocks").exists());
+}
+
+async fn replay_into(exporter: &ColumnarExporter) -> StateReconstructor {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let mut state = StateReconstructor::from_genesis(&genesis).with_hooks(exporter.clone());
+    replay_blocks(&mut state, &FileSource::new("tests/fixtures/blocks"), 1, 3)
+        .await
+        .unwrap();
+    state
+}
+
+#[tokio::test]
+async fn drops_rows_of_reverted_and_discarded_blocks() {
+    let dir = temp_dir("columnar-revert");
+    let exporter = ColumnarExporter::new(&dir, ExportFormat::Csv, 2);
+    let mut state = replay_into(&exporter).await;
+    state.revert_blocks(1).unwrap();
+
+    // 0xdd has no funds, so the second transfer fails and block 3 is discarded
+    let transfer = |from: u8| Transaction {
+        from: H160::repeat_byte(from),
+        to: Some(H160::repeat_byte(0xcc)),
+        value: 1.into(),
+        gas: 21_000.into(),
+        ..Default::default()
+    };
+    let failing = Block {
+        number: Some(3.into()),
+        parent_hash: state.head_hash.unwrap(),
+        gas_limit: 30_000_000.into(),
+        transactions: vec![transfer(0xbb), transfer(0xdd)],
+        ..Default::default()
+    };
+    let receipts = vec![TransactionReceipt::default(); 2];
+    assert!(state.apply_block(&failing, &receipts).is_err());
+
+    let files = exporter.finish().unwrap();
+    let blocks = fs::read_to_string(dir.join("blocks/0000000002-0000000003.csv")).unwrap();
+    assert_eq!(blocks.lines().count(), 2);
+    assert!(blocks.lines().nth(1).unwrap().starts_with("2,"));
+    for path in &files {
+        let rows = fs::read_to_string(path).unwrap();
+        assert!(
+            !rows.lines().any(|row| row.starts_with("3,")),
+            "{} has rows of block 3",
+            path.display()
+        );
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn reverting_written_blocks_is_an_error() {
+    let dir = temp_dir("columnar-revert-written");
+    let exporter = ColumnarExporter::new(&dir, ExportFormat::Csv, 2);
+    let mut state = replay_into(&exporter).await;
+
+    // blocks 0-1 were written when block 2 started
+    state.revert_blocks(3).unwrap();
+    let error = exporter.finish().unwrap_err().to_string();
+    assert!(error.contains("already written"), "{}", error);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn never_overwrites_existing_files() {
+    let dir = temp_dir("columnar-overwrite");
+    export(&dir, ExportFormat::Csv).await;
+    let before = fs::read_to_string(dir.join("blocks/0000000000-0000000001.csv")).unwrap();
+
+    let exporter = ColumnarExporter::new(&dir, ExportFormat::Csv, 2);
+    replay_into(&exporter).await;
+    let error = exporter.finish().unwrap_err().to_string();
+    assert!(error.contains("0000000000-0000000001.csv"), "{}", error);
+    assert_eq!(
+        fs::read_to_string(dir.join("blocks/0000000000-0000000001.csv")).unwrap(),
+        before
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
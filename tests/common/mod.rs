@@ -0,0 +1,157 @@
+// every test crate includes this module but uses only some of it
+#![allow(dead_code)]
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use ethers::types::{Address, U256};
+use ethers::utils::get_contract_address;
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::{load_genesis_from_file, Genesis};
+use evm_state_reconstructing::utils::core::reconstruct::{
+    reconstruct_from_source, reconstruct_with_mode,
+};
+use evm_state_reconstructing::utils::core::reconstructor::{ReconstructionMode, Reconstructor};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+pub const GENESIS: &str = "tests/fixtures/genesis.json";
+/// Archived blocks 1 to 3: transfers from 0xaa, a contract deployment and a call to it.
+pub const BLOCKS: &str = "tests/fixtures/blocks";
+
+pub fn genesis() -> Genesis {
+    load_genesis_from_file(GENESIS).unwrap()
+}
+
+/// Replays the fixture blocks 1 to `to` on top of the fixture genesis.
+pub async fn reconstruct(to: u64) -> StateReconstructor {
+    reconstruct_from_source(&genesis(), &FileSource::new(BLOCKS), 1, to)
+        .await
+        .unwrap()
+}
+
+/// Like `reconstruct`, with the given reconstruction mode.
+pub async fn reconstruct_with(mode: ReconstructionMode, to: u64) -> Box<dyn Reconstructor> {
+    reconstruct_with_mode(mode, &genesis(), &FileSource::new(BLOCKS), 1, to)
+        .await
+        .unwrap()
+}
+
+/// The contract 0xaa deploys in block 2.
+pub fn contract() -> Address {
+    get_contract_address(account(0xaa), 1u64)
+}
+
+pub fn account(byte: u8) -> Address {
+    Address::repeat_byte(byte)
+}
+
+pub fn ether(amount: u64) -> U256 {
+    U256::exp10(18) * amount
+}
+
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+    Json(String),
+    Status(u16),
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    blocks: BTreeMap<u64, MockResponse>,
+    info_status: Option<u16>,
+}
+
+/// In-process stand-in for a wvm-archiver server, serving `/v1/info` and
+/// `/v1/block/raw/{n}` from fixture files.
+pub struct MockArchiver {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockArchiver {
+    pub async fn start() -> Self {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let app = Router::new()
+            .route("/v1/info", get(info))
+            .route("/v1/block/raw/:block_nr", get(raw_block))
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        Self { url, state }
+    }
+
+    /// Serves every `<n>.json` fixture found in `dir` as block `n`.
+    pub async fn with_fixtures(dir: &str) -> Self {
+        let archiver = Self::start().await;
+        archiver.load_fixtures(dir);
+        archiver
+    }
+
+    pub fn load_fixtures(&self, dir: &str) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let block_nr: u64 = path.file_stem().unwrap().to_str().unwrap().parse().unwrap();
+            let body = std::fs::read_to_string(&path).unwrap();
+            self.set_block(block_nr, MockResponse::Json(body));
+        }
+    }
+
+    pub fn set_block(&self, block_nr: u64, response: MockResponse) {
+        self.state.lock().unwrap().blocks.insert(block_nr, response);
+    }
+
+    pub fn remove_block(&self, block_nr: u64) {
+        self.state.lock().unwrap().blocks.remove(&block_nr);
+    }
+
+    pub fn fail_info(&self, status: u16) {
+        self.state.lock().unwrap().info_status = Some(status);
+    }
+}
+
+async fn info(State(state): State<Arc<Mutex<MockState>>>) -> Response {
+    let state = state.lock().unwrap();
+    if let Some(status) = state.info_status {
+        return StatusCode::from_u16(status).unwrap().into_response();
+    }
+
+    let first = state.blocks.keys().next().copied();
+    let last = state.blocks.keys().next_back().copied();
+    axum::Json(json!({
+        "first_livesync_archived_block": first,
+        "last_livesync_archived_block": last,
+        "first_backfill_archived_block": first,
+        "last_backfill_archived_block": last,
+        "livesync_start_block": first,
+        "total_archived_blocks": state.blocks.len(),
+        "blocks_behind_live_blockheight": 0,
+        "network_name": "mocknet",
+        "network_chain_id": 1337,
+    }))
+    .into_response()
+}
+
+async fn raw_block(
+    State(state): State<Arc<Mutex<MockState>>>,
+    Path(block_nr): Path<u64>,
+) -> Response {
+    match state.lock().unwrap().blocks.get(&block_nr) {
+        Some(MockResponse::Json(body)) => (
+            [(axum::http::header::CONTENT_TYPE, "application/json")],
+            body.clone(),
+        )
+            .into_response(),
+        Some(MockResponse::Status(status)) => {
+            StatusCode::from_u16(*status).unwrap().into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
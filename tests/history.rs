@@ -0,0 +1,136 @@
+mod common;
+
+use common::{account, ether, genesis, BLOCKS};
+use ethers::types::{Address, H256, U256};
+use ethers::utils::{get_contract_address, keccak256};
+use evm_state_reconstructing::utils::core::block_source::{BlockSource, FileSource};
+use evm_state_reconstructing::utils::core::evm_exec::{CallRequest, StateReconstructor};
+use evm_state_reconstructing::utils::core::genesis_load::AccountAlloc;
+use evm_state_reconstructing::utils::core::reconstruct::replay_blocks;
+
+const GAS_PRICE: u64 = 1_000_000_000;
+
+fn balance_at(state: &StateReconstructor, byte: u8, block: u64) -> Option<U256> {
+    state
+        .account_state_at(account(byte), block)
+        .unwrap()
+        .map(|account| account.balance)
+}
+
+async fn replay_with_history(retention: Option<u64>) -> StateReconstructor {
+    let mut state = StateReconstructor::from_genesis(&genesis()).with_history(retention);
+    replay_blocks(&mut state, &FileSource::new(BLOCKS), 1, 3)
+        .await
+        .unwrap();
+    state
+}
+
+#[tokio::test]
+async fn queries_state_as_of_past_blocks() {
+    let state = replay_with_history(None).await;
+    let fee = U256::from(21_000 * GAS_PRICE);
+
+    assert_eq!(balance_at(&state, 0xaa, 0), Some(ether(1000)));
+    assert_eq!(balance_at(&state, 0xaa, 1), Some(ether(999) - fee));
+    assert_eq!(balance_at(&state, 0xbb, 0), Some(ether(5)));
+    assert_eq!(balance_at(&state, 0xbb, 2), Some(ether(6)));
+    assert_eq!(balance_at(&state, 0xcc, 2), None);
+    assert_eq!(balance_at(&state, 0xcc, 3), Some(ether(3)));
+
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+    assert!(state.account_state_at(contract, 1).unwrap().is_none());
+    let deployed = state.account_state_at(contract, 2).unwrap().unwrap();
+    assert!(!deployed.code.is_empty());
+    assert_eq!(
+        state.storage_at(contract, H256::zero(), 1).unwrap(),
+        H256::zero()
+    );
+    assert_eq!(
+        state.storage_at(contract, H256::zero(), 2).unwrap(),
+        H256::from_low_u64_be(42)
+    );
+    assert!(state.account_state_at(account(0xaa), 4).is_err());
+}
+
+#[tokio::test]
+async fn calls_against_past_blocks() {
+    let state = replay_with_history(None).await;
+    let request = CallRequest {
+        to: Some(get_contract_address(Address::repeat_byte(0xaa), 1u64)),
+        ..Default::default()
+    };
+
+    let before = state.call_at(&request, 1).unwrap();
+    assert!(before.success);
+    assert!(before.output.is_empty());
+
+    let after = state.call_at(&request, 2).unwrap();
+    assert_eq!(H256::from_slice(&after.output), H256::from_low_u64_be(42));
+}
+
+#[tokio::test]
+async fn blockhash_serves_recorded_hashes() {
+    // PUSH1 0x00 CALLDATALOAD BLOCKHASH PUSH1 0x00 MSTORE PUSH1 0x20 PUSH1 0x00 RETURN
+    let reader = Address::repeat_byte(0xee);
+    let mut genesis = genesis();
+    genesis.alloc.insert(
+        reader,
+        AccountAlloc {
+            code: "0x6000354060005260206000f3".to_string(),
+            ..Default::default()
+        },
+    );
+    let mut state = StateReconstructor::from_genesis(&genesis).with_history(None);
+    replay_blocks(&mut state, &FileSource::new(BLOCKS), 1, 3)
+        .await
+        .unwrap();
+    let blockhash = |number: u64, block: u64| {
+        let request = CallRequest {
+            to: Some(reader),
+            data: Some(H256::from_low_u64_be(number).as_bytes().to_vec().into()),
+            ..Default::default()
+        };
+        state
+            .call_at(&request, block)
+            .map(|outcome| H256::from_slice(&outcome.output))
+    };
+
+    // at the head and in the past
+    assert_eq!(blockhash(2, 3).unwrap(), H256::from(keccak256("block-2")));
+    assert_eq!(blockhash(1, 2).unwrap(), H256::from(keccak256("block-1")));
+    // the genesis hash was never recorded
+    assert!(blockhash(0, 1).is_err());
+}
+
+#[tokio::test]
+async fn retention_prunes_old_blocks() {
+    let state = replay_with_history(Some(1)).await;
+
+    assert!(state.account_state_at(account(0xaa), 0).is_err());
+    assert!(state.account_state_at(account(0xaa), 1).is_err());
+    assert_eq!(balance_at(&state, 0xbb, 2), Some(ether(6)));
+    assert_eq!(balance_at(&state, 0xcc, 2), None);
+    assert_eq!(balance_at(&state, 0xcc, 3), Some(ether(3)));
+}
+
+#[tokio::test]
+async fn history_follows_reverts() {
+    let mut state = replay_with_history(None).await;
+    state.revert_blocks(1).unwrap();
+    assert!(state.account_state_at(account(0xcc), 3).is_err());
+
+    let (block, receipts) = FileSource::new("tests/fixtures/reorg")
+        .get_block(3)
+        .await
+        .unwrap();
+    state.apply_block(&block, &receipts).unwrap();
+
+    assert_eq!(balance_at(&state, 0xcc, 3), None);
+    assert_eq!(balance_at(&state, 0xbb, 2), Some(ether(6)));
+    assert_eq!(
+        balance_at(&state, 0xbb, 3),
+        state
+            .get_account_state(account(0xbb))
+            .map(|acc| acc.balance)
+    );
+}
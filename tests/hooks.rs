@@ -0,0 +1,258 @@
+use ethers::types::{Address, Block, Log, Transaction, TransactionReceipt, H160, H256, U256};
+use ethers::utils::get_contract_address;
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::evm_exec::{CallOutcome, StateReconstructor};
+use evm_state_reconstructing::utils::core::genesis_load::{load_genesis_from_file, AccountAlloc};
+use evm_state_reconstructing::utils::core::hooks::{ReplayHooks, StateChange};
+use evm_state_reconstructing::utils::core::reconstruct::replay_blocks;
+use evm_state_reconstructing::utils::core::selection::Selection;
+use revm::interpreter::Interpreter;
+use revm::{Database, EvmContext, Inspector};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    BlockStart(u64),
+    Tx(H256, bool),
+    Log(Log),
+    StateChange(StateChange),
+    BlockEnd(u64),
+    Discarded(u64),
+    Revert(u64),
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<Event>>>);
+
+impl Recorder {
+    fn events(&self) -> Vec<Event> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, event: Event) {
+        self.0.lock().unwrap().push(event);
+    }
+}
+
+impl ReplayHooks for Recorder {
+    fn on_block_start(&mut self, block: &Block<Transaction>) {
+        self.push(Event::BlockStart(block.number.unwrap().as_u64()));
+    }
+
+    fn on_tx(&mut self, tx: &Transaction, outcome: &CallOutcome) {
+        self.push(Event::Tx(tx.hash, outcome.success));
+    }
+
+    fn on_log(&mut self, _tx: &Transaction, log: &Log) {
+        self.push(Event::Log(log.clone()));
+    }
+
+    fn on_state_change(&mut self, change: &StateChange) {
+        self.push(Event::StateChange(change.clone()));
+    }
+
+    fn on_block_end(&mut self, block: &Block<Transaction>) {
+        self.push(Event::BlockEnd(block.number.unwrap().as_u64()));
+    }
+
+    fn on_block_discarded(&mut self, block: &Block<Transaction>) {
+        self.push(Event::Discarded(block.number.unwrap().as_u64()));
+    }
+
+    fn on_revert(&mut self, number: u64) {
+        self.push(Event::Revert(number));
+    }
+}
+
+#[derive(Clone, Default)]
+struct StepCounter(Arc<AtomicUsize>);
+
+impl<DB: Database> Inspector<DB> for StepCounter {
+    fn step(&mut self, _interp: &mut Interpreter, _context: &mut EvmContext<DB>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[tokio::test]
+async fn hooks_follow_the_replay() {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let recorder = Recorder::default();
+    let mut state = StateReconstructor::from_genesis(&genesis).with_hooks(recorder.clone());
+    replay_blocks(&mut state, &FileSource::new("tests/fixtures/blocks"), 1, 3)
+        .await
+        .unwrap();
+    let events = recorder.events();
+
+    let outline: Vec<String> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::BlockStart(n) => Some(format!("start {}", n)),
+            Event::Tx(_, success) => Some(format!("tx {}", success)),
+            Event::BlockEnd(n) => Some(format!("end {}", n)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        outline,
+        [
+            "start 1", "tx true", "end 1", "start 2", "tx true", "end 2", "start 3", "tx true",
+            "tx true", "end 3"
+        ]
+    );
+
+    let changes: Vec<&StateChange> = events
+        .iter()
+        .filter_map(|event| match event {
+            Event::StateChange(change) => Some(change),
+            _ => None,
+        })
+        .collect();
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+    let deployed = changes
+        .iter()
+        .find(|change| change.address == contract)
+        .unwrap();
+    assert!(deployed.created);
+    assert_eq!(deployed.nonce, (U256::zero(), U256::one()));
+    assert_eq!(
+        deployed.storage,
+        vec![(H256::zero(), H256::zero(), H256::from_low_u64_be(42))]
+    );
+
+    // the last change of an account leaves it as the final state has it
+    let first_funding = changes
+        .iter()
+        .find(|change| change.address == H160::repeat_byte(0xcc))
+        .unwrap();
+    assert_eq!(first_funding.balance.0, U256::zero());
+    assert!(first_funding.balance.1 > U256::zero());
+    let sender = changes
+        .iter()
+        .rev()
+        .find(|change| change.address == H160::repeat_byte(0xaa))
+        .unwrap();
+    assert_eq!(
+        sender.nonce.1,
+        state.accounts[&H160::repeat_byte(0xaa)].nonce
+    );
+    assert_eq!(
+        sender.balance.1,
+        state.accounts[&H160::repeat_byte(0xaa)].balance
+    );
+}
+
+#[tokio::test]
+async fn state_changes_ignore_the_selection() {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let source = FileSource::new("tests/fixtures/blocks");
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+
+    let (full, selective) = (Recorder::default(), Recorder::default());
+    let mut state = StateReconstructor::from_genesis(&genesis).with_hooks(full.clone());
+    replay_blocks(&mut state, &source, 1, 3).await.unwrap();
+    let mut state = StateReconstructor::from_genesis(&genesis)
+        .with_selection(Selection::new([contract]))
+        .with_hooks(selective.clone());
+    replay_blocks(&mut state, &source, 1, 3).await.unwrap();
+
+    // unselected accounts still report their real prior nonce and balance
+    assert!(!state.accounts.contains_key(&H160::repeat_byte(0xaa)));
+    assert_eq!(selective.events(), full.events());
+}
+
+#[tokio::test]
+async fn discarded_and_reverted_blocks_are_reported() {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let recorder = Recorder::default();
+    let mut state = StateReconstructor::from_genesis(&genesis).with_hooks(recorder.clone());
+    replay_blocks(&mut state, &FileSource::new("tests/fixtures/blocks"), 1, 2)
+        .await
+        .unwrap();
+
+    // 0xdd has no funds, so the second transaction fails after the first was executed
+    let transfer = |from: u8, hash: u64| Transaction {
+        hash: H256::from_low_u64_be(hash),
+        from: H160::repeat_byte(from),
+        to: Some(H160::repeat_byte(0xcc)),
+        value: 1.into(),
+        gas: 21_000.into(),
+        ..Default::default()
+    };
+    let failing = Block {
+        number: Some(3.into()),
+        parent_hash: state.head_hash.unwrap(),
+        gas_limit: 30_000_000.into(),
+        transactions: vec![transfer(0xbb, 1), transfer(0xdd, 2)],
+        ..Default::default()
+    };
+    let receipts = vec![TransactionReceipt::default(); 2];
+    let before = recorder.events().len();
+    assert!(state.apply_block(&failing, &receipts).is_err());
+
+    let events = recorder.events().split_off(before);
+    assert_eq!(events.first(), Some(&Event::BlockStart(3)));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Event::StateChange(_))));
+    assert_eq!(events.last(), Some(&Event::Discarded(3)));
+    assert!(!events.contains(&Event::BlockEnd(3)));
+
+    state.revert_blocks(1).unwrap();
+    assert_eq!(recorder.events().last(), Some(&Event::Revert(1)));
+}
+
+#[test]
+fn inspector_and_logs() {
+    // PUSH1 0x07 PUSH1 0x20 PUSH1 0x00 LOG1 STOP
+    let emitter = H160::repeat_byte(0xee);
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    genesis.alloc.insert(
+        emitter,
+        AccountAlloc {
+            code: "0x600760206000a100".to_string(),
+            ..Default::default()
+        },
+    );
+
+    let steps = StepCounter::default();
+    let recorder = Recorder::default();
+    let mut state = StateReconstructor::from_genesis(&genesis)
+        .with_inspector(steps.clone())
+        .with_hooks(recorder.clone());
+
+    let tx = Transaction {
+        hash: H256::repeat_byte(1),
+        from: H160::repeat_byte(0xaa),
+        to: Some(emitter),
+        gas: 100_000.into(),
+        block_number: Some(1.into()),
+        transaction_index: Some(0.into()),
+        ..Default::default()
+    };
+    let block = Block {
+        number: Some(1.into()),
+        gas_limit: 30_000_000.into(),
+        transactions: vec![tx],
+        ..Default::default()
+    };
+    state
+        .apply_block(&block, &[TransactionReceipt::default()])
+        .unwrap();
+
+    assert_eq!(steps.0.load(Ordering::Relaxed), 5);
+    let logs: Vec<Log> = recorder
+        .events()
+        .into_iter()
+        .filter_map(|event| match event {
+            Event::Log(log) => Some(log),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(logs.len(), 1);
+    assert_eq!(logs[0].address, emitter);
+    assert_eq!(logs[0].topics, vec![H256::from_low_u64_be(7)]);
+    assert_eq!(logs[0].data.to_vec(), vec![0u8; 32]);
+    assert_eq!(logs[0].transaction_hash, Some(H256::repeat_byte(1)));
+    assert_eq!(logs[0].transaction_log_index, Some(0.into()));
+}
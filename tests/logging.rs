@@ -0,0 +1,67 @@
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::genesis_load::load_genesis_from_file;
+use evm_state_reconstructing::utils::core::reconstruct::reconstruct_from_source;
+use serde_json::Value;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn replay_emits_structured_events() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    reconstruct_from_source(&genesis, &FileSource::new("tests/fixtures/blocks"), 1, 3)
+        .await
+        .unwrap();
+
+    let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+    let events: Vec<Value> = output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    let blocks: Vec<&Value> = events
+        .iter()
+        .filter(|event| event["fields"]["message"] == "reconstructed block")
+        .collect();
+    assert_eq!(blocks.len(), 3);
+    for (number, event) in (1..=3).zip(&blocks) {
+        assert_eq!(event["span"]["name"], "block");
+        assert_eq!(event["span"]["number"], number);
+        assert!(event["fields"]["duration_ms"].is_u64());
+        assert_eq!(event["spans"][0]["name"], "replay_blocks");
+        assert_eq!(event["spans"][0]["to"], 3);
+    }
+
+    let transactions: Vec<&Value> = events
+        .iter()
+        .filter(|event| event["fields"]["message"] == "applied transaction")
+        .collect();
+    assert!(!transactions.is_empty());
+    assert!(transactions
+        .iter()
+        .all(|event| event["fields"]["tx_hash"].is_string()));
+    // account states are only dumped at trace level
+    assert!(!output.contains("account states after transaction"));
+}
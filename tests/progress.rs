@@ -0,0 +1,103 @@
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::load_genesis_from_file;
+use evm_state_reconstructing::utils::core::metrics::{spawn_metrics_server, ReplayMetrics};
+use evm_state_reconstructing::utils::core::progress::Progress;
+use evm_state_reconstructing::utils::core::reconstruct::replay_blocks_with_progress;
+use std::sync::{Arc, Mutex};
+
+async fn replay(from: u64, to: u64, observer: &ReplayMetrics) -> StateReconstructor {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let mut state = StateReconstructor::from_genesis(&genesis);
+    replay_blocks_with_progress(
+        &mut state,
+        &FileSource::new("tests/fixtures/blocks"),
+        from,
+        to,
+        observer,
+    )
+    .await
+    .unwrap();
+    state
+}
+
+/// The value of an unlabelled sample in Prometheus' text format.
+fn sample(metrics: &str, name: &str) -> f64 {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no sample {} in\n{}", name, metrics))
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn observer_sees_every_block() {
+    let updates: Mutex<Vec<Progress>> = Mutex::default();
+    let observer = |progress: &Progress| updates.lock().unwrap().push(progress.clone());
+
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let mut state = StateReconstructor::from_genesis(&genesis);
+    replay_blocks_with_progress(
+        &mut state,
+        &FileSource::new("tests/fixtures/blocks"),
+        1,
+        4,
+        &observer,
+    )
+    .await
+    .unwrap_err();
+
+    let updates = updates.into_inner().unwrap();
+    // the fixtures stop at block 3, where the replay stops too
+    assert_eq!(
+        updates.iter().map(|p| p.block_number).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    let last = updates.last().unwrap();
+    assert_eq!((last.from, last.to, last.blocks), (1, 4, 3));
+    assert_eq!(
+        last.transactions,
+        updates.iter().map(|p| p.block_transactions).sum::<u64>()
+    );
+    assert!(last.transactions > 0 && last.gas_used > 0);
+    assert_eq!(last.remaining_blocks(), 1);
+    assert!(last.eta().is_some());
+    assert!(last.blocks_per_sec() > 0.0 && last.gas_per_sec() > 0.0);
+
+    // measured on the first block, then only every STATE_SIZE_INTERVAL
+    assert_eq!(updates[0].state_size.accounts, 3);
+    assert_eq!(last.state_size, updates[0].state_size);
+}
+
+#[tokio::test]
+async fn serves_prometheus_metrics() {
+    let metrics = Arc::new(ReplayMetrics::new().unwrap());
+    let (addr, _server) = spawn_metrics_server(metrics.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+
+    let state = replay(1, 3, &metrics).await;
+    let body = reqwest::get(format!("http://{}/metrics", addr))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert_eq!(sample(&body, "evm_reconstruct_blocks_total"), 3.0);
+    assert_eq!(sample(&body, "evm_reconstruct_fetch_errors_total"), 0.0);
+    assert_eq!(sample(&body, "evm_reconstruct_height"), 3.0);
+    assert_eq!(sample(&body, "evm_reconstruct_target_height"), 3.0);
+    assert_eq!(sample(&body, "evm_reconstruct_eta_seconds"), 0.0);
+    assert_eq!(
+        sample(&body, "evm_reconstruct_block_fetch_seconds_count"),
+        3.0
+    );
+    assert!(sample(&body, "evm_reconstruct_transactions_total") > 0.0);
+    assert!(sample(&body, "evm_reconstruct_gas_used_total") > 0.0);
+    assert_eq!(
+        sample(&body, "evm_reconstruct_state_accounts"),
+        state.accounts.len() as f64
+    );
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{contract, genesis, reconstruct, BLOCKS};
+use ethers::types::{Address, H256, U256};
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::proof::{hashed_address, verify_account_proof};
+use evm_state_reconstructing::utils::core::reconstruct::replay_blocks;
+
+const EMPTY_ROOT: &str = "0x56e81f171bcc55a6ff8345e692c0f86e5b48e01b996cadc001622fb5e363b421";
+
+#[tokio::test]
+async fn state_root_tracks_state_changes() {
+    let empty = StateReconstructor::new();
+    assert_eq!(empty.state_root(), EMPTY_ROOT.parse().unwrap());
+
+    let mut state = StateReconstructor::from_genesis(&genesis());
+    let genesis_root = state.state_root();
+    assert_ne!(genesis_root, empty.state_root());
+
+    replay_blocks(&mut state, &FileSource::new(BLOCKS), 1, 1)
+        .await
+        .unwrap();
+    let block_1_root = state.state_root();
+    assert_ne!(block_1_root, genesis_root);
+
+    // the root only depends on the state, not on how it was reached
+    assert_eq!(reconstruct(1).await.state_root(), block_1_root);
+    state.revert_blocks(1).unwrap();
+    assert_eq!(state.state_root(), genesis_root);
+}
+
+#[tokio::test]
+async fn proves_accounts_and_storage() {
+    let state = reconstruct(3).await;
+    let root = state.state_root();
+
+    let sender = state.get_proof(Address::repeat_byte(0xaa), &[]);
+    assert_eq!(sender.nonce.as_u64(), 3);
+    assert_eq!(sender.storage_hash, EMPTY_ROOT.parse().unwrap());
+    verify_account_proof(root, &sender).unwrap();
+
+    let proof = state.get_proof(contract(), &[H256::zero(), H256::from_low_u64_be(1)]);
+    assert_ne!(proof.storage_hash, EMPTY_ROOT.parse().unwrap());
+    assert_eq!(proof.storage_proof[0].value, U256::from(42));
+    assert_eq!(proof.storage_proof[1].value, U256::zero());
+    verify_account_proof(root, &proof).unwrap();
+
+    let missing = state.get_proof(Address::repeat_byte(0xdd), &[]);
+    assert_eq!(missing.balance, U256::zero());
+    verify_account_proof(root, &missing).unwrap();
+}
+
+#[tokio::test]
+async fn rejects_tampered_proofs() {
+    let state = reconstruct(3).await;
+    let root = state.state_root();
+    let proof = state.get_proof(contract(), &[H256::zero()]);
+
+    let mut balance = proof.clone();
+    balance.balance += U256::one();
+    assert!(verify_account_proof(root, &balance).is_err());
+
+    let mut value = proof.clone();
+    value.storage_proof[0].value = U256::from(43);
+    assert!(verify_account_proof(root, &value).is_err());
+
+    let mut absent = proof.clone();
+    absent.storage_proof[0].value = U256::zero();
+    assert!(verify_account_proof(root, &absent).is_err());
+
+    let stale_root = reconstruct(1).await.state_root();
+    assert!(verify_account_proof(stale_root, &proof).is_err());
+}
+
+#[tokio::test]
+async fn hashed_keys_follow_the_state() {
+    let mut state = StateReconstructor::from_genesis(&genesis()).with_hashed_keys();
+    replay_blocks(&mut state, &FileSource::new(BLOCKS), 1, 3)
+        .await
+        .unwrap();
+
+    let keys = state.hashed_keys.as_ref().unwrap();
+    assert_eq!(keys.len(), state.accounts.len());
+    let (address, account) = state
+        .account_by_hashed_key(hashed_address(contract()))
+        .unwrap();
+    assert_eq!(address, contract());
+    assert_eq!(
+        account.storage.get(&H256::zero()),
+        Some(&H256::from_low_u64_be(42))
+    );
+
+    // block 3 funds 0xcc for the first time
+    let recipient = hashed_address(Address::repeat_byte(0xcc));
+    assert!(state.account_by_hashed_key(recipient).is_some());
+    state.revert_blocks(1).unwrap();
+    assert!(state.account_by_hashed_key(recipient).is_none());
+    assert!(reconstruct(3)
+        .await
+        .account_by_hashed_key(recipient)
+        .is_none());
+}
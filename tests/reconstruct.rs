@@ -0,0 +1,277 @@
+mod common;
+
+use common::{account, ether, genesis, MockArchiver, MockResponse, GENESIS};
+use ethers::types::{Address, Block, Transaction, TransactionReceipt, H256, U256};
+use ethers::utils::{get_contract_address, keccak256};
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::AccountAlloc;
+use evm_state_reconstructing::utils::core::journal::StateJournal;
+use evm_state_reconstructing::utils::core::networks::{Networks, NETWORK_NAMES};
+use evm_state_reconstructing::utils::core::reconstruct::{
+    follow_network, reconstruct_network, replay_blocks,
+};
+use evm_state_reconstructing::utils::core::wvm_archiver::get_block_from_wvm;
+use std::time::Duration;
+
+const GAS_PRICE: u64 = 1_000_000_000;
+
+fn network(archiver: &MockArchiver) -> Networks {
+    Networks::custom("http://127.0.0.1:1", Some(archiver.url.clone()), GENESIS).unwrap()
+}
+
+fn transfer_fee() -> U256 {
+    U256::from(21_000 * GAS_PRICE)
+}
+
+fn balance(state: &StateReconstructor, byte: u8) -> Option<U256> {
+    state
+        .get_account_state(account(byte))
+        .map(|acc| acc.balance)
+}
+
+async fn follow_for(network: Networks, state: &mut StateReconstructor) -> Option<anyhow::Error> {
+    match tokio::time::timeout(
+        Duration::from_millis(500),
+        follow_network(network, state, Duration::from_millis(20)),
+    )
+    .await
+    {
+        Ok(result) => result.err(),
+        Err(_) => None,
+    }
+}
+
+#[tokio::test]
+async fn reconstructs_archived_blocks() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    let state = reconstruct_network(network(&archiver)).await.unwrap();
+
+    assert_eq!(state.block_number, 3);
+    assert_eq!(state.head_hash, Some(H256::from(keccak256("block-3"))));
+    assert_eq!(balance(&state, 0xcc), Some(ether(3)));
+    assert_eq!(
+        balance(&state, 0xbb),
+        Some(ether(5) + ether(1) - ether(1) - transfer_fee())
+    );
+
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+    let contract = state.get_account_state(contract).unwrap();
+    assert!(!contract.code.is_empty());
+    assert_eq!(
+        contract.storage.get(&H256::zero()),
+        Some(&H256::from_low_u64_be(42))
+    );
+}
+
+#[test]
+fn malformed_rpc_url_is_an_error() {
+    assert!(Networks::custom("not a url", None, GENESIS).is_err());
+}
+
+#[test]
+fn missing_genesis_is_an_error() {
+    let missing = "tests/fixtures/no-such-genesis.json";
+    assert!(Networks::custom("http://127.0.0.1:1", None, missing).is_err());
+}
+
+#[test]
+fn registry_networks_ship_their_genesis() {
+    for name in NETWORK_NAMES {
+        let network = Networks::by_name(name).unwrap().unwrap();
+        assert!(!network.genesis_file.alloc.is_empty(), "{}", name);
+    }
+    assert!(Networks::by_name("unknown").is_none());
+}
+
+#[tokio::test]
+async fn stops_at_missing_blocks() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    archiver.remove_block(2);
+    assert!(reconstruct_network(network(&archiver)).await.is_err());
+
+    let mut state = StateReconstructor::from_genesis(&genesis());
+    let source = network(&archiver).archiver_source();
+    assert!(replay_blocks(&mut state, &source, 1, 3).await.is_err());
+
+    assert_eq!(state.block_number, 1);
+    assert_eq!(state.head_hash, Some(H256::from(keccak256("block-1"))));
+    assert_eq!(balance(&state, 0xcc), None);
+}
+
+#[tokio::test]
+async fn malformed_block_is_an_error() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    archiver.set_block(2, MockResponse::Json("{\"block\": [1, 2".to_string()));
+
+    assert!(get_block_from_wvm(Some(archiver.url.clone()), 2)
+        .await
+        .is_err());
+
+    assert!(reconstruct_network(network(&archiver)).await.is_err());
+
+    let mut state = StateReconstructor::from_genesis(&genesis());
+    let source = network(&archiver).archiver_source();
+    assert!(replay_blocks(&mut state, &source, 1, 3).await.is_err());
+    assert_eq!(state.block_number, 1);
+}
+
+#[tokio::test]
+async fn server_errors_are_surfaced() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    archiver.set_block(3, MockResponse::Status(503));
+    assert!(get_block_from_wvm(Some(archiver.url.clone()), 3)
+        .await
+        .is_err());
+
+    archiver.fail_info(500);
+    assert!(reconstruct_network(network(&archiver)).await.is_err());
+}
+
+#[tokio::test]
+async fn reverts_blocks_through_the_journal() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    let mut state = reconstruct_network(network(&archiver)).await.unwrap();
+    let balance_at_2 = ether(5) + ether(1);
+
+    state.revert_blocks(1).unwrap();
+
+    assert_eq!(state.block_number, 2);
+    assert_eq!(state.head_hash, Some(H256::from(keccak256("block-2"))));
+    assert_eq!(balance(&state, 0xbb), Some(balance_at_2));
+    assert_eq!(balance(&state, 0xcc), None);
+}
+
+#[tokio::test]
+async fn follow_mode_switches_to_the_reorged_branch() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    let mut state = reconstruct_network(network(&archiver)).await.unwrap();
+    assert_eq!(state.block_number, 3);
+
+    // block 3 is replaced by 3b and the chain continues with 4b
+    archiver.load_fixtures("tests/fixtures/reorg");
+    assert!(follow_for(network(&archiver), &mut state).await.is_none());
+
+    assert_eq!(state.block_number, 4);
+    assert_eq!(state.head_hash, Some(H256::from(keccak256("block-4b"))));
+    assert_eq!(balance(&state, 0xcc), None);
+    assert_eq!(balance(&state, 0xdd), Some(ether(3)));
+    assert_eq!(
+        balance(&state, 0xbb),
+        Some(ether(5) + ether(1) + ether(5) - ether(3) - transfer_fee())
+    );
+}
+
+#[tokio::test]
+async fn reorg_deeper_than_the_journal_fails() {
+    let archiver = MockArchiver::with_fixtures("tests/fixtures/blocks").await;
+    let mut state = reconstruct_network(network(&archiver)).await.unwrap();
+    state.journal = StateJournal::new(0);
+
+    archiver.load_fixtures("tests/fixtures/reorg");
+    assert!(follow_for(network(&archiver), &mut state).await.is_some());
+    assert_eq!(state.block_number, 3);
+}
+
+fn transfer(from: u8, to: u8, value: U256) -> Transaction {
+    Transaction {
+        hash: H256::random(),
+        from: account(from),
+        to: Some(account(to)),
+        value,
+        gas: U256::from(21_000),
+        gas_price: Some(U256::from(GAS_PRICE)),
+        ..Default::default()
+    }
+}
+
+fn block(number: u64, transactions: Vec<Transaction>) -> Block<Transaction> {
+    Block {
+        number: Some(number.into()),
+        gas_limit: 30_000_000.into(),
+        hash: Some(H256::from(keccak256(format!("block-{}", number)))),
+        transactions,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn failing_transaction_discards_the_whole_block() {
+    let mut state = StateReconstructor::from_genesis(&genesis()).with_history(None);
+    let before = balance(&state, 0xaa);
+
+    // 0xdd has no funds, so the second transaction cannot be executed
+    let failing = block(
+        1,
+        vec![
+            transfer(0xaa, 0xcc, ether(1)),
+            transfer(0xdd, 0xcc, ether(1)),
+        ],
+    );
+    let receipts = vec![TransactionReceipt::default(); 2];
+    assert!(state.apply_block(&failing, &receipts).is_err());
+
+    assert_eq!(state.block_number, 0);
+    assert_eq!(state.head_hash, None);
+    assert!(state.journal.blocks.is_empty());
+    assert_eq!(balance(&state, 0xaa), before);
+    assert_eq!(balance(&state, 0xcc), None);
+    assert!(state.account_state_at(account(0xcc), 1).is_err());
+
+    let good = block(1, vec![transfer(0xaa, 0xcc, ether(1))]);
+    state
+        .apply_block(&good, &[TransactionReceipt::default()])
+        .unwrap();
+    assert_eq!(state.block_number, 1);
+    assert_eq!(balance(&state, 0xcc), Some(ether(1)));
+}
+
+#[test]
+fn transactions_see_their_block_header() {
+    // SSTORE(0, NUMBER) SSTORE(1, TIMESTAMP) SSTORE(2, COINBASE) SSTORE(3, BASEFEE)
+    let probe = account(0xee);
+    let mut genesis = genesis();
+    genesis.alloc.insert(
+        probe,
+        AccountAlloc {
+            code: "0x4360005542600155416002554860035500".to_string(),
+            ..Default::default()
+        },
+    );
+    let mut state = StateReconstructor::from_genesis(&genesis);
+
+    let author = account(0xf0);
+    let header = Block {
+        author: Some(author),
+        timestamp: U256::from(1_800_000_000u64),
+        base_fee_per_gas: Some(U256::from(GAS_PRICE / 4)),
+        ..block(
+            7,
+            vec![Transaction {
+                to: Some(probe),
+                gas: U256::from(200_000),
+                ..transfer(0xaa, 0xee, U256::zero())
+            }],
+        )
+    };
+    state
+        .apply_block(&header, &[TransactionReceipt::default()])
+        .unwrap();
+
+    let slot = |index: u64| state.accounts[&probe].storage[&H256::from_low_u64_be(index)];
+    assert_eq!(slot(0), H256::from_low_u64_be(7));
+    assert_eq!(slot(1), H256::from_low_u64_be(1_800_000_000));
+    assert_eq!(slot(2), H256::from(author));
+    assert_eq!(slot(3), H256::from_low_u64_be(GAS_PRICE / 4));
+    // the priority fee goes to the block's author
+    assert!(balance(&state, 0xf0).is_some_and(|balance| !balance.is_zero()));
+}
+
+#[test]
+fn mismatched_receipts_are_an_error() {
+    let mut state = StateReconstructor::from_genesis(&genesis());
+
+    let block = block(1, vec![transfer(0xaa, 0xcc, ether(1))]);
+    assert!(state.apply_block(&block, &[]).is_err());
+    assert_eq!(state.block_number, 0);
+    assert_eq!(balance(&state, 0xcc), None);
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use common::{genesis, reconstruct_with};
+use ethers::types::Address;
+use ethers::types::{
+    Action, ActionType, Block, Call, CallType, Create, CreateResult, Res, Trace, Transaction,
+    TransactionReceipt, H160, H256, U256,
+};
+use ethers::utils::{get_contract_address, keccak256};
+use evm_state_reconstructing::utils::core::reconstructor::ReconstructionMode;
+use evm_state_reconstructing::utils::core::state;
+use evm_state_reconstructing::utils::core::state_iter::KeyRange;
+
+const MODES: [ReconstructionMode; 2] = [
+    ReconstructionMode::ReceiptDriven,
+    ReconstructionMode::Reexecution,
+];
+
+#[tokio::test]
+async fn modes_are_interchangeable() {
+    let mut senders = Vec::new();
+    for mode in MODES {
+        let state = reconstruct_with(mode, 3).await;
+        assert_eq!(state.mode(), mode);
+        assert_eq!(state.head(), (3, Some(H256::from(keccak256("block-3")))));
+        senders.push(state.account(H160::repeat_byte(0xaa)).cloned().unwrap());
+    }
+
+    // plain transfers and fees come out the same either way
+    assert_eq!(senders[0].nonce.as_u64(), 3);
+    assert_eq!(senders[0].nonce, senders[1].nonce);
+    assert_eq!(senders[0].balance, senders[1].balance);
+}
+
+#[tokio::test]
+async fn snapshots_record_what_the_mode_knows() {
+    let executed = reconstruct_with(ReconstructionMode::Reexecution, 3).await;
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+    assert_eq!(
+        executed.storage_slot(contract, H256::zero()),
+        H256::from_low_u64_be(42)
+    );
+    assert_eq!(
+        executed.storage_slot(H160::repeat_byte(0xdd), H256::zero()),
+        H256::zero()
+    );
+
+    let snapshot = executed.snapshot().unwrap();
+    assert_eq!(snapshot.block_number, 3);
+    assert!(snapshot.state_root().is_some());
+
+    let approximated = reconstruct_with(ReconstructionMode::ReceiptDriven, 3)
+        .await
+        .snapshot()
+        .unwrap();
+    assert_eq!(approximated.block_number, 3);
+    assert_eq!(approximated.block_hash(), snapshot.block_hash());
+    assert_eq!(approximated.state_root(), None);
+}
+
+#[tokio::test]
+async fn receipts_match_execution_where_known() {
+    let executed = reconstruct_with(ReconstructionMode::Reexecution, 3).await;
+    let approximated = reconstruct_with(ReconstructionMode::ReceiptDriven, 3).await;
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+
+    for account in executed.accounts(KeyRange::all()) {
+        let address = account.unwrap().address;
+        let expected = executed.account(address).unwrap();
+        let actual = approximated.account(address).unwrap();
+        let unknown = approximated.unknown_fields(address);
+
+        assert_eq!(actual.nonce, expected.nonce, "nonce of {:?}", address);
+        if !unknown.balance {
+            assert_eq!(actual.balance, expected.balance, "balance of {:?}", address);
+        }
+        if !unknown.code {
+            assert_eq!(actual.code, expected.code, "code of {:?}", address);
+        }
+    }
+
+    let unknown = approximated.unknown_fields(contract);
+    assert!(unknown.code && unknown.storage && !unknown.balance);
+    assert!(approximated.account(contract).unwrap().storage.is_empty());
+    assert!(approximated
+        .unknown_fields(H160::repeat_byte(0xaa))
+        .is_empty());
+    assert!(executed.unknown_fields(contract).is_empty());
+}
+
+fn call_trace(from: u8, to: u8, value: u64, trace_address: Vec<usize>) -> Trace {
+    Trace {
+        action: Action::Call(Call {
+            from: H160::repeat_byte(from),
+            to: H160::repeat_byte(to),
+            value: value.into(),
+            call_type: CallType::Call,
+            ..Default::default()
+        }),
+        result: Some(Res::None),
+        trace_address,
+        subtraces: 0,
+        transaction_position: Some(0),
+        transaction_hash: None,
+        block_number: 1,
+        block_hash: H256::zero(),
+        action_type: ActionType::Call,
+        error: None,
+    }
+}
+
+#[test]
+fn traces_account_for_internal_transfers() {
+    let genesis = genesis();
+    let tx = Transaction {
+        from: H160::repeat_byte(0xaa),
+        to: Some(H160::repeat_byte(0xcc)),
+        value: 10.into(),
+        gas_price: Some(1.into()),
+        input: vec![1].into(),
+        ..Default::default()
+    };
+    let block = Block {
+        number: Some(1.into()),
+        transactions: vec![tx],
+        ..Default::default()
+    };
+    let receipts = vec![TransactionReceipt {
+        gas_used: Some(21_000.into()),
+        effective_gas_price: Some(1.into()),
+        status: Some(1.into()),
+        ..Default::default()
+    }];
+
+    let mut reverted = call_trace(0xcc, 0xee, 3, vec![1]);
+    reverted.error = Some("Reverted".to_string());
+    let mut create = call_trace(0xcc, 0xff, 1, vec![2]);
+    create.action = Action::Create(Create {
+        from: H160::repeat_byte(0xcc),
+        value: 1.into(),
+        ..Default::default()
+    });
+    create.result = Some(Res::Create(CreateResult {
+        address: H160::repeat_byte(0xff),
+        code: vec![0x60, 0x00].into(),
+        ..Default::default()
+    }));
+    let traces = vec![
+        call_trace(0xaa, 0xcc, 10, vec![]),
+        call_trace(0xcc, 0xdd, 4, vec![0]),
+        reverted,
+        call_trace(0xee, 0xdd, 3, vec![1, 0]),
+        create,
+    ];
+
+    let mut traced = state::StateReconstructor::from_genesis(&genesis);
+    traced
+        .apply_block_with_traces(&block, &receipts, Some(&traces))
+        .unwrap();
+    let balance = |state: &state::StateReconstructor, byte: u8| {
+        state
+            .get_account_state(H160::repeat_byte(byte))
+            .map(|account| account.balance)
+            .unwrap_or_default()
+    };
+    assert_eq!(balance(&traced, 0xcc), U256::from(5));
+    assert_eq!(balance(&traced, 0xdd), U256::from(4));
+    assert_eq!(balance(&traced, 0xee), U256::zero());
+    assert_eq!(balance(&traced, 0xff), U256::one());
+    let created = traced.get_account_state(H160::repeat_byte(0xff)).unwrap();
+    assert_eq!(created.code, vec![0x60, 0x00]);
+    assert_eq!(created.nonce, U256::one());
+    assert!(traced.unknown_fields(H160::repeat_byte(0xff)).storage);
+
+    // without traces the internal transfers are invisible
+    let mut untraced = state::StateReconstructor::from_genesis(&genesis);
+    untraced.apply_block(&block, &receipts).unwrap();
+    assert_eq!(balance(&untraced, 0xcc), U256::from(10));
+    assert_eq!(balance(&untraced, 0xdd), U256::zero());
+    assert_eq!(
+        balance(&untraced, 0xaa),
+        balance(&traced, 0xaa),
+        "fees and top-level transfers do not depend on traces"
+    );
+}
+
+#[test]
+fn failing_receipt_discards_the_whole_block() {
+    let genesis = genesis();
+    let transfer = |from: u8, value: u64| Transaction {
+        from: H160::repeat_byte(from),
+        to: Some(H160::repeat_byte(0xcc)),
+        value: value.into(),
+        gas_price: Some(1.into()),
+        ..Default::default()
+    };
+    // 0xdd has no funds to send
+    let block = Block {
+        number: Some(1.into()),
+        transactions: vec![transfer(0xaa, 10), transfer(0xdd, 10)],
+        ..Default::default()
+    };
+    let receipt = TransactionReceipt {
+        gas_used: Some(21_000.into()),
+        effective_gas_price: Some(1.into()),
+        status: Some(1.into()),
+        ..Default::default()
+    };
+    let receipts = vec![receipt.clone(), TransactionReceipt::default()];
+
+    let mut state = state::StateReconstructor::from_genesis(&genesis);
+    let before = state.accounts.clone();
+    assert!(state.apply_block(&block, &receipts).is_err());
+    assert_eq!(state.accounts.len(), before.len());
+    for (address, account) in &before {
+        assert_eq!(state.accounts[address].balance, account.balance);
+        assert_eq!(state.accounts[address].nonce, account.nonce);
+    }
+    assert_eq!(state.block_number, 0);
+
+    // the tip goes to the author, and nowhere when the block has none
+    let paid = Block {
+        author: Some(H160::repeat_byte(0xee)),
+        transactions: vec![transfer(0xaa, 10)],
+        ..block.clone()
+    };
+    state.apply_block(&paid, &receipts[..1]).unwrap();
+    let author = state.get_account_state(H160::repeat_byte(0xee)).unwrap();
+    assert_eq!(author.balance, U256::from(21_000));
+
+    let unpaid = Block {
+        number: Some(2.into()),
+        transactions: vec![transfer(0xaa, 10)],
+        ..block
+    };
+    state.apply_block(&unpaid, &[receipt]).unwrap();
+    assert!(state.get_account_state(H160::zero()).is_none());
+}
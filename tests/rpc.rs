@@ -0,0 +1,174 @@
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use ethers::providers::{Http, Provider};
+use ethers::types::{Block, Transaction, TransactionReceipt, H256, U64};
+use evm_state_reconstructing::utils::core::rpc::get_block_txs_receipts;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+/// A node holding a single block with two transactions.
+#[derive(Default)]
+struct MockNode {
+    /// Whether `eth_getBlockReceipts` exists; when it does it leaves out the second receipt.
+    block_receipts: bool,
+    /// HTTP status batch requests are answered with instead of their results, if any.
+    batch_status: Option<StatusCode>,
+    /// Methods called so far, in order.
+    calls: Mutex<Vec<String>>,
+}
+
+fn tx_hash(index: u64) -> H256 {
+    H256::from_low_u64_be(index + 1)
+}
+
+fn block() -> Block<Transaction> {
+    Block {
+        number: Some(U64::one()),
+        hash: Some(H256::repeat_byte(1)),
+        transactions: (0..2)
+            .map(|index| Transaction {
+                hash: tx_hash(index),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn receipt(index: u64) -> TransactionReceipt {
+    TransactionReceipt {
+        transaction_hash: tx_hash(index),
+        transaction_index: index.into(),
+        ..Default::default()
+    }
+}
+
+fn answer(node: &MockNode, request: &Value) -> Value {
+    let method = request["method"].as_str().unwrap().to_string();
+    node.calls.lock().unwrap().push(method.clone());
+    let result = match method.as_str() {
+        "eth_getBlockByNumber" => serde_json::to_value(block()).unwrap(),
+        "eth_getBlockReceipts" if node.block_receipts => json!([receipt(0)]),
+        "eth_getTransactionReceipt" => {
+            let hash: H256 = serde_json::from_value(request["params"][0].clone()).unwrap();
+            json!(receipt(hash.to_low_u64_be() - 1))
+        }
+        _ => {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": request["id"],
+                "error": {"code": -32601, "message": format!("the method {} does not exist", method)},
+            })
+        }
+    };
+    json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+}
+
+async fn handle(State(node): State<Arc<MockNode>>, Json(request): Json<Value>) -> Response {
+    match request {
+        Value::Array(_) if node.batch_status.is_some() => {
+            node.calls.lock().unwrap().push("batch".to_string());
+            node.batch_status.unwrap().into_response()
+        }
+        Value::Array(batch) => Json(
+            batch
+                .iter()
+                .map(|call| answer(&node, call))
+                .collect::<Value>(),
+        )
+        .into_response(),
+        call => Json(answer(&node, &call)).into_response(),
+    }
+}
+
+async fn start(node: MockNode) -> (Provider<Http>, Arc<MockNode>) {
+    let node = Arc::new(node);
+    let app = Router::new()
+        .route("/", post(handle))
+        .with_state(node.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (Provider::<Http>::try_from(url).unwrap(), node)
+}
+
+#[tokio::test]
+async fn falls_back_when_block_receipts_are_unsupported() {
+    let (provider, node) = start(MockNode::default()).await;
+
+    let (block, receipts) = get_block_txs_receipts(provider, 1).await.unwrap();
+
+    assert_eq!(block.transactions.len(), 2);
+    let hashes: Vec<H256> = receipts.iter().map(|r| r.transaction_hash).collect();
+    assert_eq!(hashes, vec![tx_hash(0), tx_hash(1)]);
+    assert_eq!(
+        node.calls.lock().unwrap()[..2],
+        ["eth_getBlockByNumber", "eth_getBlockReceipts"]
+    );
+}
+
+#[tokio::test]
+async fn missing_receipt_is_reported_without_falling_back() {
+    let (provider, node) = start(MockNode {
+        block_receipts: true,
+        ..Default::default()
+    })
+    .await;
+
+    let error = get_block_txs_receipts(provider, 1).await.unwrap_err();
+
+    assert!(
+        error.to_string().contains("Receipts not found"),
+        "{}",
+        error
+    );
+    assert!(error.to_string().contains(&format!("{:?}", tx_hash(1))));
+    assert_eq!(
+        *node.calls.lock().unwrap(),
+        ["eth_getBlockByNumber", "eth_getBlockReceipts"]
+    );
+}
+
+#[tokio::test]
+async fn falls_back_to_single_calls_when_batches_are_not_allowed() {
+    let (provider, node) = start(MockNode {
+        batch_status: Some(StatusCode::METHOD_NOT_ALLOWED),
+        ..Default::default()
+    })
+    .await;
+
+    let (_, receipts) = get_block_txs_receipts(provider, 1).await.unwrap();
+
+    assert_eq!(receipts.len(), 2);
+    assert_eq!(
+        *node.calls.lock().unwrap(),
+        [
+            "eth_getBlockByNumber",
+            "eth_getBlockReceipts",
+            "batch",
+            "eth_getTransactionReceipt",
+            "eth_getTransactionReceipt"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rate_limits_are_returned_without_falling_back() {
+    let (provider, node) = start(MockNode {
+        batch_status: Some(StatusCode::TOO_MANY_REQUESTS),
+        ..Default::default()
+    })
+    .await;
+
+    let error = get_block_txs_receipts(provider, 1).await.unwrap_err();
+
+    assert!(error.to_string().contains("429"), "{}", error);
+    assert_eq!(
+        *node.calls.lock().unwrap(),
+        ["eth_getBlockByNumber", "eth_getBlockReceipts", "batch"]
+    );
+}
@@ -0,0 +1,210 @@
+use ethers::middleware::Middleware;
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, BlockId, BlockNumber, TransactionRequest, H256, U256};
+use ethers::utils::get_contract_address;
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::genesis_load::load_genesis_from_file;
+use evm_state_reconstructing::utils::core::proof::verify_account_proof;
+use evm_state_reconstructing::utils::core::reconstruct::reconstruct_from_source;
+use evm_state_reconstructing::utils::core::rpc_server::{
+    spawn_server, SharedState, INVALID_REQUEST, MAX_BATCH_SIZE, PARSE_ERROR, SERVER_ERROR,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+async fn serve_fixtures() -> (Provider<Http>, SharedState) {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let state = reconstruct_from_source(&genesis, &FileSource::new("tests/fixtures/blocks"), 1, 3)
+        .await
+        .unwrap();
+    let state = Arc::new(RwLock::new(state));
+
+    let (addr, _) = spawn_server(state.clone(), "127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap();
+    let provider = Provider::<Http>::try_from(format!("http://{}", addr)).unwrap();
+    (provider, state)
+}
+
+#[tokio::test]
+async fn serves_reconstructed_accounts() {
+    let (provider, _state) = serve_fixtures().await;
+    let sender = Address::repeat_byte(0xaa);
+
+    assert_eq!(provider.get_chainid().await.unwrap(), U256::from(1337));
+    assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 3);
+    assert_eq!(
+        provider
+            .get_balance(Address::repeat_byte(0xcc), None)
+            .await
+            .unwrap(),
+        U256::exp10(18) * 3
+    );
+    assert_eq!(
+        provider.get_transaction_count(sender, None).await.unwrap(),
+        U256::from(3)
+    );
+    assert_eq!(
+        provider
+            .get_balance(Address::repeat_byte(0xdd), None)
+            .await
+            .unwrap(),
+        U256::zero()
+    );
+}
+
+#[tokio::test]
+async fn serves_contract_code_and_storage() {
+    let (provider, state) = serve_fixtures().await;
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+
+    let code = provider.get_code(contract, None).await.unwrap();
+    let expected = state
+        .read()
+        .await
+        .get_account_state(contract)
+        .unwrap()
+        .code
+        .clone();
+    assert!(!code.is_empty());
+    assert_eq!(code.to_vec(), expected);
+
+    let slot = provider
+        .get_storage_at(contract, H256::zero(), None)
+        .await
+        .unwrap();
+    assert_eq!(slot, H256::from_low_u64_be(42));
+    let empty = provider
+        .get_storage_at(contract, H256::from_low_u64_be(1), None)
+        .await
+        .unwrap();
+    assert_eq!(empty, H256::zero());
+}
+
+#[tokio::test]
+async fn rejects_historical_blocks() {
+    let (provider, _state) = serve_fixtures().await;
+    let sender = Address::repeat_byte(0xaa);
+
+    let head = BlockId::Number(BlockNumber::Number(3.into()));
+    assert!(provider.get_balance(sender, Some(head)).await.is_ok());
+
+    let past = BlockId::Number(BlockNumber::Number(1.into()));
+    let err = provider.get_balance(sender, Some(past)).await.unwrap_err();
+    assert!(err.to_string().contains("historical state not available"));
+}
+
+#[tokio::test]
+async fn calls_and_estimates_without_mutating_state() {
+    let (provider, _state) = serve_fixtures().await;
+    let caller = Address::repeat_byte(0xaa);
+    let contract = get_contract_address(caller, 1u64);
+
+    let view = TransactionRequest::new().from(caller).to(contract).into();
+    let output = provider.call(&view, None).await.unwrap();
+    assert_eq!(H256::from_slice(&output), H256::from_low_u64_be(42));
+
+    let gas = provider.estimate_gas(&view, None).await.unwrap();
+    assert!(gas > U256::from(21_000));
+    let exact = TransactionRequest::new()
+        .from(caller)
+        .to(contract)
+        .gas(gas)
+        .into();
+    assert!(provider.call(&exact, None).await.is_ok());
+
+    let transfer = TransactionRequest::new()
+        .from(caller)
+        .to(Address::repeat_byte(0xdd))
+        .value(U256::exp10(18))
+        .into();
+    assert_eq!(
+        provider.estimate_gas(&transfer, None).await.unwrap(),
+        U256::from(21_000)
+    );
+    assert_eq!(
+        provider
+            .get_balance(Address::repeat_byte(0xdd), None)
+            .await
+            .unwrap(),
+        U256::zero()
+    );
+    assert_eq!(
+        provider.get_transaction_count(caller, None).await.unwrap(),
+        U256::from(3)
+    );
+}
+
+#[tokio::test]
+async fn serves_verifiable_proofs() {
+    let (provider, state) = serve_fixtures().await;
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+
+    let proof = provider
+        .get_proof(contract, vec![H256::zero()], None)
+        .await
+        .unwrap();
+    assert_eq!(proof.storage_proof[0].value, U256::from(42));
+    verify_account_proof(state.read().await.state_root(), &proof).unwrap();
+}
+
+async fn post(provider: &Provider<Http>, body: impl Into<reqwest::Body>) -> Value {
+    let response = reqwest::Client::new()
+        .post(provider.url().clone())
+        .header("content-type", "application/json")
+        .body(body)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn malformed_json_is_a_parse_error() {
+    let (provider, _state) = serve_fixtures().await;
+
+    let response = post(&provider, "{\"method\": \"eth_blockNumber\"").await;
+    assert_eq!(response["error"]["code"], PARSE_ERROR);
+    assert_eq!(response["id"], Value::Null);
+}
+
+#[tokio::test]
+async fn batches_are_answered_up_to_the_limit() {
+    let (provider, _state) = serve_fixtures().await;
+    let request = |id: usize| json!({"jsonrpc": "2.0", "id": id, "method": "eth_blockNumber"});
+
+    let batch: Vec<Value> = (0..MAX_BATCH_SIZE).map(request).collect();
+    let response = post(&provider, json!(batch).to_string()).await;
+    let response = response.as_array().unwrap();
+    assert_eq!(response.len(), MAX_BATCH_SIZE);
+    assert_eq!(response[7]["id"], 7);
+    assert_eq!(response[7]["result"], "0x3");
+
+    let batch: Vec<Value> = (0..=MAX_BATCH_SIZE).map(request).collect();
+    let response = post(&provider, json!(batch).to_string()).await;
+    assert_eq!(response["error"]["code"], INVALID_REQUEST);
+
+    let response = post(&provider, "[]").await;
+    assert_eq!(response["error"]["code"], INVALID_REQUEST);
+}
+
+#[tokio::test]
+async fn oversized_gas_is_saturated() {
+    let (provider, _state) = serve_fixtures().await;
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+
+    for method in ["eth_call", "eth_estimateGas"] {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": [{"to": contract, "gas": "0x10000000000000000"}],
+        });
+        let response = post(&provider, request.to_string()).await;
+        // more gas than the block allows, which is an ordinary error rather than a crash
+        assert_eq!(response["error"]["code"], SERVER_ERROR, "{}", response);
+    }
+    assert_eq!(provider.get_block_number().await.unwrap().as_u64(), 3);
+}
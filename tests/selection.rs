@@ -0,0 +1,226 @@
+use ethers::types::{Block, Transaction, TransactionReceipt, H160, H256};
+use ethers::utils::get_contract_address;
+use evm_state_reconstructing::utils::core::block_source::FileSource;
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::{load_genesis_from_file, AccountAlloc};
+use evm_state_reconstructing::utils::core::reconstruct::replay_blocks;
+use evm_state_reconstructing::utils::core::selection::Selection;
+use evm_state_reconstructing::utils::core::snapshot::StateSnapshot;
+
+#[tokio::test]
+async fn keeps_only_selected_accounts() {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let source = FileSource::new("tests/fixtures/blocks");
+    let mut full = StateReconstructor::from_genesis(&genesis);
+    replay_blocks(&mut full, &source, 1, 3).await.unwrap();
+
+    // the contract deployed in block 3
+    let contract = get_contract_address(H160::repeat_byte(0xaa), 1);
+    let mut state =
+        StateReconstructor::from_genesis(&genesis).with_selection(Selection::new([contract]));
+    replay_blocks(&mut state, &source, 1, 3).await.unwrap();
+
+    assert_eq!(state.accounts.keys().collect::<Vec<_>>(), vec![&contract]);
+    let (kept, expected) = (&state.accounts[&contract], &full.accounts[&contract]);
+    assert_eq!(kept.storage, expected.storage);
+    assert_eq!(kept.code, expected.code);
+    // the full state is still executed and hashed
+    assert_eq!(state.state_root(), full.state_root());
+}
+
+#[tokio::test]
+async fn history_and_snapshots_keep_to_the_selection() {
+    let genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    let source = FileSource::new("tests/fixtures/blocks");
+    let contract = get_contract_address(H160::repeat_byte(0xaa), 1);
+    let mut state = StateReconstructor::from_genesis(&genesis)
+        .with_history(None)
+        .with_selection(Selection::new([contract]));
+    replay_blocks(&mut state, &source, 1, 3).await.unwrap();
+
+    let sender = H160::repeat_byte(0xaa);
+    for block in 1..=3 {
+        let alloc = state.alloc_at(block).unwrap();
+        assert!(
+            alloc.keys().all(|address| *address == contract),
+            "{:?}",
+            alloc
+        );
+        assert!(state.account_state_at(sender, block).unwrap().is_none());
+    }
+    assert!(state.alloc_at(2).unwrap().contains_key(&contract));
+
+    // the root covers accounts the snapshot leaves out
+    assert_eq!(state.snapshot_root(), None);
+    let snapshot = StateSnapshot::capture(&state).unwrap();
+    assert_eq!(snapshot.state_root(), None);
+    let restored = StateReconstructor::from_snapshot(&genesis, &snapshot).unwrap();
+    assert_eq!(
+        restored.accounts.keys().collect::<Vec<_>>(),
+        vec![&contract]
+    );
+}
+
+#[test]
+fn tracks_dependencies_and_skips_untouched_blocks() {
+    let (caller, callee, other) = (
+        H160::repeat_byte(0x0a),
+        H160::repeat_byte(0x0b),
+        H160::repeat_byte(0x0c),
+    );
+    // CALL(gas, callee, 0, 0, 0, 0, 0) POP, then SSTORE(0, 1)
+    let calls_callee = format!(
+        "0x6000600060006000600073{}5af150600160005500",
+        hex::encode(callee)
+    );
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    for (address, code) in [
+        (caller, calls_callee.as_str()),
+        (callee, "0x00"),
+        (other, "0x600160005500"),
+    ] {
+        genesis.alloc.insert(
+            address,
+            AccountAlloc {
+                code: code.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+    let mut state = StateReconstructor::from_genesis(&genesis)
+        .with_selection(Selection::new([caller]).skip_untouched_blocks());
+
+    // the second block only names accounts the state has never seen, the third a known one
+    for (number, (from, to)) in (1u64..).zip([
+        (H160::repeat_byte(0xaa), caller),
+        (H160::repeat_byte(0xee), H160::repeat_byte(0xef)),
+        (H160::repeat_byte(0xbb), other),
+    ]) {
+        let tx = Transaction {
+            hash: H256::from_low_u64_be(number),
+            from,
+            to: Some(to),
+            gas: 100_000.into(),
+            ..Default::default()
+        };
+        let block = Block {
+            number: Some(number.into()),
+            gas_limit: 30_000_000.into(),
+            transactions: vec![tx],
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            status: Some(1.into()),
+            ..Default::default()
+        };
+        state.apply_block(&block, &[receipt]).unwrap();
+    }
+
+    let selection = state.selection.as_ref().unwrap();
+    assert_eq!(selection.dependencies().collect::<Vec<_>>(), vec![&callee]);
+    assert_eq!(selection.skipped_blocks, 1);
+    let mut kept: Vec<_> = state.accounts.keys().copied().collect();
+    kept.sort();
+    assert_eq!(kept, vec![caller, callee]);
+    assert_eq!(
+        state.accounts[&caller].storage[&H256::zero()],
+        H256::from_low_u64_be(1)
+    );
+}
+
+#[test]
+fn skipping_never_leaves_known_accounts_stale() {
+    let (deployer, stranger) = (H160::repeat_byte(0x0d), H160::repeat_byte(0x0f));
+    let contract = get_contract_address(deployer, 1);
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    genesis.alloc.insert(deployer, AccountAlloc::default());
+    let mut state = StateReconstructor::from_genesis(&genesis)
+        .with_selection(Selection::new([contract]).skip_untouched_blocks());
+
+    let block = |number: u64, from: H160, to: Option<H160>| {
+        let tx = Transaction {
+            hash: H256::from_low_u64_be(number),
+            from,
+            to,
+            gas: 100_000.into(),
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            status: Some(1.into()),
+            contract_address: to.is_none().then(|| get_contract_address(from, 1)),
+            ..Default::default()
+        };
+        let block = Block {
+            number: Some(number.into()),
+            gas_limit: 30_000_000.into(),
+            transactions: vec![tx],
+            ..Default::default()
+        };
+        (block, vec![receipt])
+    };
+
+    // the deployer is known, so its first transaction runs and the deployment lands at nonce 1
+    for (block, receipts) in [
+        block(1, deployer, Some(H160::repeat_byte(0x0e))),
+        block(2, deployer, None),
+    ] {
+        state.apply_block(&block, &receipts).unwrap();
+    }
+    assert!(state.accounts.contains_key(&contract));
+    assert_eq!(state.selection.as_ref().unwrap().skipped_blocks, 0);
+
+    // a skipped stranger is stale, so calling the selection with it fails
+    let (skipped, receipts) = block(3, stranger, Some(H160::repeat_byte(0x10)));
+    state.apply_block(&skipped, &receipts).unwrap();
+    let selection = state.selection.as_ref().unwrap();
+    assert_eq!(selection.skipped_blocks, 1);
+    assert!(selection.is_stale(stranger));
+    let (call, receipts) = block(4, stranger, Some(contract));
+    let error = state.apply_block(&call, &receipts).unwrap_err();
+    assert!(error.to_string().contains("stale"), "{}", error);
+    assert_eq!(state.block_number, 3);
+}
+
+#[test]
+fn code_reads_are_dependencies() {
+    let (reader, library) = (H160::repeat_byte(0x0a), H160::repeat_byte(0x0b));
+    // SSTORE(0, EXTCODESIZE(library))
+    let reads_library = format!("0x73{}3b60005500", hex::encode(library));
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    for (address, code) in [(reader, reads_library.as_str()), (library, "0x00")] {
+        genesis.alloc.insert(
+            address,
+            AccountAlloc {
+                code: code.to_string(),
+                ..Default::default()
+            },
+        );
+    }
+    let mut state =
+        StateReconstructor::from_genesis(&genesis).with_selection(Selection::new([reader]));
+
+    let tx = Transaction {
+        hash: H256::from_low_u64_be(1),
+        from: H160::repeat_byte(0xaa),
+        to: Some(reader),
+        gas: 100_000.into(),
+        ..Default::default()
+    };
+    let block = Block {
+        number: Some(1.into()),
+        gas_limit: 30_000_000.into(),
+        transactions: vec![tx],
+        ..Default::default()
+    };
+    state
+        .apply_block(&block, &[TransactionReceipt::default()])
+        .unwrap();
+
+    let selection = state.selection.as_ref().unwrap();
+    assert_eq!(selection.dependencies().collect::<Vec<_>>(), vec![&library]);
+    assert!(state.accounts.contains_key(&library));
+    assert_eq!(
+        state.accounts[&reader].storage[&H256::zero()],
+        H256::from_low_u64_be(1)
+    );
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use anyhow::Error;
+use async_trait::async_trait;
+use common::{genesis, reconstruct, BLOCKS};
+use ethers::types::{Address, H160, H256, U256};
+use ethers::utils::{get_contract_address, keccak256};
+use evm_state_reconstructing::utils::core::block_source::{
+    BlockSource, BlockWithReceipts, FileSource,
+};
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::{
+    load_genesis_from_file, save_genesis_to_file,
+};
+use evm_state_reconstructing::utils::core::reconstruct::{
+    import_snapshot, replay_blocks, resume_from_snapshot,
+};
+use evm_state_reconstructing::utils::core::snapshot::StateSnapshot;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()))
+}
+
+/// The fixture headers carry no state roots; this fills in the ones replay produces.
+struct RootedSource {
+    inner: FileSource,
+    roots: BTreeMap<u64, H256>,
+}
+
+impl RootedSource {
+    async fn new() -> Self {
+        let mut roots = BTreeMap::new();
+        for block in 1..=3 {
+            roots.insert(block, reconstruct(block).await.state_root());
+        }
+        Self {
+            inner: FileSource::new(BLOCKS),
+            roots,
+        }
+    }
+}
+
+#[async_trait]
+impl BlockSource for RootedSource {
+    async fn block_range(&self) -> Result<(u64, u64), Error> {
+        self.inner.block_range().await
+    }
+
+    async fn get_block(&self, block_nr: u64) -> Result<BlockWithReceipts, Error> {
+        let (mut block, receipts) = self.inner.get_block(block_nr).await?;
+        block.state_root = self.roots[&block_nr];
+        Ok((block, receipts))
+    }
+}
+
+#[tokio::test]
+async fn exported_genesis_boots_the_same_state() {
+    let state = reconstruct(3).await;
+    let exported = state.genesis_at(&genesis(), 3).unwrap();
+
+    let path = temp_path("exported-genesis");
+    save_genesis_to_file(&path, &exported).unwrap();
+    let reloaded = load_genesis_from_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(reloaded.config.chain_id, 1337);
+    assert_eq!(reloaded.alloc, exported.alloc);
+    let booted = StateReconstructor::from_genesis(&reloaded);
+    assert_eq!(booted.state_root(), state.state_root());
+
+    let contract = get_contract_address(Address::repeat_byte(0xaa), 1u64);
+    let original = state.get_account_state(contract).unwrap();
+    let copy = booted.get_account_state(contract).unwrap();
+    assert_eq!(copy.code, original.code);
+    assert_eq!(
+        copy.storage.get(&H256::zero()),
+        Some(&H256::from_low_u64_be(42))
+    );
+}
+
+#[tokio::test]
+async fn alloc_uses_the_genesis_shape() {
+    let alloc = reconstruct(3).await.alloc_at(3).unwrap();
+    let json = serde_json::to_value(&alloc).unwrap();
+
+    let sender = &json[format!("{:?}", H160::repeat_byte(0xaa))];
+    assert_eq!(sender["nonce"], "0x3");
+    assert!(sender["balance"].as_str().unwrap().starts_with("996"));
+    assert!(sender.get("code").is_none());
+
+    let contract = &json[format!(
+        "{:?}",
+        get_contract_address(Address::repeat_byte(0xaa), 1u64)
+    )];
+    assert!(contract["code"].as_str().unwrap().starts_with("0x6000"));
+    assert_eq!(
+        contract["storage"][format!("{:?}", H256::zero())],
+        format!("{:?}", H256::from_low_u64_be(42))
+    );
+}
+
+#[tokio::test]
+async fn exports_past_blocks_from_history() {
+    let mut state = StateReconstructor::from_genesis(&genesis()).with_history(None);
+    replay_blocks(&mut state, &FileSource::new(BLOCKS), 1, 3)
+        .await
+        .unwrap();
+
+    let at_1 = state.genesis_at(&genesis(), 1).unwrap();
+    // the two funded accounts and the zero-address coinbase
+    assert_eq!(at_1.alloc.len(), 3);
+    assert_eq!(
+        StateReconstructor::from_genesis(&at_1).state_root(),
+        reconstruct(1).await.state_root()
+    );
+
+    assert!(reconstruct(3).await.alloc_at(1).is_err());
+}
+
+#[tokio::test]
+async fn resumes_from_a_binary_snapshot() {
+    let source = RootedSource::new().await;
+    let path = temp_path("snapshot-bin");
+    let snapshot = StateSnapshot::capture(&reconstruct(2).await).unwrap();
+    snapshot.save(&path).unwrap();
+
+    let loaded = StateSnapshot::load(&path, None).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, snapshot);
+    assert_eq!(loaded.block_number, 2);
+
+    let state = resume_from_snapshot(&genesis(), &loaded, &source, 3)
+        .await
+        .unwrap();
+    assert_eq!(state.block_number, 3);
+    assert_eq!(state.head_hash, Some(H256::from(keccak256("block-3"))));
+    assert_eq!(state.state_root(), reconstruct(3).await.state_root());
+}
+
+#[tokio::test]
+async fn imports_alloc_files() {
+    let source = RootedSource::new().await;
+    let path = temp_path("snapshot-alloc");
+    let alloc = reconstruct(2).await.alloc_at(2).unwrap();
+    std::fs::write(&path, serde_json::to_vec(&alloc).unwrap()).unwrap();
+
+    assert!(StateSnapshot::load(&path, None).is_err());
+    let snapshot = StateSnapshot::load(&path, Some(2)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(snapshot, StateSnapshot::from_alloc(2, &alloc));
+    assert_eq!(snapshot.block_hash(), None);
+
+    let state = import_snapshot(&genesis(), &snapshot, &source)
+        .await
+        .unwrap();
+    assert_eq!(state.block_number, 2);
+    assert_eq!(state.head_hash, Some(H256::from(keccak256("block-2"))));
+
+    // taken at another height than claimed
+    let wrong_block = StateSnapshot::from_alloc(1, &alloc);
+    assert!(import_snapshot(&genesis(), &wrong_block, &source)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn rejects_tampered_snapshots() {
+    let source = RootedSource::new().await;
+    let mut snapshot = StateSnapshot::capture(&reconstruct(2).await).unwrap();
+    let sender = snapshot
+        .accounts
+        .iter_mut()
+        .find(|account| account.address == [0xaa; 20])
+        .unwrap();
+    let balance = U256::from_big_endian(&sender.balance) + U256::one();
+    balance.to_big_endian(&mut sender.balance);
+
+    // the recorded root no longer matches the accounts
+    assert!(StateReconstructor::from_snapshot(&genesis(), &snapshot).is_err());
+
+    // without one, the header root still catches it
+    snapshot.state_root = [0u8; 32];
+    assert!(import_snapshot(&genesis(), &snapshot, &source)
+        .await
+        .is_err());
+}
@@ -0,0 +1,191 @@
+mod common;
+
+use common::{contract, genesis, reconstruct};
+use ethers::types::{H160, H256, U256};
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::snapshot::{
+    write_snapshot, SnapshotAccount, SnapshotReader, StateSnapshot,
+};
+use evm_state_reconstructing::utils::core::state;
+use evm_state_reconstructing::utils::core::state_iter::{AccountEntry, KeyRange, StateStore};
+
+fn all_accounts(store: &dyn StateStore) -> Vec<AccountEntry> {
+    store
+        .accounts(KeyRange::all())
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn pages_through_accounts_in_order() {
+    let state = reconstruct(3).await;
+    let accounts = all_accounts(&state);
+    assert_eq!(accounts.len(), state.accounts.len());
+    assert!(accounts
+        .windows(2)
+        .all(|pair| pair[0].address < pair[1].address));
+
+    let mut paged = Vec::new();
+    let mut range = KeyRange::all();
+    loop {
+        let page = state.accounts_page(range.clone(), 2).unwrap();
+        assert!(page.items.len() <= 2);
+        paged.extend(page.items);
+        match page.next {
+            Some(next) => range = KeyRange::all().after(next),
+            None => break,
+        }
+    }
+    assert_eq!(paged, accounts);
+
+    let sender: Vec<_> = state
+        .accounts(KeyRange::with_prefix(vec![0xaa]))
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(sender.len(), 1);
+    assert_eq!(sender[0].address, H160::repeat_byte(0xaa));
+    assert_eq!(sender[0].nonce.as_u64(), 3);
+
+    let below = state
+        .accounts(KeyRange::all().until(H160::repeat_byte(0xaa)))
+        .count();
+    let from = state
+        .accounts(KeyRange::all().from(H160::repeat_byte(0xaa)))
+        .count();
+    assert_eq!(below + from, accounts.len());
+}
+
+#[tokio::test]
+async fn iterates_storage() {
+    let state = reconstruct(3).await;
+    let slots: Vec<_> = state
+        .storage(contract(), KeyRange::all())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(slots, vec![(H256::zero(), H256::from_low_u64_be(42))]);
+
+    let page = state
+        .storage_page(contract(), KeyRange::all().after(H256::zero()), 10)
+        .unwrap();
+    assert!(page.items.is_empty());
+    assert_eq!(page.next, None);
+    assert_eq!(
+        state
+            .storage(H160::repeat_byte(0xdd), KeyRange::all())
+            .count(),
+        0
+    );
+}
+
+#[tokio::test]
+async fn streams_snapshot_files() {
+    let state = reconstruct(3).await;
+    let path = std::env::temp_dir().join(format!("state-iter-{}.snap", std::process::id()));
+    let written = write_snapshot(
+        &state,
+        state.block_number,
+        state.head_hash,
+        Some(state.state_root()),
+        &path,
+    )
+    .unwrap();
+
+    let reader = SnapshotReader::open(&path).unwrap();
+    assert_eq!(reader.block_number, 3);
+    assert_eq!(reader.state_root, Some(state.state_root()));
+    assert_eq!(reader.account_count, written);
+
+    let non_empty: Vec<_> = all_accounts(&state)
+        .into_iter()
+        .filter(|account| !account.has_no_info())
+        .collect();
+    assert_eq!(all_accounts(&reader), non_empty);
+
+    let page = reader
+        .accounts_page(KeyRange::all().after(non_empty[0].address), 1)
+        .unwrap();
+    assert_eq!(page.items, non_empty[1..2]);
+    assert_eq!(
+        reader
+            .storage(contract(), KeyRange::all())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap(),
+        vec![(H256::zero(), H256::from_low_u64_be(42))]
+    );
+
+    // streamed files are ordinary snapshots
+    let snapshot = StateSnapshot::load(&path, None).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let restored = StateReconstructor::from_snapshot(&genesis(), &snapshot).unwrap();
+    assert_eq!(restored.state_root(), state.state_root());
+}
+
+#[test]
+fn reads_snapshots_spanning_several_chunks() {
+    // four accounts of about 160 KiB each, so several chunks
+    let accounts = (1u8..=4)
+        .map(|byte| SnapshotAccount {
+            address: [byte; 20],
+            nonce: 1,
+            balance: [0u8; 32],
+            code: Vec::new(),
+            storage: (0..2_500u64)
+                .map(|slot| {
+                    (
+                        H256::from_low_u64_be(slot).0,
+                        H256::from_low_u64_be(slot + 1).0,
+                    )
+                })
+                .collect(),
+        })
+        .collect();
+    let snapshot = StateSnapshot {
+        block_number: 1,
+        block_hash: [0u8; 32],
+        state_root: [0u8; 32],
+        accounts,
+    };
+    let path = std::env::temp_dir().join(format!("state-iter-chunks-{}.snap", std::process::id()));
+    snapshot.save(&path).unwrap();
+
+    let reader = SnapshotReader::open(&path).unwrap();
+    assert_eq!(reader.account_count, 4);
+    let page = reader
+        .accounts_page(KeyRange::all().after(H160::repeat_byte(2)), 10)
+        .unwrap();
+    assert_eq!(
+        page.items
+            .iter()
+            .map(|account| account.address)
+            .collect::<Vec<_>>(),
+        vec![H160::repeat_byte(3), H160::repeat_byte(4)]
+    );
+    let slots = reader
+        .storage_page(
+            H160::repeat_byte(4),
+            KeyRange::all().after(H256::from_low_u64_be(2_497)),
+            10,
+        )
+        .unwrap();
+    assert_eq!(
+        slots.items,
+        vec![
+            (H256::from_low_u64_be(2_498), H256::from_low_u64_be(2_499)),
+            (H256::from_low_u64_be(2_499), H256::from_low_u64_be(2_500)),
+        ]
+    );
+    assert_eq!(StateSnapshot::load(&path, None).unwrap(), snapshot);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn nonces_beyond_u64_are_an_error() {
+    let mut state = state::StateReconstructor::from_genesis(&genesis());
+    let account = state.accounts.values_mut().next().unwrap();
+    account.nonce = U256::MAX;
+
+    let path = std::env::temp_dir().join(format!("state-iter-nonce-{}.snap", std::process::id()));
+    let error = write_snapshot(&state, 0, None, None, &path).unwrap_err();
+    let _ = std::fs::remove_file(&path);
+    assert!(error.to_string().contains("nonce"), "{}", error);
+}
@@ -0,0 +1,180 @@
+use ethers::abi::{encode, Token};
+use ethers::types::{Block, Log, Transaction, TransactionReceipt, H160, H256, U256};
+use ethers::utils::keccak256;
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::{load_genesis_from_file, AccountAlloc};
+use evm_state_reconstructing::utils::core::tokens::{
+    check_token_balances, decode_transfers, BalanceMismatch, TokenHolder, TokenStandard,
+    TokenTransfer,
+};
+
+const TOKEN: u8 = 0x70;
+
+fn topic(address: H160) -> H256 {
+    address.into()
+}
+
+fn log(topics: Vec<H256>, data: Vec<u8>) -> Log {
+    Log {
+        address: H160::repeat_byte(TOKEN),
+        topics,
+        data: data.into(),
+        ..Default::default()
+    }
+}
+
+fn erc20_transfer(from: H160, to: H160, amount: u64) -> Log {
+    log(
+        vec![
+            keccak256("Transfer(address,address,uint256)").into(),
+            topic(from),
+            topic(to),
+        ],
+        encode(&[Token::Uint(amount.into())]),
+    )
+}
+
+#[test]
+fn decodes_transfer_events() {
+    let (a, b) = (H160::repeat_byte(0xaa), H160::repeat_byte(0xbb));
+    let transfer = |standard, id, amount: u64| TokenTransfer {
+        token: H160::repeat_byte(TOKEN),
+        standard,
+        from: a,
+        to: b,
+        id,
+        amount: amount.into(),
+    };
+
+    assert_eq!(
+        decode_transfers(&erc20_transfer(a, b, 5)),
+        vec![transfer(TokenStandard::Erc20, None, 5)]
+    );
+
+    let erc721 = log(
+        vec![
+            keccak256("Transfer(address,address,uint256)").into(),
+            topic(a),
+            topic(b),
+            H256::from_low_u64_be(9),
+        ],
+        Vec::new(),
+    );
+    assert_eq!(
+        decode_transfers(&erc721),
+        vec![transfer(TokenStandard::Erc721, Some(9.into()), 1)]
+    );
+
+    let operator = topic(H160::repeat_byte(0x0f));
+    let single = log(
+        vec![
+            keccak256("TransferSingle(address,address,address,uint256,uint256)").into(),
+            operator,
+            topic(a),
+            topic(b),
+        ],
+        encode(&[Token::Uint(3.into()), Token::Uint(40.into())]),
+    );
+    assert_eq!(
+        decode_transfers(&single),
+        vec![transfer(TokenStandard::Erc1155, Some(3.into()), 40)]
+    );
+
+    let uints =
+        |values: &[u64]| Token::Array(values.iter().map(|v| Token::Uint((*v).into())).collect());
+    let batch = log(
+        vec![
+            keccak256("TransferBatch(address,address,address,uint256[],uint256[])").into(),
+            operator,
+            topic(a),
+            topic(b),
+        ],
+        encode(&[uints(&[1, 2]), uints(&[10, 20])]),
+    );
+    assert_eq!(
+        decode_transfers(&batch),
+        vec![
+            transfer(TokenStandard::Erc1155, Some(1.into()), 10),
+            transfer(TokenStandard::Erc1155, Some(2.into()), 20),
+        ]
+    );
+
+    // other events and malformed transfers are ignored
+    assert!(decode_transfers(&log(vec![H256::repeat_byte(1)], Vec::new())).is_empty());
+    let mut truncated = erc20_transfer(a, b, 5);
+    truncated.data = vec![0u8; 31].into();
+    assert!(decode_transfers(&truncated).is_empty());
+}
+
+#[test]
+fn indexes_and_checks_balances() {
+    let token = H160::repeat_byte(TOKEN);
+    let (a, b) = (H160::repeat_byte(0xaa), H160::repeat_byte(0xbb));
+
+    // balanceOf(holder) returns the slot keyed by the holder's address:
+    // PUSH1 4 CALLDATALOAD SLOAD PUSH1 0 MSTORE PUSH1 32 PUSH1 0 RETURN
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    genesis.alloc.insert(
+        token,
+        AccountAlloc {
+            code: "0x6004355460005260206000f3".to_string(),
+            storage: [
+                (topic(a), H256::from_low_u64_be(70)),
+                (topic(b), H256::from_low_u64_be(31)),
+            ]
+            .into(),
+            ..Default::default()
+        },
+    );
+    let mut state = StateReconstructor::from_genesis(&genesis).with_token_index();
+
+    let blocks = [
+        vec![erc20_transfer(H160::zero(), a, 100)],
+        vec![erc20_transfer(a, b, 30)],
+    ];
+    for (number, logs) in (1u64..).zip(blocks) {
+        let tx = Transaction {
+            hash: H256::from_low_u64_be(number),
+            from: H160::repeat_byte(0xaa),
+            to: Some(H160::repeat_byte(0xcc)),
+            nonce: (number - 1).into(),
+            gas: 21_000.into(),
+            ..Default::default()
+        };
+        let block = Block {
+            number: Some(number.into()),
+            gas_limit: 30_000_000.into(),
+            transactions: vec![tx],
+            ..Default::default()
+        };
+        let receipt = TransactionReceipt {
+            status: Some(1.into()),
+            logs,
+            ..Default::default()
+        };
+        state.apply_block(&block, &[receipt]).unwrap();
+    }
+
+    let tokens = state.tokens.as_ref().unwrap();
+    assert_eq!(tokens.standard(token), Some(TokenStandard::Erc20));
+    let holder = |holder, balance: u64| TokenHolder {
+        holder,
+        id: None,
+        balance: balance.into(),
+    };
+    assert_eq!(tokens.holders_at(token, 1), vec![holder(a, 100)]);
+    assert_eq!(tokens.holders(token), vec![holder(a, 70), holder(b, 30)]);
+    assert_eq!(tokens.balance_at(token, b, None, 1), U256::zero());
+
+    assert_eq!(
+        check_token_balances(&state, token, 2).unwrap(),
+        vec![BalanceMismatch {
+            holder: b,
+            id: None,
+            indexed: 30.into(),
+            on_chain: Some(31.into()),
+        }]
+    );
+    // past blocks need history
+    assert!(check_token_balances(&state, token, 1).is_err());
+}
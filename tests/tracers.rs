@@ -0,0 +1,251 @@
+use ethers::types::{
+    Block, CallConfig, GethDebugTracingOptions, GethTraceFrame, PreStateConfig, PreStateFrame,
+    Transaction, TransactionReceipt, H160, H256,
+};
+use evm_state_reconstructing::utils::core::evm_exec::StateReconstructor;
+use evm_state_reconstructing::utils::core::genesis_load::{load_genesis_from_file, AccountAlloc};
+use evm_state_reconstructing::utils::core::rpc_server::dispatch;
+use evm_state_reconstructing::utils::core::tracers::Tracer;
+use serde_json::json;
+
+const CALLER: u8 = 0xdd;
+const EMITTER: u8 = 0xee;
+
+/// Genesis with a contract at 0xdd that stores 1 in slot 0 and calls 0xee, which logs.
+fn state() -> StateReconstructor {
+    // PUSH1 1 PUSH1 0 SSTORE, CALL(0xffff, 0xee.., 0, 0, 0, 0, 0) POP STOP
+    let caller = format!(
+        "0x60016000556000600060006000600073{}61fffff15000",
+        "ee".repeat(20)
+    );
+    // PUSH1 0x07 PUSH1 0x20 PUSH1 0x00 LOG1 STOP
+    let emitter = "0x600760206000a100".to_string();
+
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    for (address, code) in [(CALLER, caller), (EMITTER, emitter)] {
+        genesis.alloc.insert(
+            H160::repeat_byte(address),
+            AccountAlloc {
+                code,
+                ..Default::default()
+            },
+        );
+    }
+    StateReconstructor::from_genesis(&genesis)
+}
+
+fn block(number: u64) -> Block<Transaction> {
+    let tx = Transaction {
+        hash: H256::from_low_u64_be(number),
+        from: H160::repeat_byte(0xaa),
+        to: Some(H160::repeat_byte(CALLER)),
+        nonce: (number - 1).into(),
+        gas: 100_000.into(),
+        block_number: Some(number.into()),
+        transaction_index: Some(0.into()),
+        ..Default::default()
+    };
+    Block {
+        number: Some(number.into()),
+        gas_limit: 30_000_000.into(),
+        transactions: vec![tx],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn call_and_prestate_traces() {
+    let mut state = state();
+    let block = block(1);
+    let caller = H160::repeat_byte(CALLER);
+    let emitter = H160::repeat_byte(EMITTER);
+
+    let tracer = Tracer::Call(CallConfig {
+        only_top_call: None,
+        with_log: Some(true),
+    });
+    let GethTraceFrame::CallTracer(top) =
+        state.trace_block_transaction(&block, 0, &tracer).unwrap()
+    else {
+        panic!("expected a call frame");
+    };
+    assert_eq!(top.typ, "CALL");
+    assert_eq!(top.from, H160::repeat_byte(0xaa));
+    assert_eq!(top.to, Some(caller.into()));
+    assert_eq!(top.gas, 100_000.into());
+    assert!(top.error.is_none() && top.logs.is_none());
+    let calls = top.calls.unwrap();
+    assert_eq!(calls.len(), 1);
+    assert_eq!(
+        (calls[0].from, calls[0].to.clone()),
+        (caller, Some(emitter.into()))
+    );
+    assert_eq!(calls[0].logs.as_ref().unwrap().len(), 1);
+    assert_eq!(
+        calls[0].logs.as_ref().unwrap()[0].topics,
+        Some(vec![H256::from_low_u64_be(7)])
+    );
+
+    let prestate = |diff_mode| {
+        let tracer = Tracer::Prestate(PreStateConfig {
+            diff_mode: Some(diff_mode),
+        });
+        match state.trace_block_transaction(&block, 0, &tracer).unwrap() {
+            GethTraceFrame::PreStateTracer(frame) => frame,
+            other => panic!("expected a prestate frame, got {:?}", other),
+        }
+    };
+    let PreStateFrame::Default(pre) = prestate(false) else {
+        panic!("expected the default prestate mode");
+    };
+    assert!(pre.0.contains_key(&emitter));
+    assert_eq!(
+        pre.0[&caller].storage.as_ref().unwrap()[&H256::zero()],
+        H256::zero()
+    );
+    let PreStateFrame::Diff(diff) = prestate(true) else {
+        panic!("expected the diff prestate mode");
+    };
+    // the emitter is only called, so it does not change
+    assert!(!diff.pre.contains_key(&emitter));
+    assert_eq!(
+        diff.post[&caller].storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(1)
+    );
+    assert_eq!(diff.post[&H160::repeat_byte(0xaa)].nonce, Some(1.into()));
+
+    // tracing leaves the state alone
+    assert!(state.get_account_state(caller).unwrap().storage.is_empty());
+    state
+        .apply_block(&block, &[TransactionReceipt::default()])
+        .unwrap();
+    assert_eq!(
+        state.get_account_state(caller).unwrap().storage[&H256::zero()],
+        H256::from_low_u64_be(1)
+    );
+}
+
+#[test]
+fn traces_run_in_the_block_header_context() {
+    // SSTORE(0, TIMESTAMP) STOP
+    let clock = H160::repeat_byte(0xcc);
+    let mut genesis = load_genesis_from_file("tests/fixtures/genesis.json").unwrap();
+    genesis.alloc.insert(
+        clock,
+        AccountAlloc {
+            code: "0x4260005500".to_string(),
+            ..Default::default()
+        },
+    );
+    let state = StateReconstructor::from_genesis(&genesis);
+
+    let mut block = block(1);
+    block.timestamp = 1_800_000_000u64.into();
+    block.transactions[0].to = Some(clock);
+    let tracer = Tracer::Prestate(PreStateConfig {
+        diff_mode: Some(true),
+    });
+    let GethTraceFrame::PreStateTracer(PreStateFrame::Diff(diff)) =
+        state.trace_block_transaction(&block, 0, &tracer).unwrap()
+    else {
+        panic!("expected a prestate diff");
+    };
+    assert_eq!(
+        diff.post[&clock].storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(1_800_000_000)
+    );
+}
+
+#[test]
+fn struct_logs() {
+    let state = state();
+    let options = GethDebugTracingOptions {
+        enable_memory: Some(true),
+        ..Default::default()
+    };
+    let GethTraceFrame::Default(frame) = state
+        .trace_block_transaction(&block(1), 0, &Tracer::StructLogs(options))
+        .unwrap()
+    else {
+        panic!("expected struct logs");
+    };
+
+    assert!(!frame.failed);
+    let ops: Vec<&str> = frame
+        .struct_logs
+        .iter()
+        .map(|log| log.op.as_str())
+        .collect();
+    assert_eq!(ops.len(), 13 + 5);
+    assert_eq!(&ops[..3], ["PUSH1", "PUSH1", "SSTORE"]);
+    assert_eq!(ops.last(), Some(&"STOP"));
+
+    let sstore = &frame.struct_logs[2];
+    assert_eq!((sstore.pc, sstore.depth), (4, 1));
+    assert_eq!(
+        sstore.storage.as_ref().unwrap()[&H256::zero()],
+        H256::from_low_u64_be(1)
+    );
+    assert_eq!(sstore.gas - frame.struct_logs[3].gas, sstore.gas_cost);
+
+    let log1 = frame
+        .struct_logs
+        .iter()
+        .find(|log| log.op == "LOG1")
+        .unwrap();
+    assert_eq!(log1.depth, 2);
+    assert_eq!(log1.stack.as_ref().unwrap().len(), 3);
+    assert!(log1.memory.as_ref().unwrap().is_empty());
+}
+
+#[test]
+fn traces_retained_blocks_over_rpc() {
+    let mut state = state().with_history(None);
+    for number in 1..=2 {
+        state
+            .apply_block(&block(number), &[TransactionReceipt::default()])
+            .unwrap();
+    }
+
+    let trace = dispatch(
+        &state,
+        "debug_traceTransaction",
+        &[
+            json!(H256::from_low_u64_be(1)),
+            json!({ "tracer": "prestateTracer", "tracerConfig": { "diffMode": true } }),
+        ],
+    )
+    .unwrap();
+    // block 1 is traced on the state before it, where slot 0 was still empty
+    let caller = format!("{:?}", H160::repeat_byte(CALLER));
+    assert_eq!(
+        trace["pre"][&caller]["storage"][format!("{:?}", H256::zero())],
+        json!(H256::zero())
+    );
+    assert_eq!(trace["post"][&caller]["balance"], json!(null));
+
+    let traces = dispatch(
+        &state,
+        "debug_traceBlockByNumber",
+        &[json!("0x2"), json!({ "tracer": "callTracer" })],
+    )
+    .unwrap();
+    assert_eq!(traces.as_array().unwrap().len(), 1);
+    assert_eq!(traces[0]["txHash"], json!(H256::from_low_u64_be(2)));
+    assert_eq!(traces[0]["result"]["type"], json!("CALL"));
+
+    let error = dispatch(
+        &state,
+        "debug_traceTransaction",
+        &[json!(H256::repeat_byte(9))],
+    )
+    .unwrap_err();
+    assert!(error.message.contains("not in the retained history"));
+    let error = dispatch(
+        &state,
+        "debug_traceBlockByNumber",
+        &[json!("0x1"), json!({ "tracer": "4byteTracer" })],
+    )
+    .unwrap_err();
+    assert!(error.message.contains("not supported"));
+}
@@ -0,0 +1,97 @@
+use ethers::types::{Transaction, H256};
+use evm_state_reconstructing::utils::core::block_source::BlockSource;
+use evm_state_reconstructing::utils::core::wvm_archiver::WvmArchiverDataBlock;
+use evm_state_reconstructing::utils::core::wvm_calldata::{
+    decode_archived_calldata, encode_archived_calldata, WvmCalldataSource, WvmTxIndex,
+};
+use std::collections::BTreeMap;
+
+fn archived_block(block_nr: u64) -> WvmArchiverDataBlock {
+    let json = std::fs::read(format!("tests/fixtures/blocks/{}.json", block_nr)).unwrap();
+    serde_json::from_slice(&json).unwrap()
+}
+
+/// A synthetic archive transaction, encoded by this crate; see `tests/fixtures/wvm_tx/README.md`.
+fn wvm_tx(block_nr: u64) -> Transaction {
+    let json = std::fs::read(format!("tests/fixtures/wvm_tx/{}.json", block_nr)).unwrap();
+    serde_json::from_slice(&json).unwrap()
+}
+
+#[test]
+fn decodes_synthetic_calldata_into_archived_block() {
+    for block_nr in 1..=3 {
+        let decoded = decode_archived_calldata(&wvm_tx(block_nr).input).unwrap();
+        assert_eq!(decoded, archived_block(block_nr));
+    }
+}
+
+/// Archive transactions captured from WeaveVM with the block the archiver serves for them, as
+/// `captured/<name>.tx.json` and `captured/<name>.block.json`; see the fixtures README.
+fn captured_wvm_txs() -> Vec<(String, Transaction, WvmArchiverDataBlock)> {
+    let Ok(entries) = std::fs::read_dir("tests/fixtures/wvm_tx/captured") else {
+        return Vec::new();
+    };
+    let mut captured: Vec<_> = entries
+        .map(|entry| entry.unwrap().path())
+        .filter_map(|path| {
+            let name = path
+                .file_name()?
+                .to_str()?
+                .strip_suffix(".tx.json")?
+                .to_string();
+            let tx = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+            let block = std::fs::read(path.with_file_name(format!("{}.block.json", name)))
+                .unwrap_or_else(|_| panic!("{} has no expected block", name));
+            Some((name, tx, serde_json::from_slice(&block).unwrap()))
+        })
+        .collect();
+    captured.sort_by(|a, b| a.0.cmp(&b.0));
+    captured
+}
+
+#[test]
+fn decodes_captured_calldata_into_served_block() {
+    for (name, tx, expected) in captured_wvm_txs() {
+        let decoded = decode_archived_calldata(&tx.input).unwrap();
+        assert_eq!(decoded, expected, "{}", name);
+    }
+}
+
+#[test]
+fn decoded_block_carries_transactions_and_receipts() {
+    let decoded = decode_archived_calldata(&wvm_tx(3).input).unwrap();
+    let (block, receipts) = decoded.into_block_receipts().unwrap();
+
+    assert_eq!(block.number.unwrap().as_u64(), 3);
+    assert_eq!(block.transactions.len(), 2);
+    assert_eq!(receipts.len(), 2);
+    assert_eq!(receipts[1].transaction_hash, block.transactions[1].hash);
+}
+
+#[test]
+fn encoding_round_trips() {
+    let data = archived_block(2);
+    let calldata = encode_archived_calldata(&data).unwrap();
+    assert_eq!(decode_archived_calldata(&calldata).unwrap(), data);
+}
+
+#[test]
+fn rejects_corrupted_calldata() {
+    let mut calldata = wvm_tx(1).input.to_vec();
+    calldata.truncate(calldata.len() / 2);
+    assert!(decode_archived_calldata(&calldata).is_err());
+    assert!(decode_archived_calldata(b"not brotli").is_err());
+}
+
+#[tokio::test]
+async fn static_index_defines_block_range() {
+    let index: BTreeMap<u64, H256> = (1..=3).map(|n| (n, wvm_tx(n).hash)).collect();
+    let source = WvmCalldataSource::new(
+        ethers::providers::Provider::try_from("http://127.0.0.1:1").unwrap(),
+        WvmTxIndex::Static(index.clone()),
+    );
+
+    assert_eq!(source.block_range().await.unwrap(), (1, 3));
+    assert_eq!(source.index.lookup(2).await.unwrap(), index[&2]);
+    assert!(source.index.lookup(4).await.is_err());
+}